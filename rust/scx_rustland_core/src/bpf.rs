@@ -16,6 +16,8 @@ use libbpf_rs::skel::SkelBuilder as _;
 use libc::{sched_param, sched_setscheduler};
 
 use scx_utils::init_libbpf_logging;
+use scx_utils::ScxSchedulerRegistry;
+use scx_utils::ScxVerifier;
 use scx_utils::uei_exited;
 use scx_utils::uei_report;
 
@@ -24,6 +26,9 @@ use scx_rustland_core::ALLOCATOR;
 // Defined in UAPI
 const SCHED_EXT: i32 = 7;
 
+// The BPF object the skeleton is generated from, built by RustLandBuilder.
+const BPF_OBJ: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/bpf.bpf.o"));
+
 // Do not assign any specific CPU to the task.
 //
 // The task will be dispatched to the global shared DSQ and it will run on the first CPU available.
@@ -132,6 +137,7 @@ pub struct BpfScheduler<'cb> {
     pub skel: BpfSkel<'cb>,              // Low-level BPF connector
     queued: libbpf_rs::RingBuffer<'cb>,  // Ring buffer of queued tasks
     struct_ops: Option<libbpf_rs::Link>, // Low-level BPF methods
+    _registry: ScxSchedulerRegistry,     // Released after detaching the scheduler
 }
 
 // Buffer to store a task read from the ring buffer.
@@ -210,6 +216,16 @@ impl<'cb> BpfScheduler<'cb> {
         skel.rodata_mut().debug = debug;
         skel.rodata_mut().full_user = full_user;
 
+        // Make sure no other sched_ext scheduler is running before attaching.
+        let registry = ScxSchedulerRegistry::acquire()?;
+
+        // Make sure the running kernel supports all the sched_ext_ops used by the BPF program,
+        // to report a clear error instead of a libbpf failure.
+        ScxVerifier::new()
+            .check_object(BPF_OBJ)
+            .context("Failed to verify BPF program")?
+            .ensure_compatible()?;
+
         // Attach BPF scheduler.
         let mut skel = skel.load().context("Failed to load BPF program")?;
         skel.attach().context("Failed to attach BPF program")?;
@@ -234,6 +250,7 @@ impl<'cb> BpfScheduler<'cb> {
                 skel,
                 queued,
                 struct_ops,
+                _registry: registry,
             }),
             err => Err(anyhow::Error::msg(format!(
                 "sched_setscheduler error: {}",
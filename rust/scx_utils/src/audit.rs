@@ -0,0 +1,220 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Audit Log
+//!
+//! A crate that allows schedulers to record their dispatch decisions into a
+//! fixed-size in-memory ring buffer, so that the last decisions leading up to
+//! a misbehavior can be inspected after the fact.
+//!
+//! ScxAuditLog
+//! -----------
+//!
+//! Recording an entry never blocks and never allocates. Once the ring buffer
+//! is full, the oldest entries are overwritten:
+//!
+//!```
+//!     let log = ScxAuditLog::new();
+//!     log.push(AuditEntry { ts_ns, pid, from_cpu, to_cpu, reason_code: 0 });
+//!
+//!     // Later, e.g. after the BPF scheduler exited with an error.
+//!     let mut log = log;
+//!     log.dump_to_writer(&mut std::io::stderr())?;
+//!```
+//!
+//! Any number of threads can push() into the same ScxAuditLog concurrently.
+//! Each slot of the ring buffer carries the sequence number of the entry it
+//! holds, which writers claim with a compare-and-swap before writing the
+//! entry. If another writer still holds the slot, i.e. if there are at least
+//! as many concurrent writers as entries, the entry is dropped instead of
+//! waiting. Reading entries back requires exclusive access, i.e. writers must
+//! have been quiesced, which matches the post-mortem use case.
+
+use anyhow::Result;
+use std::cell::UnsafeCell;
+use std::io::Write;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+const DFL_NR_ENTRIES: usize = 65536;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub ts_ns: u64,
+    pub pid: u32,
+    pub from_cpu: u32,
+    pub to_cpu: u32,
+    pub reason_code: u32,
+}
+
+// The sequence number of a slot which is being written.
+const SLOT_BUSY: usize = usize::MAX;
+
+struct Slot {
+    // The sequence number of the entry in the slot + 1, 0 if the slot was
+    // never written, or SLOT_BUSY.
+    seq: AtomicUsize,
+    entry: UnsafeCell<AuditEntry>,
+}
+
+pub struct ScxAuditLog {
+    slots: Box<[Slot]>,
+    head: AtomicUsize,
+}
+
+// Writers only write a slot after claiming it by swapping its sequence number
+// to SLOT_BUSY, and reads require &mut self. See the module documentation.
+unsafe impl Sync for ScxAuditLog {}
+
+impl ScxAuditLog {
+    /// Create a ScxAuditLog with the default number of entries.
+    pub fn new() -> ScxAuditLog {
+        Self::with_capacity(DFL_NR_ENTRIES)
+    }
+
+    /// Create a ScxAuditLog which holds up to @nr_entries entries.
+    pub fn with_capacity(nr_entries: usize) -> ScxAuditLog {
+        let nr_entries = nr_entries.max(1);
+        ScxAuditLog {
+            slots: (0..nr_entries)
+                .map(|_| Slot {
+                    seq: AtomicUsize::new(0),
+                    entry: UnsafeCell::new(AuditEntry::default()),
+                })
+                .collect(),
+            head: AtomicUsize::new(0),
+        }
+    }
+
+    /// The maximum number of entries held by the log.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// The number of entries currently held by the log.
+    pub fn len(&self) -> usize {
+        self.head.load(Ordering::Acquire).min(self.capacity())
+    }
+
+    /// Test whether no entry has been recorded yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Record an entry, overwriting the oldest one if the log is full. The
+    /// entry is dropped if another writer is still writing its slot.
+    pub fn push(&self, entry: AuditEntry) {
+        let seq = self.head.fetch_add(1, Ordering::AcqRel);
+        let slot = &self.slots[seq % self.capacity()];
+
+        // Don't wait for a concurrent writer, nor overwrite a newer entry.
+        let cur = slot.seq.load(Ordering::Relaxed);
+        if cur == SLOT_BUSY || cur > seq + 1 {
+            return;
+        }
+        if slot
+            .seq
+            .compare_exchange(cur, SLOT_BUSY, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+
+        unsafe { *slot.entry.get() = entry };
+        slot.seq.store(seq + 1, Ordering::Release);
+    }
+
+    /// Iterate over the @n most recent entries, from oldest to newest.
+    /// Entries which were dropped by push() are skipped.
+    pub fn iter_recent(&mut self, n: usize) -> impl Iterator<Item = &AuditEntry> {
+        let head = *self.head.get_mut();
+        let nr = n.min(head).min(self.capacity());
+        let cap = self.capacity();
+        let slots = &self.slots;
+
+        (head - nr..head).filter_map(move |seq| {
+            let slot = &slots[seq % cap];
+            if slot.seq.load(Ordering::Acquire) == seq + 1 {
+                Some(unsafe { &*slot.entry.get() })
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Write all entries held by the log, from oldest to newest, one entry
+    /// per line.
+    pub fn dump_to_writer(&mut self, w: &mut impl Write) -> Result<()> {
+        writeln!(
+            w,
+            "{:>20} {:>10} {:>8} {:>8} {:>8}",
+            "ts_ns", "pid", "from", "to", "reason"
+        )?;
+        let nr = self.len();
+        for entry in self.iter_recent(nr) {
+            writeln!(
+                w,
+                "{:>20} {:>10} {:>8} {:>8} {:>8}",
+                entry.ts_ns, entry.pid, entry.from_cpu, entry.to_cpu, entry.reason_code
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for ScxAuditLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AuditEntry;
+    use super::ScxAuditLog;
+
+    fn entry(ts_ns: u64) -> AuditEntry {
+        AuditEntry {
+            ts_ns,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_audit_log_wraparound() {
+        let mut log = ScxAuditLog::with_capacity(4);
+        assert!(log.is_empty());
+
+        for ts in 0..6 {
+            log.push(entry(ts));
+        }
+        assert_eq!(log.len(), 4);
+
+        let recent: Vec<u64> = log.iter_recent(3).map(|e| e.ts_ns).collect();
+        assert_eq!(recent, vec![3, 4, 5]);
+
+        let all: Vec<u64> = log.iter_recent(100).map(|e| e.ts_ns).collect();
+        assert_eq!(all, vec![2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_audit_log_concurrent_push() {
+        let log = ScxAuditLog::with_capacity(1);
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let log = &log;
+                s.spawn(move || {
+                    for ts in 0..1000 {
+                        log.push(entry(t * 1000 + ts));
+                    }
+                });
+            }
+        });
+
+        // The last entry may have been dropped if its slot was still busy.
+        let mut log = log;
+        assert!(log.iter_recent(1).count() <= 1);
+    }
+}
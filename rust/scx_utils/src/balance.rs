@@ -0,0 +1,291 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Domain Balancer
+//!
+//! A crate that implements the load balancing algorithm of scx_rusty
+//! independently of any BPF maps, so that it can be reused by other
+//! schedulers and unit tested.
+//!
+//! ScxDomainBalancer
+//! -----------------
+//!
+//! A ScxDomainBalancer reads the load and the number of tasks of each domain
+//! through a user-supplied DomainStats implementation, and balances load at
+//! two levels: first between NUMA nodes, then between the domains within
+//! each node. At each level, loads are compared to the average, and the
+//! entities whose load deviates from the average by more than 5% push tasks
+//! to or pull tasks from the others. Pushing entities are considered in
+//! descending order of imbalance, and each moves one task at a time to the
+//! most imbalanced pulling entity, for as long as that reduces the total
+//! imbalance of the two and up to half of its load per round.
+//!
+//! As DomainStats only provides the load of each domain rather than of each
+//! task, the tasks of a domain are assumed to contribute equally to its load:
+//!
+//!```
+//!     struct BpfDomainStats<'a> { skel: &'a BpfSkel<'a> }
+//!
+//!     impl DomainStats for BpfDomainStats<'_> {
+//!         fn nr_domains(&self) -> usize { ... }
+//!         fn domain_load(&self, dom: usize) -> f64 { ... }
+//!         fn domain_nr_tasks(&self, dom: usize) -> usize { ... }
+//!         fn domain_node(&self, dom: usize) -> usize { ... }
+//!     }
+//!
+//!     let balancer = ScxDomainBalancer::new(BpfDomainStats { skel: &skel });
+//!     for (src, dst, nr_tasks) in balancer.balance() {
+//!         migrate_tasks(src, dst, nr_tasks);
+//!     }
+//!```
+
+use std::collections::BTreeMap;
+
+/// Provides the load of the domains to balance. Domains are identified by
+/// their index between 0 and nr_domains().
+pub trait DomainStats {
+    /// The number of domains.
+    fn nr_domains(&self) -> usize;
+    /// The load of domain @dom, in any unit as long as it's the same for all
+    /// domains.
+    fn domain_load(&self, dom: usize) -> f64;
+    /// The number of tasks in domain @dom.
+    fn domain_nr_tasks(&self, dom: usize) -> usize;
+    /// The NUMA node of domain @dom. Domains on the same node are balanced
+    /// among each other after balancing across nodes.
+    fn domain_node(&self, dom: usize) -> usize;
+}
+
+// If imbalance gets higher than this ratio, try to balance the loads.
+const LOAD_IMBAL_HIGH_RATIO: f64 = 0.05;
+
+// Don't push out more than this ratio of load on each round, to avoid
+// draining a given domain too much in a single round.
+const LOAD_IMBAL_PUSH_MAX_RATIO: f64 = 0.50;
+
+#[derive(Debug, Clone, Copy)]
+struct Entity {
+    load: f64,
+    nr_tasks: usize,
+}
+
+impl Entity {
+    fn task_load(&self) -> f64 {
+        match self.nr_tasks {
+            0 => 0.0,
+            nr => self.load / nr as f64,
+        }
+    }
+}
+
+// Plan the transfers between @entities, returning (src, dst, nr_tasks)
+// tuples of entity indices.
+fn plan_transfers(entities: &[Entity]) -> Vec<(usize, usize, usize)> {
+    let mut transfers: BTreeMap<(usize, usize), usize> = BTreeMap::new();
+    if entities.len() < 2 {
+        return vec![];
+    }
+
+    let load_avg = entities.iter().map(|e| e.load).sum::<f64>() / entities.len() as f64;
+    let threshold = load_avg * LOAD_IMBAL_HIGH_RATIO;
+    let mut to_push = vec![];
+    let mut to_pull = vec![];
+    for (idx, entity) in entities.iter().enumerate() {
+        let imbal = entity.load - load_avg;
+        if imbal.abs() >= threshold && imbal != 0.0 {
+            if imbal > 0.0 {
+                to_push.push((imbal, idx));
+            } else {
+                to_pull.push((-imbal, idx));
+            }
+        }
+    }
+
+    // Push from the most imbalanced to least.
+    to_push.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    for (mut push_imbal, push_idx) in to_push {
+        let task_load = entities[push_idx].task_load();
+        let push_max = entities[push_idx].load * LOAD_IMBAL_PUSH_MAX_RATIO;
+        let mut nr_tasks = entities[push_idx].nr_tasks;
+        let mut pushed = 0.0;
+
+        while nr_tasks > 0 && pushed < push_max {
+            // Pull into the most imbalanced first.
+            to_pull.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+
+            let old_imbal = |pull_imbal: f64| push_imbal + pull_imbal;
+            let new_imbal =
+                |pull_imbal: f64| (push_imbal - task_load).abs() + (pull_imbal - task_load).abs();
+            let pull = to_pull
+                .iter_mut()
+                .find(|(pull_imbal, _)| new_imbal(*pull_imbal) < old_imbal(*pull_imbal));
+
+            // Stop if no transfer reduces the imbalance.
+            let (pull_imbal, pull_idx) = match pull {
+                Some(pull) => pull,
+                None => break,
+            };
+            *pull_imbal -= task_load;
+            push_imbal -= task_load;
+            pushed += task_load;
+            nr_tasks -= 1;
+            *transfers.entry((push_idx, *pull_idx)).or_insert(0) += 1;
+        }
+    }
+
+    transfers
+        .into_iter()
+        .map(|((src, dst), nr_tasks)| (src, dst, nr_tasks))
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct ScxDomainBalancer<D: DomainStats> {
+    stats: D,
+}
+
+impl<D: DomainStats> ScxDomainBalancer<D> {
+    /// Create a ScxDomainBalancer which reads domain loads from @stats.
+    pub fn new(stats: D) -> ScxDomainBalancer<D> {
+        ScxDomainBalancer { stats }
+    }
+
+    /// Get the DomainStats of the balancer.
+    pub fn stats(&self) -> &D {
+        &self.stats
+    }
+
+    /// Get the DomainStats of the balancer, e.g. to refresh it before the
+    /// next call to balance().
+    pub fn stats_mut(&mut self) -> &mut D {
+        &mut self.stats
+    }
+
+    /// Compute the task transfers which balance the current domain loads, as
+    /// (source domain, destination domain, number of tasks) tuples. Transfers
+    /// across NUMA nodes move tasks from the most loaded domain of the
+    /// source node to the least loaded domain of the destination node.
+    pub fn balance(&self) -> Vec<(usize, usize, usize)> {
+        let nr_doms = self.stats.nr_domains();
+        let mut doms: Vec<Entity> = (0..nr_doms)
+            .map(|dom| Entity {
+                load: self.stats.domain_load(dom),
+                nr_tasks: self.stats.domain_nr_tasks(dom),
+            })
+            .collect();
+
+        let mut node_doms: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for dom in 0..nr_doms {
+            node_doms
+                .entry(self.stats.domain_node(dom))
+                .or_default()
+                .push(dom);
+        }
+        let node_doms: Vec<Vec<usize>> = node_doms.into_values().collect();
+
+        let mut transfers: BTreeMap<(usize, usize), usize> = BTreeMap::new();
+        let mut transfer = |doms: &mut [Entity], src: usize, dst: usize, nr_tasks: usize| {
+            let load = (doms[src].task_load() * nr_tasks as f64).min(doms[src].load);
+            doms[src].load -= load;
+            doms[src].nr_tasks -= nr_tasks;
+            doms[dst].load += load;
+            doms[dst].nr_tasks += nr_tasks;
+            *transfers.entry((src, dst)).or_insert(0) += nr_tasks;
+        };
+
+        // Balance across nodes first.
+        let nodes: Vec<Entity> = node_doms
+            .iter()
+            .map(|node| Entity {
+                load: node.iter().map(|dom| doms[*dom].load).sum(),
+                nr_tasks: node.iter().map(|dom| doms[*dom].nr_tasks).sum(),
+            })
+            .collect();
+        for (src_node, dst_node, nr_tasks) in plan_transfers(&nodes) {
+            let by_load = |a: &&usize, b: &&usize| doms[**a].load.total_cmp(&doms[**b].load);
+            let src = node_doms[src_node]
+                .iter()
+                .filter(|dom| doms[**dom].nr_tasks > 0)
+                .max_by(by_load);
+            let dst = node_doms[dst_node].iter().min_by(by_load);
+            if let (Some(&src), Some(&dst)) = (src, dst) {
+                let nr_tasks = nr_tasks.min(doms[src].nr_tasks);
+                transfer(&mut doms, src, dst, nr_tasks);
+            }
+        }
+
+        // Then balance the domains within each node.
+        for node in node_doms.iter() {
+            let entities: Vec<Entity> = node.iter().map(|dom| doms[*dom]).collect();
+            for (src, dst, nr_tasks) in plan_transfers(&entities) {
+                transfer(&mut doms, node[src], node[dst], nr_tasks);
+            }
+        }
+
+        transfers
+            .into_iter()
+            .filter(|(_, nr_tasks)| *nr_tasks > 0)
+            .map(|((src, dst), nr_tasks)| (src, dst, nr_tasks))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DomainStats;
+    use super::ScxDomainBalancer;
+
+    // (load, nr_tasks, node) of each domain
+    struct MockStats(Vec<(f64, usize, usize)>);
+
+    impl DomainStats for MockStats {
+        fn nr_domains(&self) -> usize {
+            self.0.len()
+        }
+        fn domain_load(&self, dom: usize) -> f64 {
+            self.0[dom].0
+        }
+        fn domain_nr_tasks(&self, dom: usize) -> usize {
+            self.0[dom].1
+        }
+        fn domain_node(&self, dom: usize) -> usize {
+            self.0[dom].2
+        }
+    }
+
+    #[test]
+    fn test_balance_intra_node() {
+        let balancer = ScxDomainBalancer::new(MockStats(vec![(800.0, 8, 0), (200.0, 2, 0)]));
+        // Moving 3 tasks of load 100 brings both domains to the average.
+        assert_eq!(balancer.balance(), vec![(0, 1, 3)]);
+    }
+
+    #[test]
+    fn test_balance_balanced() {
+        let balancer = ScxDomainBalancer::new(MockStats(vec![(500.0, 5, 0), (510.0, 5, 1)]));
+        assert!(balancer.balance().is_empty());
+    }
+
+    #[test]
+    fn test_balance_inter_node() {
+        let balancer = ScxDomainBalancer::new(MockStats(vec![
+            (400.0, 4, 0),
+            (400.0, 4, 0),
+            (0.0, 0, 1),
+            (0.0, 0, 1),
+        ]));
+        let transfers = balancer.balance();
+
+        // Node 0 pushes 4 tasks to node 1, which are then spread across its
+        // domains.
+        let to_node1: usize = transfers
+            .iter()
+            .filter(|(src, dst, _)| *src < 2 && *dst >= 2)
+            .map(|(_, _, nr)| nr)
+            .sum();
+        assert_eq!(to_node1, 4);
+        assert!(transfers.contains(&(2, 3, 2)) || transfers.contains(&(3, 2, 2)));
+    }
+}
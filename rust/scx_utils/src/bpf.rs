@@ -0,0 +1,479 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX BPF Helpers
+//!
+//! A crate of helpers for interacting with the BPF side of a scheduler from
+//! the Rust userspace component.
+//!
+//! BpfGlobalVar
+//! ------------
+//!
+//! BPF skeletons expose the global variables of the BPF program through the
+//! memory mapped .bss, .data and .rodata sections. A BpfGlobalVar wraps a
+//! pointer to such a variable and provides volatile reads and writes, so that
+//! updates made by the BPF side are always observed:
+//!
+//!```
+//!     let nr_dispatched = unsafe {
+//!         BpfGlobalVar::new(&mut skel.bss_mut().nr_dispatched as *mut u64)?
+//!     };
+//!
+//!     info!("dispatched {} tasks", nr_dispatched.get());
+//!     nr_dispatched.set(0);
+//!```
+//!
+//! ScxBpfSkelBuilder
+//! -----------------
+//!
+//! A ScxBpfSkelBuilder wraps the skeleton builder generated by libbpf-cargo,
+//! and applies the configuration that schedulers commonly need between
+//! opening and loading a skeleton:
+//!
+//!```
+//!     let mut skel = ScxBpfSkelBuilder::new(BpfSkelBuilder::default())
+//!         .log_level(1)
+//!         .btf_path(Path::new("/boot/vmlinux.btf"))
+//!         .resize_map("task_ctx", 65536)
+//!         .build()?;
+//!```
+//!
+//! BpfPinHandle
+//! ------------
+//!
+//! A BpfPinHandle pins a BPF program or map to bpffs under
+//! /sys/fs/bpf/scx/<scheduler>/<name>, where <scheduler> is the name of the
+//! scheduler's executable, and unpins it when dropped. Objects which should
+//! outlive the scheduler, e.g. to be picked up again after a restart, can be
+//! kept pinned with keep():
+//!
+//!```
+//!     let map = match BpfPinHandle::load_pinned_map("task_ctx") {
+//!         Ok(map) => map,
+//!         Err(_) => {
+//!             BpfPinHandle::pin_map(skel.maps().task_ctx(), "task_ctx")?.keep();
+//!             MapHandle::try_clone(skel.maps().task_ctx())?
+//!         }
+//!     };
+//!```
+//!
+//! ScxBpfProgramLoader
+//! -------------------
+//!
+//! A ScxBpfProgramLoader retries loading the BPF side of a scheduler with
+//! exponential backoff, e.g. to ride out temporary memory exhaustion while
+//! allocating maps. The load is passed as a closure, which must open the
+//! skeleton again as loading consumes the open skeleton:
+//!
+//!```
+//!     let skel = ScxBpfProgramLoader::new(3, 100).load(|| {
+//!         ScxBpfSkelBuilder::new(BpfSkelBuilder::default()).build()
+//!     })?;
+//!```
+//!
+//! BpfCpumaskSyncMap
+//! -----------------
+//!
+//! A BpfCpumaskSyncMap keeps a BPF_MAP_TYPE_PERCPU_ARRAY with a single entry
+//! in sync with a Cpumask, so that each CPU has a local copy of the mask
+//! which the BPF side can read without locking. The value of the entry is
+//! an array of u64 words laid out as Cpumask::as_raw_slice():
+//!
+//!```
+//!     struct {
+//!         __uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+//!         __uint(max_entries, 1);
+//!         __type(key, u32);
+//!         __type(value, u64[MAX_CPUS / 64]);
+//!     } allowed_cpus SEC(".maps");
+//!```
+//!
+//! sync() updates the copies of all CPUs at once, and is a no-op if the mask
+//! didn't change since the last sync():
+//!
+//!```
+//!     let mut allowed = BpfCpumaskSyncMap::new(skel.maps().allowed_cpus())?;
+//!     loop {
+//!         allowed.sync(&compute_allowed_cpus()?)?;
+//!         std::thread::sleep(Duration::from_secs(1));
+//!     }
+//!```
+
+use crate::Cpumask;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use libbpf_rs::libbpf_sys;
+use libbpf_rs::skel::OpenSkel;
+use libbpf_rs::skel::SkelBuilder;
+use libbpf_rs::Map;
+use libbpf_rs::MapFlags;
+use libbpf_rs::MapHandle;
+use libbpf_rs::MapType;
+use libbpf_rs::Program;
+use std::ffi::CString;
+use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
+use std::os::fd::BorrowedFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct BpfGlobalVar<T: Copy> {
+    ptr: *mut T,
+}
+
+// The pointed-to memory is shared with the BPF program anyway, and all
+// accesses are single volatile loads and stores of T. See BpfGlobalVar::new()
+// for the requirements on the pointer.
+unsafe impl<T: Copy + Send> Send for BpfGlobalVar<T> {}
+unsafe impl<T: Copy + Send> Sync for BpfGlobalVar<T> {}
+
+impl<T: Copy> BpfGlobalVar<T> {
+    /// Wrap a pointer to a BPF global variable. Returns an error if @ptr is
+    /// NULL or not properly aligned for T.
+    ///
+    /// # Safety
+    ///
+    /// @ptr must point to a valid T which remains mapped for the lifetime of
+    /// the BpfGlobalVar, e.g. a global variable of a BPF skeleton which
+    /// outlives the BpfGlobalVar. T must be a type for which every bit
+    /// pattern the BPF side can write is valid, e.g. integers.
+    ///
+    /// Note that volatile accesses aren't atomic. If T is larger than the
+    /// native word size, get() may observe a torn value written concurrently
+    /// by the BPF side.
+    pub unsafe fn new(ptr: *mut T) -> Result<BpfGlobalVar<T>> {
+        if ptr.is_null() {
+            bail!("BPF global variable pointer is NULL");
+        }
+        if ptr.align_offset(std::mem::align_of::<T>()) != 0 {
+            bail!(
+                "BPF global variable pointer {:p} is not aligned to {} bytes",
+                ptr,
+                std::mem::align_of::<T>()
+            );
+        }
+
+        Ok(BpfGlobalVar { ptr })
+    }
+
+    /// Read the current value of the variable.
+    pub fn get(&self) -> T {
+        unsafe { std::ptr::read_volatile(self.ptr) }
+    }
+
+    /// Update the value of the variable.
+    pub fn set(&self, val: T) {
+        unsafe { std::ptr::write_volatile(self.ptr, val) }
+    }
+}
+
+pub struct ScxBpfSkelBuilder<'a, B: SkelBuilder<'a>> {
+    builder: B,
+    log_level: Option<u32>,
+    btf_path: Option<PathBuf>,
+    map_sizes: Vec<(String, u32)>,
+    _marker: std::marker::PhantomData<&'a ()>,
+}
+
+impl<'a, B: SkelBuilder<'a>> ScxBpfSkelBuilder<'a, B> {
+    /// Wrap a skeleton builder generated by libbpf-cargo.
+    pub fn new(builder: B) -> ScxBpfSkelBuilder<'a, B> {
+        ScxBpfSkelBuilder {
+            builder,
+            log_level: None,
+            btf_path: None,
+            map_sizes: vec![],
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Set the log level of the BPF verifier when loading the programs, see
+    /// kernel_log_level of struct bpf_object_open_opts.
+    pub fn log_level(mut self, level: u32) -> Self {
+        self.log_level = Some(level);
+        self
+    }
+
+    /// Use the kernel BTF at @path for CO-RE relocations instead of the BTF
+    /// of the running kernel, e.g. on kernels without /sys/kernel/btf/vmlinux.
+    pub fn btf_path(mut self, path: &Path) -> Self {
+        self.btf_path = Some(path.to_path_buf());
+        self
+    }
+
+    /// Set the max_entries of the map @name before loading.
+    pub fn resize_map(mut self, name: &str, max_entries: u32) -> Self {
+        self.map_sizes.push((name.to_string(), max_entries));
+        self
+    }
+
+    /// Open the skeleton and apply the configuration, without loading it.
+    /// This allows further configuration, e.g. of read-only data, before
+    /// calling load() on the returned skeleton.
+    pub fn open(self) -> Result<B::Output> {
+        let mut opts = *self.builder.object_builder().opts();
+
+        // Must outlive open_opts() below.
+        let btf_path = match &self.btf_path {
+            Some(path) => Some(CString::new(path.as_os_str().as_bytes())?),
+            None => None,
+        };
+        if let Some(path) = btf_path.as_ref() {
+            opts.btf_custom_path = path.as_ptr();
+        }
+        if let Some(level) = self.log_level {
+            opts.kernel_log_level = level;
+        }
+
+        let mut skel = self
+            .builder
+            .open_opts(opts)
+            .context("Failed to open BPF program")?;
+
+        for (name, max_entries) in self.map_sizes.iter() {
+            let map = match skel.open_object_mut().map_mut(name) {
+                Some(map) => map,
+                None => bail!("BPF program has no map {:?} to resize", name),
+            };
+            map.set_max_entries(*max_entries)
+                .with_context(|| format!("Failed to resize map {:?} to {}", name, max_entries))?;
+        }
+
+        Ok(skel)
+    }
+
+    /// Open the skeleton, apply the configuration and load it.
+    pub fn build(self) -> Result<<B::Output as OpenSkel>::Output> {
+        self.open()?.load().context("Failed to load BPF program")
+    }
+}
+
+const BPF_PIN_ROOT: &str = "/sys/fs/bpf/scx";
+
+#[derive(Debug)]
+pub struct BpfPinHandle {
+    path: PathBuf,
+}
+
+impl BpfPinHandle {
+    fn pin_path(name: &str) -> Result<PathBuf> {
+        let exe = std::env::current_exe().context("Failed to find the scheduler executable")?;
+        let scheduler = match exe.file_name() {
+            Some(scheduler) => scheduler.to_owned(),
+            None => bail!("Failed to get the scheduler name from {:?}", exe),
+        };
+        Ok(Path::new(BPF_PIN_ROOT).join(scheduler).join(name))
+    }
+
+    fn pin_fd(fd: BorrowedFd, name: &str) -> Result<BpfPinHandle> {
+        let path = Self::pin_path(name)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create bpffs directory {:?}", dir))?;
+        }
+
+        let cpath = CString::new(path.as_os_str().as_bytes())?;
+        if unsafe { libbpf_sys::bpf_obj_pin(fd.as_raw_fd(), cpath.as_ptr()) } < 0 {
+            bail!(
+                "Failed to pin BPF object to {:?} ({})",
+                path,
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(BpfPinHandle { path })
+    }
+
+    /// Pin @prog as @name under the bpffs directory of the scheduler.
+    pub fn pin_program(prog: &Program, name: &str) -> Result<BpfPinHandle> {
+        Self::pin_fd(prog.as_fd(), name)
+            .with_context(|| format!("Failed to pin program {}", prog.name()))
+    }
+
+    /// Pin @map as @name under the bpffs directory of the scheduler.
+    pub fn pin_map(map: &Map, name: &str) -> Result<BpfPinHandle> {
+        Self::pin_fd(map.as_fd(), name).with_context(|| format!("Failed to pin map {}", map.name()))
+    }
+
+    /// Open the map pinned as @name under the bpffs directory of the
+    /// scheduler, e.g. by a previous instance of the scheduler. As libbpf-rs
+    /// only creates Maps as part of an Object, this returns a MapHandle.
+    pub fn load_pinned_map(name: &str) -> Result<MapHandle> {
+        let path = Self::pin_path(name)?;
+        MapHandle::from_pinned_path(&path)
+            .with_context(|| format!("Failed to open pinned map {:?}", path))
+    }
+
+    /// The bpffs path the object is pinned at.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Keep the object pinned after the handle is gone, e.g. so that it can
+    /// be reattached after a restart. Returns the pin path.
+    pub fn keep(mut self) -> PathBuf {
+        // An empty path tells drop() not to unpin.
+        std::mem::take(&mut self.path)
+    }
+}
+
+impl Drop for BpfPinHandle {
+    fn drop(&mut self) {
+        if self.path.as_os_str().is_empty() {
+            return;
+        }
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            log::warn!("Failed to unpin {:?}: {}", self.path, e);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScxBpfProgramLoader {
+    max_retries: u32,
+    base_delay: Duration,
+}
+
+impl ScxBpfProgramLoader {
+    /// Create a ScxBpfProgramLoader which retries a failed load up to
+    /// @max_retries times, waiting @base_delay_ms before the first retry and
+    /// doubling the delay before each subsequent one.
+    pub fn new(max_retries: u32, base_delay_ms: u64) -> ScxBpfProgramLoader {
+        ScxBpfProgramLoader {
+            max_retries,
+            base_delay: Duration::from_millis(base_delay_ms),
+        }
+    }
+
+    /// Call @load until it succeeds or the retries are exhausted, in which
+    /// case the error of the last attempt is returned.
+    pub fn load<T, F: FnMut() -> Result<T>>(&self, mut load: F) -> Result<T> {
+        let mut delay = self.base_delay;
+        let mut attempt = 0;
+        loop {
+            match load() {
+                Ok(loaded) => return Ok(loaded),
+                Err(e) if attempt < self.max_retries => {
+                    attempt += 1;
+                    log::warn!(
+                        "Failed to load BPF program ({:#}), retry {}/{} in {:?}",
+                        e,
+                        attempt,
+                        self.max_retries,
+                        delay
+                    );
+                    std::thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("Failed to load BPF program after {} attempts", attempt + 1)
+                    })
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BpfCpumaskSyncMap {
+    map: MapHandle,
+    last: Option<Cpumask>,
+}
+
+impl BpfCpumaskSyncMap {
+    /// Wrap @map, which must be a BPF_MAP_TYPE_PERCPU_ARRAY with u32 keys
+    /// whose values are arrays of u64 words.
+    pub fn new(map: &Map) -> Result<BpfCpumaskSyncMap> {
+        if map.map_type() != MapType::PercpuArray {
+            bail!("Map {} is not a BPF_MAP_TYPE_PERCPU_ARRAY", map.name());
+        }
+        if map.key_size() as usize != std::mem::size_of::<u32>() {
+            bail!(
+                "Map {} has key size {}, expected {}",
+                map.name(),
+                map.key_size(),
+                std::mem::size_of::<u32>()
+            );
+        }
+        if map.value_size() == 0 || map.value_size() % 8 != 0 {
+            bail!(
+                "Map {} has value size {}, expected a multiple of 8",
+                map.name(),
+                map.value_size()
+            );
+        }
+
+        let map = MapHandle::try_clone(map)
+            .with_context(|| format!("Failed to clone handle of map {}", map.name()))?;
+        Ok(BpfCpumaskSyncMap { map, last: None })
+    }
+
+    /// Update the copy of every CPU to @mask, unless @mask is the same as in
+    /// the last successful sync(). Returns an error if @mask doesn't fit in
+    /// the map value.
+    pub fn sync(&mut self, mask: &Cpumask) -> Result<()> {
+        if self.last.as_ref() == Some(mask) {
+            return Ok(());
+        }
+
+        let mut value = vec![0u8; self.map.value_size() as usize];
+        for (i, word) in mask.as_raw_slice().iter().enumerate() {
+            if *word == 0 {
+                continue;
+            }
+            match value.get_mut(i * 8..(i + 1) * 8) {
+                Some(dst) => dst.copy_from_slice(&word.to_ne_bytes()),
+                None => bail!(
+                    "Cpumask of {} CPUs doesn't fit in map {} of value size {}",
+                    mask.len(),
+                    self.map.name(),
+                    value.len()
+                ),
+            }
+        }
+
+        let values = vec![value; libbpf_rs::num_possible_cpus()?];
+        self.map
+            .update_percpu(&0u32.to_ne_bytes(), &values, MapFlags::ANY)
+            .with_context(|| format!("Failed to update map {}", self.map.name()))?;
+        self.last = Some(mask.clone());
+        Ok(())
+    }
+
+    /// Read back the copies of all CPUs and OR them together, e.g. to see
+    /// whether the BPF side modified them. The returned Cpumask has as many
+    /// CPUs as the last synced one, or all possible CPUs if none was synced
+    /// yet.
+    pub fn get(&self) -> Result<Cpumask> {
+        let values = self
+            .map
+            .lookup_percpu(&0u32.to_ne_bytes(), MapFlags::ANY)
+            .with_context(|| format!("Failed to look up map {}", self.map.name()))?
+            .with_context(|| format!("Cpumask missing in map {}", self.map.name()))?;
+
+        let mut words = vec![0u64; self.map.value_size() as usize / 8];
+        for value in values.iter() {
+            for (word, bytes) in words.iter_mut().zip(value.chunks_exact(8)) {
+                *word |= u64::from_ne_bytes(bytes.try_into().unwrap());
+            }
+        }
+
+        let mut mask = match &self.last {
+            Some(last) => last.clone(),
+            None => Cpumask::new()?,
+        };
+        mask.clear();
+        mask.set_if(|cpu| {
+            words
+                .get(cpu / 64)
+                .is_some_and(|w| w & (1 << (cpu % 64)) != 0)
+        });
+        Ok(mask)
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX BPF Program Statistics
+//!
+//! A crate that allows schedulers to read how often the programs implementing
+//! their BPF ops are invoked, and how long they take on average.
+//!
+//! BpfProgramStats
+//! ---------------
+//!
+//! The kernel only accounts BPF program run counts and run times while BPF
+//! statistics are enabled, which is the case if the kernel.bpf_stats_enabled
+//! sysctl is set (e.g. on kernels with CONFIG_BPF_STATS_DEFAULT_ENABLE), or
+//! while a BpfStatsEnabled guard is alive:
+//!
+//!```
+//!     let _guard = BpfProgramStats::enable_stats()?;
+//!
+//!     // ...
+//!
+//!     let stats = BpfProgramStats::from_object(skel.object())?;
+//!     for (name, prog) in stats.programs().iter() {
+//!         info!("{}: {} runs, {}ns avg", name, prog.run_cnt, prog.avg_run_time_ns());
+//!     }
+//!```
+
+use anyhow::bail;
+use anyhow::Result;
+use libbpf_rs::libbpf_sys;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
+use std::os::fd::BorrowedFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+static NR_STATS_GUARDS: AtomicUsize = AtomicUsize::new(0);
+
+/// Keeps BPF statistics enabled while alive. See
+/// BpfProgramStats::enable_stats().
+#[derive(Debug)]
+pub struct BpfStatsEnabled {
+    _fd: OwnedFd,
+}
+
+impl Drop for BpfStatsEnabled {
+    fn drop(&mut self) {
+        NR_STATS_GUARDS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProgramStat {
+    /// The number of times the program ran
+    pub run_cnt: u64,
+    /// The total time the program ran for in nanoseconds
+    pub run_time_ns: u64,
+}
+
+impl ProgramStat {
+    /// Get the average run time of the program in nanoseconds.
+    pub fn avg_run_time_ns(&self) -> u64 {
+        match self.run_cnt {
+            0 => 0,
+            cnt => self.run_time_ns / cnt,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BpfProgramStats {
+    /// The number of times any of the programs ran
+    pub run_cnt: u64,
+    /// The total time all programs ran for in nanoseconds
+    pub run_time_ns: u64,
+    programs: HashMap<String, ProgramStat>,
+}
+
+fn read_prog_info(fd: BorrowedFd<'_>) -> Result<libbpf_sys::bpf_prog_info> {
+    let mut info = libbpf_sys::bpf_prog_info::default();
+    let mut len = std::mem::size_of::<libbpf_sys::bpf_prog_info>() as u32;
+
+    let ret = unsafe {
+        libbpf_sys::bpf_obj_get_info_by_fd(
+            fd.as_raw_fd(),
+            &mut info as *mut libbpf_sys::bpf_prog_info as *mut _,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        bail!(
+            "BPF_OBJ_GET_INFO_BY_FD failed for fd {} ({})",
+            fd.as_raw_fd(),
+            std::io::Error::from_raw_os_error(-ret)
+        );
+    }
+    Ok(info)
+}
+
+impl BpfProgramStats {
+    /// Are BPF statistics currently enabled, either via the
+    /// kernel.bpf_stats_enabled sysctl or a BpfStatsEnabled guard?
+    pub fn stats_enabled() -> bool {
+        if NR_STATS_GUARDS.load(Ordering::Relaxed) > 0 {
+            return true;
+        }
+
+        match std::fs::read_to_string("/proc/sys/kernel/bpf_stats_enabled") {
+            Ok(val) => val.trim() != "0",
+            Err(_) => false,
+        }
+    }
+
+    /// Enable BPF statistics until the returned guard is dropped. This
+    /// requires CAP_SYS_ADMIN.
+    pub fn enable_stats() -> Result<BpfStatsEnabled> {
+        let fd = unsafe { libbpf_sys::bpf_enable_stats(libbpf_sys::BPF_STATS_RUN_TIME) };
+        if fd < 0 {
+            bail!(
+                "Failed to enable BPF statistics ({})",
+                std::io::Error::from_raw_os_error(-fd)
+            );
+        }
+
+        NR_STATS_GUARDS.fetch_add(1, Ordering::Relaxed);
+        Ok(BpfStatsEnabled {
+            _fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    fn check_enabled() -> Result<()> {
+        if !Self::stats_enabled() {
+            bail!(concat!(
+                "BPF statistics are disabled, set the kernel.bpf_stats_enabled",
+                " sysctl or use BpfProgramStats::enable_stats()"
+            ));
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, name: String, info: &libbpf_sys::bpf_prog_info) {
+        let stat = ProgramStat {
+            run_cnt: info.run_cnt,
+            run_time_ns: info.run_time_ns,
+        };
+
+        self.run_cnt += stat.run_cnt;
+        self.run_time_ns += stat.run_time_ns;
+        self.programs.insert(name, stat);
+    }
+
+    /// Read the statistics of all programs of a loaded BPF object, keyed by
+    /// program name.
+    pub fn from_object(obj: &libbpf_rs::Object) -> Result<BpfProgramStats> {
+        Self::check_enabled()?;
+
+        let mut stats = BpfProgramStats::default();
+        for prog in obj.progs_iter() {
+            let info = read_prog_info(prog.as_fd())?;
+            stats.record(prog.name().to_string(), &info);
+        }
+        Ok(stats)
+    }
+
+    /// Read the statistics of the BPF programs referred to by @fds, keyed by
+    /// the program names reported by the kernel. Note that the kernel
+    /// truncates program names to 15 characters.
+    pub fn from_fds<'a, I>(fds: I) -> Result<BpfProgramStats>
+    where
+        I: IntoIterator<Item = BorrowedFd<'a>>,
+    {
+        Self::check_enabled()?;
+
+        let mut stats = BpfProgramStats::default();
+        for fd in fds {
+            let info = read_prog_info(fd)?;
+            let name = unsafe { CStr::from_ptr(info.name.as_ptr()) }
+                .to_string_lossy()
+                .to_string();
+            stats.record(name, &info);
+        }
+        Ok(stats)
+    }
+
+    /// Get the average run time across all programs in nanoseconds.
+    pub fn avg_run_time_ns(&self) -> u64 {
+        match self.run_cnt {
+            0 => 0,
+            cnt => self.run_time_ns / cnt,
+        }
+    }
+
+    /// Get the map of <program name, ProgramStat> for all programs.
+    pub fn programs(&self) -> &HashMap<String, ProgramStat> {
+        &self.programs
+    }
+}
@@ -0,0 +1,193 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX BTF Struct Access
+//!
+//! A crate that allows schedulers to access the fields of C structs, e.g.
+//! BPF map values or kernel structs, by name rather than by hardcoded byte
+//! offsets, which can change across kernel versions.
+//!
+//! BpfStructAccess
+//! ---------------
+//!
+//! A BpfStructAccess resolves the offsets and sizes of the fields of a struct
+//! from BTF once, and then reads fields out of raw buffers:
+//!
+//!```
+//!     let task_ctx = BpfStructAccess::from_vmlinux("task_struct")?;
+//!     let pid: i32 = task_ctx.read_field(&buf, "pid")?;
+//!```
+//!
+//! Fields of anonymous nested structs and unions are accessible by their own
+//! name, as in C. Bitfields are not supported.
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use bytemuck::Pod;
+use libbpf_rs::btf::types::Array;
+use libbpf_rs::btf::types::Composite;
+use libbpf_rs::btf::types::Enum;
+use libbpf_rs::btf::types::Enum64;
+use libbpf_rs::btf::types::Float;
+use libbpf_rs::btf::types::Int;
+use libbpf_rs::btf::types::MemberAttr;
+use libbpf_rs::btf::types::Struct;
+use libbpf_rs::btf::BtfKind;
+use libbpf_rs::btf::BtfType;
+use libbpf_rs::Btf;
+use libbpf_rs::HasSize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy)]
+struct Field {
+    offset: usize,
+    size: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct BpfStructAccess {
+    name: String,
+    size: usize,
+    // Field name -> byte offset and size
+    fields: BTreeMap<String, Field>,
+}
+
+// Resolve the size in bytes of @ty, following typedefs and modifiers.
+fn type_size(btf: &Btf, ty: BtfType) -> Result<usize> {
+    let ty = ty.skip_mods_and_typedefs();
+    let size = match ty.kind() {
+        BtfKind::Int => Int::try_from(ty).map(|t| t.size()).ok(),
+        BtfKind::Float => Float::try_from(ty).map(|t| t.size()).ok(),
+        BtfKind::Enum => Enum::try_from(ty).map(|t| t.size()).ok(),
+        BtfKind::Enum64 => Enum64::try_from(ty).map(|t| t.size()).ok(),
+        BtfKind::Struct | BtfKind::Union => Composite::try_from(ty).map(|t| t.size()).ok(),
+        BtfKind::Ptr => Some(btf.ptr_size()?.get()),
+        BtfKind::Array => match Array::try_from(ty) {
+            Ok(array) => Some(array.capacity() * type_size(btf, array.contained_type())?),
+            Err(_) => None,
+        },
+        _ => None,
+    };
+    size.ok_or_else(|| anyhow!("Can't determine the size of BTF type {:?}", ty.kind()))
+}
+
+// Add the fields of @composite, which starts at @base bytes into the
+// outermost struct, to @fields. Anonymous members are flattened.
+fn add_fields(
+    btf: &Btf,
+    composite: Composite,
+    base: usize,
+    fields: &mut BTreeMap<String, Field>,
+) -> Result<()> {
+    for member in composite.iter() {
+        let member_ty = btf
+            .type_by_id::<BtfType>(member.ty)
+            .ok_or_else(|| anyhow!("Invalid BTF type id {:?}", member.ty))?;
+        // In structs which contain bitfields, all members are described as
+        // bitfields, the ones of size 0 being regular members.
+        let offset = match member.attr {
+            MemberAttr::Normal { offset } | MemberAttr::BitField { size: 0, offset }
+                if offset % 8 == 0 =>
+            {
+                base + offset as usize / 8
+            }
+            // Bitfields can't be read as a whole number of bytes, skip them.
+            _ => continue,
+        };
+
+        match member.name {
+            Some(name) if !name.to_bytes().is_empty() => {
+                let size = type_size(btf, member_ty)?;
+                fields.insert(name.to_string_lossy().to_string(), Field { offset, size });
+            }
+            _ => {
+                if let Ok(nested) = Composite::try_from(member_ty.skip_mods_and_typedefs()) {
+                    add_fields(btf, nested, offset, fields)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl BpfStructAccess {
+    /// Resolve the fields of struct @name in @btf, e.g. the BTF of a BPF
+    /// object to access the values of its maps.
+    pub fn new(btf: &Btf, name: &str) -> Result<BpfStructAccess> {
+        let st: Struct = btf
+            .type_by_name(name)
+            .ok_or_else(|| anyhow!("No struct {} found in BTF", name))?;
+        let size = st.size();
+
+        let composite =
+            Composite::try_from(*st).map_err(|_| anyhow!("BTF type {} is not a struct", name))?;
+        let mut fields = BTreeMap::new();
+        add_fields(btf, composite, 0, &mut fields)
+            .with_context(|| format!("Failed to resolve the fields of struct {}", name))?;
+
+        Ok(BpfStructAccess {
+            name: name.to_string(),
+            size,
+            fields,
+        })
+    }
+
+    /// Resolve the fields of struct @name in the BTF of the running kernel,
+    /// /sys/kernel/btf/vmlinux.
+    pub fn from_vmlinux(name: &str) -> Result<BpfStructAccess> {
+        let btf = Btf::from_vmlinux().context("Failed to read kernel BTF")?;
+        Self::new(&btf, name)
+    }
+
+    /// The size of the struct in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn field(&self, name: &str) -> Result<Field> {
+        match self.fields.get(name) {
+            Some(field) => Ok(*field),
+            None => bail!("No field {} in struct {}", name, self.name),
+        }
+    }
+
+    /// Get the byte offset of field @name in the struct.
+    pub fn field_offset(&self, name: &str) -> Result<usize> {
+        Ok(self.field(name)?.offset)
+    }
+
+    /// Get the size in bytes of field @name.
+    pub fn field_size(&self, name: &str) -> Result<usize> {
+        Ok(self.field(name)?.size)
+    }
+
+    /// Read field @name out of @buf, which holds the raw bytes of the struct.
+    /// Returns an error if @T isn't of the size of the field, or if @buf is
+    /// too short to contain the field.
+    pub fn read_field<T: Pod>(&self, buf: &[u8], name: &str) -> Result<T> {
+        let field = self.field(name)?;
+        if std::mem::size_of::<T>() != field.size {
+            bail!(
+                "Field {}.{} is {} bytes, can't read it as a {} byte value",
+                self.name,
+                name,
+                field.size,
+                std::mem::size_of::<T>()
+            );
+        }
+        match buf.get(field.offset..field.offset + field.size) {
+            Some(bytes) => Ok(bytemuck::pod_read_unaligned(bytes)),
+            None => bail!(
+                "Buffer of {} bytes is too short for field {}.{} at offset {}",
+                buf.len(),
+                self.name,
+                name,
+                field.offset
+            ),
+        }
+    }
+}
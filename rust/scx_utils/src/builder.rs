@@ -45,6 +45,7 @@ impl Builder {
         let bindings = bindgen::Builder::default()
             .header("bindings.h")
             .allowlist_type("scx_exit_kind")
+            .allowlist_type("scx_dsq_id_flags")
             .parse_callbacks(Box::new(bindgen::CargoCallbacks))
             .generate()
             .expect("Unable to generate bindings");
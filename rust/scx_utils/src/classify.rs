@@ -0,0 +1,210 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Task Classifier
+//!
+//! A crate that allows schedulers to classify tasks from their recent
+//! scheduling history, without having to implement the heuristics in BPF.
+//!
+//! TaskClassifier
+//! --------------
+//!
+//! A TaskClassifier tracks the following exponential moving averages for each
+//! PID that is fed to it:
+//!
+//! - The ratio of the assigned time slice that the task used before giving up
+//!   the CPU.
+//! - The frequency at which the task wakes up, in wakeups per second.
+//! - The duration the task slept for before waking up.
+//!
+//! These are then compared against a set of configurable thresholds to put the
+//! task in one of three classes:
+//!
+//! - *Idle*: The task mostly sleeps for long periods of time.
+//! - *LatencySensitive*: The task wakes up frequently and gives up the CPU well
+//!   before exhausting its slice, e.g. an interactive or audio task.
+//! - *Batch*: Everything else, e.g. CPU bound tasks.
+//!
+//!```
+//!     let mut classifier = TaskClassifier::new(4096);
+//!
+//!     // From e.g. events reported by the BPF scheduler.
+//!     classifier.record_wakeup(pid, now_ns, slept_ns);
+//!     classifier.record_slice(pid, used_ns, slice_ns);
+//!
+//!     match classifier.classify(pid) {
+//!         TaskClass::LatencySensitive => ...,
+//!         TaskClass::Batch => ...,
+//!         TaskClass::Idle => ...,
+//!     }
+//!```
+//!
+//! To bound memory usage, a TaskClassifier tracks at most a configured number
+//! of PIDs. Once the limit is reached, the least recently updated PID is
+//! forgotten.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+// Weight of a new sample in the moving averages.
+const EMA_WEIGHT: f64 = 0.25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskClass {
+    LatencySensitive,
+    Batch,
+    Idle,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClassifierThresholds {
+    /// Tasks which use at most this ratio of their slice on average can be
+    /// latency sensitive.
+    pub latency_max_slice_ratio: f64,
+    /// Tasks which wake up at least this many times per second on average can
+    /// be latency sensitive.
+    pub latency_min_wake_freq: f64,
+    /// Tasks which sleep at least this long on average are idle.
+    pub idle_min_sleep_ns: u64,
+}
+
+impl Default for ClassifierThresholds {
+    fn default() -> Self {
+        ClassifierThresholds {
+            latency_max_slice_ratio: 0.5,
+            latency_min_wake_freq: 100.0,
+            idle_min_sleep_ns: 1_000_000_000,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct TaskHistory {
+    slice_ratio: f64,
+    wake_freq: f64,
+    sleep_ns: f64,
+    last_wake_ns: Option<u64>,
+    last_update: u64,
+}
+
+fn ema(avg: f64, sample: f64) -> f64 {
+    avg * (1.0 - EMA_WEIGHT) + sample * EMA_WEIGHT
+}
+
+#[derive(Debug)]
+pub struct TaskClassifier {
+    tasks: HashMap<u32, TaskHistory>,
+    lru: BTreeMap<u64, u32>,
+    clock: u64,
+    max_pids: usize,
+    thresholds: ClassifierThresholds,
+}
+
+impl TaskClassifier {
+    /// Create a TaskClassifier which tracks up to @max_pids PIDs using the
+    /// default thresholds.
+    pub fn new(max_pids: usize) -> TaskClassifier {
+        Self::with_thresholds(max_pids, ClassifierThresholds::default())
+    }
+
+    /// Create a TaskClassifier which tracks up to @max_pids PIDs using the
+    /// specified thresholds.
+    pub fn with_thresholds(max_pids: usize, thresholds: ClassifierThresholds) -> TaskClassifier {
+        TaskClassifier {
+            tasks: HashMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            max_pids: max_pids.max(1),
+            thresholds,
+        }
+    }
+
+    /// Get the thresholds used for classification.
+    pub fn thresholds(&self) -> &ClassifierThresholds {
+        &self.thresholds
+    }
+
+    /// Update the thresholds used for classification.
+    pub fn set_thresholds(&mut self, thresholds: ClassifierThresholds) {
+        self.thresholds = thresholds;
+    }
+
+    fn touch(&mut self, pid: u32) -> &mut TaskHistory {
+        self.clock += 1;
+        let clock = self.clock;
+
+        if !self.tasks.contains_key(&pid) && self.tasks.len() >= self.max_pids {
+            if let Some((_, victim)) = self.lru.pop_first() {
+                self.tasks.remove(&victim);
+            }
+        }
+
+        let task = self.tasks.entry(pid).or_default();
+        self.lru.remove(&task.last_update);
+        self.lru.insert(clock, pid);
+        task.last_update = clock;
+        task
+    }
+
+    /// Record that @pid ran for @used_ns out of its assigned slice of
+    /// @slice_ns before giving up the CPU.
+    pub fn record_slice(&mut self, pid: u32, used_ns: u64, slice_ns: u64) {
+        if slice_ns == 0 {
+            return;
+        }
+
+        let ratio = (used_ns as f64 / slice_ns as f64).min(1.0);
+        let task = self.touch(pid);
+        task.slice_ratio = ema(task.slice_ratio, ratio);
+    }
+
+    /// Record that @pid woke up at @now_ns after sleeping for @slept_ns.
+    pub fn record_wakeup(&mut self, pid: u32, now_ns: u64, slept_ns: u64) {
+        let task = self.touch(pid);
+
+        if let Some(last_wake_ns) = task.last_wake_ns {
+            let interval_ns = now_ns.saturating_sub(last_wake_ns).max(1);
+            task.wake_freq = ema(task.wake_freq, 1_000_000_000.0 / interval_ns as f64);
+        }
+        task.last_wake_ns = Some(now_ns);
+        task.sleep_ns = ema(task.sleep_ns, slept_ns as f64);
+    }
+
+    /// Classify @pid according to its recorded history. PIDs without any
+    /// recorded history are classified as TaskClass::Batch.
+    pub fn classify(&self, pid: u32) -> TaskClass {
+        let task = match self.tasks.get(&pid) {
+            Some(task) => task,
+            None => return TaskClass::Batch,
+        };
+
+        if task.sleep_ns >= self.thresholds.idle_min_sleep_ns as f64 {
+            TaskClass::Idle
+        } else if task.slice_ratio <= self.thresholds.latency_max_slice_ratio
+            && task.wake_freq >= self.thresholds.latency_min_wake_freq
+        {
+            TaskClass::LatencySensitive
+        } else {
+            TaskClass::Batch
+        }
+    }
+
+    /// Forget the history of @pid, e.g. after the task exited.
+    pub fn remove(&mut self, pid: u32) {
+        if let Some(task) = self.tasks.remove(&pid) {
+            self.lru.remove(&task.last_update);
+        }
+    }
+
+    /// The number of PIDs currently tracked.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Test whether no PID is currently tracked.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Config
+//!
+//! A crate that allows schedulers to read their configuration from a TOML
+//! file, so that it can be version controlled and reproduced across
+//! deployments.
+//!
+//! ScxConfig
+//! ---------
+//!
+//! A ScxConfig holds the common scheduler knobs, grouped into sections:
+//!
+//!```
+//!     [timing]
+//!     slice_us = 20000
+//!     slice_min_us = 250
+//!
+//!     [topology]
+//!     cpumask = "0-15,32-47"
+//!     numa_aware = true
+//!
+//!     [power]
+//!     powersave = false
+//!     deep_idle_threshold = 0.9
+//!
+//!     [debug]
+//!     verbose = 1
+//!     exit_dump_len = 4096
+//!```
+//!
+//! All knobs are optional. Unknown sections and keys are rejected to catch
+//! typos. Knobs given on the command line, collected in a ScxOpts, override
+//! the values from the file:
+//!
+//!```
+//!     let cli = ScxOpts {
+//!         slice_us: opts.slice_us,
+//!         verbose: Some(opts.verbose),
+//!         ..Default::default()
+//!     };
+//!     let config = ScxConfig::from_toml_file(&opts.config)?.merge_with_cli(&cli);
+//!
+//!     let slice_us = config.slice_us().unwrap_or(20000);
+//!```
+
+use crate::Cpumask;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::path::Path;
+use toml_edit::Document;
+use toml_edit::Item;
+
+/// The knobs of a ScxConfig, e.g. as given on the command line. Knobs which
+/// weren't given are None.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScxOpts {
+    /// [timing] The default time slice in microseconds
+    pub slice_us: Option<u64>,
+    /// [timing] The minimum time slice in microseconds
+    pub slice_min_us: Option<u64>,
+    /// [topology] The CPUs to schedule on, as a hex mask or CPU list
+    pub cpumask: Option<String>,
+    /// [topology] Whether to keep tasks within their NUMA node
+    pub numa_aware: Option<bool>,
+    /// [power] Whether to favor energy efficiency over performance
+    pub powersave: Option<bool>,
+    /// [power] The idle ratio above which a package is left idle
+    pub deep_idle_threshold: Option<f64>,
+    /// [debug] The verbosity of the scheduler's output
+    pub verbose: Option<u32>,
+    /// [debug] The size of the BPF debug dump on exit
+    pub exit_dump_len: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScxConfig {
+    knobs: ScxOpts,
+}
+
+fn parse_u64(section: &str, key: &str, item: &Item) -> Result<u64> {
+    match item.as_integer() {
+        Some(val) if val >= 0 => Ok(val as u64),
+        _ => bail!("[{}] {} must be a non-negative integer", section, key),
+    }
+}
+
+fn parse_u32(section: &str, key: &str, item: &Item) -> Result<u32> {
+    u32::try_from(parse_u64(section, key, item)?)
+        .with_context(|| format!("[{}] {} is out of range", section, key))
+}
+
+fn parse_bool(section: &str, key: &str, item: &Item) -> Result<bool> {
+    match item.as_bool() {
+        Some(val) => Ok(val),
+        None => bail!("[{}] {} must be a boolean", section, key),
+    }
+}
+
+fn parse_f64(section: &str, key: &str, item: &Item) -> Result<f64> {
+    match (item.as_float(), item.as_integer()) {
+        (Some(val), _) => Ok(val),
+        (None, Some(val)) => Ok(val as f64),
+        _ => bail!("[{}] {} must be a number", section, key),
+    }
+}
+
+fn parse_string(section: &str, key: &str, item: &Item) -> Result<String> {
+    match item.as_str() {
+        Some(val) => Ok(val.to_string()),
+        None => bail!("[{}] {} must be a string", section, key),
+    }
+}
+
+impl ScxConfig {
+    /// Parse the TOML configuration file at @path.
+    pub fn from_toml_file(path: &Path) -> Result<ScxConfig> {
+        let toml = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {:?}", path))?;
+        Self::from_toml_str(&toml).with_context(|| format!("Invalid config file {:?}", path))
+    }
+
+    /// Parse a TOML configuration. See from_toml_file().
+    pub fn from_toml_str(toml: &str) -> Result<ScxConfig> {
+        let doc = toml.parse::<Document>()?;
+        let mut knobs = ScxOpts::default();
+
+        for (section, item) in doc.iter() {
+            let table = match item.as_table() {
+                Some(table) => table,
+                None => bail!("{} must be a section", section),
+            };
+            for (key, item) in table.iter() {
+                match (section, key) {
+                    ("timing", "slice_us") => knobs.slice_us = Some(parse_u64(section, key, item)?),
+                    ("timing", "slice_min_us") => {
+                        knobs.slice_min_us = Some(parse_u64(section, key, item)?)
+                    }
+                    ("topology", "cpumask") => {
+                        knobs.cpumask = Some(parse_string(section, key, item)?)
+                    }
+                    ("topology", "numa_aware") => {
+                        knobs.numa_aware = Some(parse_bool(section, key, item)?)
+                    }
+                    ("power", "powersave") => {
+                        knobs.powersave = Some(parse_bool(section, key, item)?)
+                    }
+                    ("power", "deep_idle_threshold") => {
+                        knobs.deep_idle_threshold = Some(parse_f64(section, key, item)?)
+                    }
+                    ("debug", "verbose") => knobs.verbose = Some(parse_u32(section, key, item)?),
+                    ("debug", "exit_dump_len") => {
+                        knobs.exit_dump_len = Some(parse_u32(section, key, item)?)
+                    }
+                    _ => bail!("Unknown config knob [{}] {}", section, key),
+                }
+            }
+        }
+
+        Ok(ScxConfig { knobs })
+    }
+
+    /// Create a ScxConfig with the knobs given in @opts overriding the ones
+    /// from the current ScxConfig.
+    pub fn merge_with_cli(&self, opts: &ScxOpts) -> ScxConfig {
+        let file = &self.knobs;
+        ScxConfig {
+            knobs: ScxOpts {
+                slice_us: opts.slice_us.or(file.slice_us),
+                slice_min_us: opts.slice_min_us.or(file.slice_min_us),
+                cpumask: opts.cpumask.clone().or_else(|| file.cpumask.clone()),
+                numa_aware: opts.numa_aware.or(file.numa_aware),
+                powersave: opts.powersave.or(file.powersave),
+                deep_idle_threshold: opts.deep_idle_threshold.or(file.deep_idle_threshold),
+                verbose: opts.verbose.or(file.verbose),
+                exit_dump_len: opts.exit_dump_len.or(file.exit_dump_len),
+            },
+        }
+    }
+
+    /// Get all knobs.
+    pub fn opts(&self) -> &ScxOpts {
+        &self.knobs
+    }
+
+    /// Get the [timing] slice_us knob.
+    pub fn slice_us(&self) -> Option<u64> {
+        self.knobs.slice_us
+    }
+
+    /// Get the [timing] slice_min_us knob.
+    pub fn slice_min_us(&self) -> Option<u64> {
+        self.knobs.slice_min_us
+    }
+
+    /// Get the configured CPUs. A value starting with "0x" is parsed as a
+    /// hex mask, anything else as a CPU list.
+    pub fn cpumask(&self) -> Result<Option<Cpumask>> {
+        let val = match &self.knobs.cpumask {
+            Some(val) => val.trim(),
+            None => return Ok(None),
+        };
+        let mask = if val.starts_with("0x") {
+            Cpumask::from_str(val)
+        } else {
+            Cpumask::from_cpu_list(val)
+        };
+        mask.map(Some)
+            .with_context(|| format!("Invalid [topology] cpumask {:?}", val))
+    }
+
+    /// Get the [topology] numa_aware knob.
+    pub fn numa_aware(&self) -> Option<bool> {
+        self.knobs.numa_aware
+    }
+
+    /// Get the [power] powersave knob.
+    pub fn powersave(&self) -> Option<bool> {
+        self.knobs.powersave
+    }
+
+    /// Get the [power] deep_idle_threshold knob.
+    pub fn deep_idle_threshold(&self) -> Option<f64> {
+        self.knobs.deep_idle_threshold
+    }
+
+    /// Get the [debug] verbose knob.
+    pub fn verbose(&self) -> Option<u32> {
+        self.knobs.verbose
+    }
+
+    /// Get the [debug] exit_dump_len knob.
+    pub fn exit_dump_len(&self) -> Option<u32> {
+        self.knobs.exit_dump_len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScxConfig;
+    use super::ScxOpts;
+
+    #[test]
+    fn test_config_parse_and_merge() {
+        let config = ScxConfig::from_toml_str(
+            r#"
+            [timing]
+            slice_us = 5000
+
+            [power]
+            deep_idle_threshold = 1
+
+            [debug]
+            verbose = 2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.slice_us(), Some(5000));
+        assert_eq!(config.deep_idle_threshold(), Some(1.0));
+        assert_eq!(config.powersave(), None);
+
+        let cli = ScxOpts {
+            slice_us: Some(1000),
+            powersave: Some(true),
+            ..Default::default()
+        };
+        let merged = config.merge_with_cli(&cli);
+        assert_eq!(merged.slice_us(), Some(1000));
+        assert_eq!(merged.powersave(), Some(true));
+        assert_eq!(merged.verbose(), Some(2));
+    }
+
+    #[test]
+    fn test_config_invalid() {
+        assert!(ScxConfig::from_toml_str("[timing]\nslice = 1").is_err());
+        assert!(ScxConfig::from_toml_str("[timing]\nslice_us = -1").is_err());
+        assert!(ScxConfig::from_toml_str("[debug]\nverbose = true").is_err());
+        assert!(ScxConfig::from_toml_str("verbose = 1").is_err());
+    }
+}
@@ -0,0 +1,212 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX CPU Stats
+//!
+//! A crate that allows schedulers to get the utilization, frequency and idle
+//! state of a CPU in one go, instead of reading /proc/stat, cpufreq and
+//! cpuidle separately.
+//!
+//! ScxCpuStats
+//! -----------
+//!
+//! ScxCpuStats::snapshot() reads the state of a CPU from its sources one
+//! right after the other. As the kernel updates them independently, the
+//! snapshot is only consistent on a best-effort basis:
+//!
+//!```
+//!     let prev = ScxCpuStats::snapshot(cpu)?;
+//!     std::thread::sleep(Duration::from_secs(1));
+//!     let cur = ScxCpuStats::snapshot(cpu)?;
+//!     info!(
+//!         "CPU {}: {:.1}% busy, {:?}MHz, {:.1}% capacity used",
+//!         cpu,
+//!         cur.utilization_pct_since(&prev),
+//!         cur.frequency_mhz(),
+//!         cur.effective_capacity()
+//!     );
+//!```
+//!
+//! The frequency and idle state are optional, as they are only available if
+//! a cpufreq or cpuidle driver is loaded, e.g. usually not in VMs.
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::path::Path;
+
+fn read_file_u64(path: &Path) -> Option<u64> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// An idle state of a CPU as reported by the cpuidle driver in
+/// /sys/devices/system/cpu/cpuN/cpuidle/stateM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdleState {
+    /// The index M of the state, higher indexes are deeper states
+    pub index: usize,
+    /// The name of the state, e.g. "C6"
+    pub name: String,
+    /// How long the CPU spent in the state in usecs since boot
+    pub residency_us: u64,
+}
+
+impl IdleState {
+    // Find the deepest idle state @cpu has entered since boot.
+    fn deepest_entered(cpu: usize) -> Option<IdleState> {
+        let cpuidle_path = format!("/sys/devices/system/cpu/cpu{}/cpuidle", cpu);
+        std::fs::read_dir(cpuidle_path)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let index = name.strip_prefix("state")?.parse::<usize>().ok()?;
+                let path = entry.path();
+                if read_file_u64(&path.join("usage"))? == 0 {
+                    return None;
+                }
+                Some(IdleState {
+                    index,
+                    name: std::fs::read_to_string(path.join("name"))
+                        .ok()?
+                        .trim()
+                        .to_string(),
+                    residency_us: read_file_u64(&path.join("time"))?,
+                })
+            })
+            .max_by_key(|state| state.index)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScxCpuStats {
+    pub cpu: usize,
+    /// The time the CPU was busy in USER_HZ ticks since boot
+    pub busy_ticks: u64,
+    /// The total time of the CPU in USER_HZ ticks since boot
+    pub total_ticks: u64,
+    /// The current frequency if a cpufreq driver is loaded
+    pub cur_khz: Option<u64>,
+    /// The maximum frequency if a cpufreq driver is loaded
+    pub max_khz: Option<u64>,
+    /// The deepest idle state the CPU has entered, if a cpuidle driver is
+    /// loaded
+    pub idle_depth: Option<IdleState>,
+}
+
+impl ScxCpuStats {
+    // Parse the (busy, total) ticks of @cpu from the contents of /proc/stat.
+    fn parse_proc_stat(stat: &str, cpu: usize) -> Result<(u64, u64)> {
+        let label = format!("cpu{}", cpu);
+        let line = match stat
+            .lines()
+            .find(|line| line.split_whitespace().next() == Some(&label))
+        {
+            Some(line) => line,
+            None => bail!("No {} in /proc/stat, is it offline?", label),
+        };
+
+        // user nice system idle iowait irq softirq steal, guest times are
+        // already included in user and nice.
+        let ticks = line
+            .split_whitespace()
+            .skip(1)
+            .take(8)
+            .map(|val| val.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Failed to parse /proc/stat line {:?}", line))?;
+        if ticks.len() < 5 {
+            bail!("Too few fields in /proc/stat line {:?}", line);
+        }
+
+        let total: u64 = ticks.iter().sum();
+        let idle = ticks[3] + ticks[4];
+        Ok((total - idle, total))
+    }
+
+    /// Take a snapshot of the utilization, frequency and idle state of
+    /// @cpu.
+    pub fn snapshot(cpu: usize) -> Result<ScxCpuStats> {
+        let stat = std::fs::read_to_string("/proc/stat").context("Failed to read /proc/stat")?;
+        let (busy_ticks, total_ticks) = Self::parse_proc_stat(&stat, cpu)?;
+
+        let cpufreq = Path::new("/sys/devices/system/cpu")
+            .join(format!("cpu{}", cpu))
+            .join("cpufreq");
+        Ok(ScxCpuStats {
+            cpu,
+            busy_ticks,
+            total_ticks,
+            cur_khz: read_file_u64(&cpufreq.join("scaling_cur_freq")),
+            max_khz: read_file_u64(&cpufreq.join("cpuinfo_max_freq")),
+            idle_depth: IdleState::deepest_entered(cpu),
+        })
+    }
+
+    /// Get the percentage of time the CPU was busy since boot.
+    pub fn utilization_pct(&self) -> f64 {
+        match self.total_ticks {
+            0 => 0.0,
+            total => self.busy_ticks as f64 * 100.0 / total as f64,
+        }
+    }
+
+    /// Get the percentage of time the CPU was busy since the snapshot
+    /// @prev of the same CPU.
+    pub fn utilization_pct_since(&self, prev: &ScxCpuStats) -> f64 {
+        let busy = self.busy_ticks.saturating_sub(prev.busy_ticks);
+        match self.total_ticks.saturating_sub(prev.total_ticks) {
+            0 => 0.0,
+            total => (busy as f64 * 100.0 / total as f64).min(100.0),
+        }
+    }
+
+    /// Get the current frequency in MHz.
+    pub fn frequency_mhz(&self) -> Option<u64> {
+        self.cur_khz.map(|khz| khz / 1000)
+    }
+
+    /// Get the utilization scaled by the current frequency relative to the
+    /// maximum frequency, i.e. the percentage of the maximum capacity of the
+    /// CPU which was used. Without cpufreq, the CPU is assumed to run at its
+    /// maximum frequency.
+    pub fn effective_capacity(&self) -> f64 {
+        let util = self.utilization_pct();
+        match (self.cur_khz, self.max_khz) {
+            (Some(cur), Some(max)) if max > 0 => util * cur as f64 / max as f64,
+            _ => util,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScxCpuStats;
+
+    #[test]
+    fn test_parse_proc_stat() {
+        let stat = concat!(
+            "cpu  400 0 200 1200 100 0 100 0 0 0\n",
+            "cpu0 300 0 100 500 50 0 50 0 0 0\n",
+            "cpu1 100 0 100 700 50 0 50 0 0 0\n",
+            "intr 12345\n",
+        );
+        assert_eq!(ScxCpuStats::parse_proc_stat(stat, 0).unwrap(), (450, 1000));
+        assert_eq!(ScxCpuStats::parse_proc_stat(stat, 1).unwrap(), (250, 1000));
+        assert!(ScxCpuStats::parse_proc_stat(stat, 2).is_err());
+
+        let cpu = ScxCpuStats {
+            cpu: 0,
+            busy_ticks: 450,
+            total_ticks: 1000,
+            cur_khz: Some(1_500_000),
+            max_khz: Some(3_000_000),
+            idle_depth: None,
+        };
+        assert_eq!(cpu.utilization_pct(), 45.0);
+        assert_eq!(cpu.frequency_mhz(), Some(1500));
+        assert_eq!(cpu.effective_capacity(), 22.5);
+    }
+}
@@ -0,0 +1,181 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Cpufreq Governor
+//!
+//! A crate that allows energy efficient schedulers to switch CPUs between
+//! the performance and powersave cpufreq governors depending on load.
+//!
+//! ScxCpufreqGovernor
+//! ------------------
+//!
+//! A ScxCpufreqGovernor tracks the governor of each cpufreq policy on the
+//! host. CPUs can be promoted to the performance governor and demoted to the
+//! powersave governor, and the original governors are restored when the
+//! ScxCpufreqGovernor is dropped:
+//!
+//!```
+//!     let mut governor = ScxCpufreqGovernor::new()?;
+//!
+//!     loop {
+//!         let (busy, idle) = classify_cpus();
+//!         governor.promote(&busy)?;
+//!         governor.demote(&idle)?;
+//!         std::thread::sleep(Duration::from_secs(1));
+//!     }
+//!```
+//!
+//! Cpufreq policies may be shared by several CPUs, in which case switching
+//! the governor of one CPU switches it for all CPUs of the policy. To keep
+//! switching cheap, only the policies whose governor actually changes are
+//! written to, and each only once.
+
+use crate::Cpumask;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use glob::glob;
+use std::path::Path;
+use std::path::PathBuf;
+
+const PERFORMANCE: &str = "performance";
+const POWERSAVE: &str = "powersave";
+
+#[derive(Debug)]
+struct Policy {
+    path: PathBuf,
+    cpus: Cpumask,
+    original: String,
+    current: String,
+}
+
+impl Policy {
+    fn set_governor(&mut self, governor: &str) -> Result<()> {
+        let path = self.path.join("scaling_governor");
+        std::fs::write(&path, governor)
+            .with_context(|| format!("Failed to write {} to {:?}", governor, path))?;
+        self.current = governor.to_string();
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct ScxCpufreqGovernor {
+    policies: Vec<Policy>,
+}
+
+fn read_file_string(path: &Path) -> Result<String> {
+    Ok(std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {:?}", path))?
+        .trim()
+        .to_string())
+}
+
+impl ScxCpufreqGovernor {
+    /// Create a ScxCpufreqGovernor for all cpufreq policies on the host.
+    /// Returns an error if there are no policies, e.g. because no cpufreq
+    /// driver is loaded, or if a policy doesn't support both the performance
+    /// and powersave governors.
+    pub fn new() -> Result<ScxCpufreqGovernor> {
+        let mut policies = vec![];
+        for path in glob("/sys/devices/system/cpu/cpufreq/policy[0-9]*")?.filter_map(Result::ok) {
+            let available = read_file_string(&path.join("scaling_available_governors"))?;
+            for governor in [PERFORMANCE, POWERSAVE] {
+                if !available.split_whitespace().any(|g| g == governor) {
+                    bail!(
+                        "Governor {} not available for {:?}, available: {}",
+                        governor,
+                        path,
+                        available
+                    );
+                }
+            }
+
+            let cpus = read_file_string(&path.join("affected_cpus"))?;
+            let cpus =
+                Cpumask::from_cpu_list(&cpus.split_whitespace().collect::<Vec<_>>().join(","))?;
+            let original = read_file_string(&path.join("scaling_governor"))?;
+            policies.push(Policy {
+                path,
+                cpus,
+                current: original.clone(),
+                original,
+            });
+        }
+
+        if policies.is_empty() {
+            bail!("No cpufreq policies found, is a cpufreq driver loaded?");
+        }
+
+        Ok(ScxCpufreqGovernor { policies })
+    }
+
+    fn switch(&mut self, mask: &Cpumask, governor: &str) -> Result<()> {
+        for policy in self.policies.iter_mut() {
+            if policy.current == governor {
+                continue;
+            }
+            if policy
+                .cpus
+                .clone()
+                .into_iter()
+                .any(|cpu| mask.test_cpu(cpu))
+            {
+                policy.set_governor(governor)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn mask_of(&self, governor: &str) -> Result<Cpumask> {
+        let mut mask = Cpumask::new()?;
+        for policy in self.policies.iter().filter(|p| p.current == governor) {
+            mask.or_in_place(&policy.cpus)?;
+        }
+        Ok(mask)
+    }
+
+    /// Switch the CPUs in @mask to the performance governor, along with the
+    /// other CPUs sharing their cpufreq policies.
+    pub fn promote(&mut self, mask: &Cpumask) -> Result<()> {
+        self.switch(mask, PERFORMANCE)
+    }
+
+    /// Switch the CPUs in @mask to the powersave governor, along with the
+    /// other CPUs sharing their cpufreq policies.
+    pub fn demote(&mut self, mask: &Cpumask) -> Result<()> {
+        self.switch(mask, POWERSAVE)
+    }
+
+    /// Get the CPUs which are using the performance governor.
+    pub fn performance_mask(&self) -> Result<Cpumask> {
+        self.mask_of(PERFORMANCE)
+    }
+
+    /// Get the CPUs which are using the powersave governor.
+    pub fn powersave_mask(&self) -> Result<Cpumask> {
+        self.mask_of(POWERSAVE)
+    }
+
+    /// Restore the governors the policies had when the ScxCpufreqGovernor
+    /// was created. This is done automatically on drop.
+    pub fn restore(&mut self) -> Result<()> {
+        for policy in self.policies.iter_mut() {
+            if policy.current != policy.original {
+                let original = policy.original.clone();
+                policy.set_governor(&original)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ScxCpufreqGovernor {
+    fn drop(&mut self) {
+        if let Err(e) = self.restore() {
+            log::warn!("Failed to restore cpufreq governors: {:#}", e);
+        }
+    }
+}
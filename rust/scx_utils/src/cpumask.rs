@@ -38,14 +38,79 @@
 //!     info!("{}", mask); // 32:<11111111111111111111111111111111>
 //!     assert!(mask.test_cpu(0));
 //!```
+//!
+//! CpumaskVec
+//! ----------
+//!
+//! A CpumaskVec stores many masks of the same number of CPUs packed
+//! end-to-end in a single Vec<u64>, e.g. for large numbers of domains. Masks
+//! are accessed in place through lightweight CpumaskRef and CpumaskMut
+//! views:
+//!
+//!```
+//!     let mut doms = CpumaskVec::new(nr_cpus);
+//!     doms.push(&dom0_mask)?;
+//!     doms.get_mut(0).unwrap().set_cpu(1)?;
+//!     assert!(doms.get(0).unwrap().test_cpu(1));
+//!```
+//!
+//! SharedCpumask
+//! -------------
+//!
+//! A SharedCpumask shares a Cpumask between threads, e.g. a stats thread
+//! which recomputes the mask of idle CPUs and a dispatch thread which uses
+//! it. Clones of a SharedCpumask refer to the same mask:
+//!
+//!```
+//!     let idle = SharedCpumask::new(Cpumask::new()?);
+//!     let stats_idle = idle.clone();
+//!     std::thread::spawn(move || loop {
+//!         stats_idle.update_if_changed(compute_idle_mask());
+//!     });
+//!
+//!     let cpus = idle.snapshot();
+//!```
+//!
+//! All accesses go through a RwLock, which orders them: a snapshot() taken
+//! after an update() returned observes the whole new mask, never a mix of
+//! the old and the new one. Readers only contend with writers, so skipping
+//! updates which don't change the mask with update_if_changed() keeps
+//! snapshot() cheap when the mask rarely changes.
 
+use crate::DomainSet;
+use crate::LocalDomainId;
+use crate::ScxError;
+use crate::Topology;
 use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 use bitvec::prelude::*;
 use std::fmt;
+use std::io::Read;
+use std::io::Write;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::RwLock;
 
-#[derive(Debug, Clone)]
+/// Policies for selecting a subset of the CPUs in a Cpumask. See
+/// Cpumask::select_n_with_policy().
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuSelectPolicy {
+    /// Select the N lowest set CPUs.
+    FirstN,
+    /// Select the N highest set CPUs.
+    LastN,
+    /// Select N set CPUs spread as far apart from each other as possible.
+    EvenlySpaced,
+    /// Select every other set CPU starting from the lowest one, wrapping
+    /// around to the skipped CPUs if N exceeds half of the set CPUs.
+    Alternating,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Cpumask {
     mask: BitVec<u64, Lsb0>,
     nr_cpus: usize,
@@ -75,9 +140,17 @@ impl Cpumask {
     }
 
     /// Build a Cpumask object from a hexadecimal string.
-    pub fn from_str(cpumask: &String) -> Result<Cpumask> {
-        let nr_cpus = Cpumask::get_cpus_possible();
+    ///
+    /// The string is interpreted as a single big-endian number, as in the
+    /// cpumasks shown by the kernel in procfs and sysfs: the rightmost hex
+    /// digit holds CPUs 0-3, e.g. "0x1" is CPU 0 and "0x100" is CPU 8. To
+    /// parse a hex dump of the raw bytes of a mask, e.g. as read from a BPF
+    /// map, use from_hex_le() instead.
+    pub fn from_str(cpumask: &str) -> Result<Cpumask> {
+        Cpumask::from_hex_str(cpumask, Cpumask::get_cpus_possible())
+    }
 
+    fn from_hex_str(cpumask: &str, nr_cpus: usize) -> Result<Cpumask> {
         let hex_str = {
             let mut tmp_str = cpumask
                 .strip_prefix("0x")
@@ -98,7 +171,7 @@ impl Cpumask {
                 let lsb = v.trailing_zeros() as usize;
                 v &= !(1 << lsb);
                 let cpu = index * 8 + lsb;
-                if cpu > nr_cpus {
+                if cpu >= nr_cpus {
                     bail!(
                         concat!(
                             "Found cpu ({}) in cpumask ({}) which is larger",
@@ -119,11 +192,555 @@ impl Cpumask {
         })
     }
 
+    /// Build a Cpumask object from a CPU list string as used by e.g.
+    /// taskset(1) and sysfs, such as "0-7,16-23".
+    pub fn from_cpu_list(cpulist: &str) -> Result<Cpumask> {
+        let mut mask = Cpumask::new()?;
+
+        for group in cpulist.trim().split(',').map(|g| g.trim()) {
+            if group.is_empty() {
+                continue;
+            }
+
+            let (first, last) = match group.split_once('-') {
+                Some((first, last)) => (first.trim(), last.trim()),
+                None => (group, group),
+            };
+            let first: usize = first
+                .parse()
+                .with_context(|| format!("Failed to parse cpu list: {}", cpulist))?;
+            let last: usize = last
+                .parse()
+                .with_context(|| format!("Failed to parse cpu list: {}", cpulist))?;
+            if first > last {
+                bail!("Invalid range {} in cpu list: {}", group, cpulist);
+            }
+
+            for cpu in first..=last {
+                mask.set_cpu(cpu)?;
+            }
+        }
+
+        Ok(mask)
+    }
+
+    /// Build a Cpumask object of @nr_cpus CPUs with the CPUs yielded by
+    /// @iter set. Unlike new(), this doesn't query the number of possible
+    /// CPUs of the host, e.g. to model the topology of another host. Returns
+    /// an error if a CPU is >= @nr_cpus.
+    pub fn from_iter_with_capacity(
+        iter: impl IntoIterator<Item = usize>,
+        nr_cpus: usize,
+    ) -> Result<Cpumask> {
+        let mut mask = Cpumask {
+            mask: bitvec![u64, Lsb0; 0; nr_cpus],
+            nr_cpus,
+        };
+        for cpu in iter {
+            mask.set_cpu(cpu)?;
+        }
+        Ok(mask)
+    }
+
+    /// Build a Cpumask object from the cpuset.cpus file of the cgroup at
+    /// @path. On cgroup v2, an empty cpuset.cpus means that the cgroup
+    /// inherits the CPUs of its parent, in which case cpuset.cpus.effective
+    /// is used instead.
+    pub fn from_cgroup_cpuset(path: &Path) -> Result<Cpumask> {
+        let cpus_path = path.join("cpuset.cpus");
+        let mut cpus = std::fs::read_to_string(&cpus_path)
+            .with_context(|| format!("Failed to read {:?}", cpus_path))?;
+
+        if cpus.trim().is_empty() {
+            let effective_path = path.join("cpuset.cpus.effective");
+            if let Ok(effective) = std::fs::read_to_string(&effective_path) {
+                cpus = effective;
+            }
+        }
+
+        Cpumask::from_cpu_list(&cpus).with_context(|| format!("Invalid cpuset in {:?}", path))
+    }
+
+    /// Return the set CPUs in the canonical CPU list format of cpuset.cpus,
+    /// e.g. "0-3,8,10-11", to be written back to a cgroup.
+    pub fn to_cgroup_cpuset_string(&self) -> String {
+        let mut groups: Vec<String> = vec![];
+        let mut range: Option<(usize, usize)> = None;
+
+        for cpu in self.mask.iter_ones() {
+            range = match range {
+                Some((first, last)) if cpu == last + 1 => Some((first, cpu)),
+                Some((first, last)) => {
+                    groups.push(Self::format_cpu_range(first, last));
+                    Some((cpu, cpu))
+                }
+                None => Some((cpu, cpu)),
+            };
+        }
+        if let Some((first, last)) = range {
+            groups.push(Self::format_cpu_range(first, last));
+        }
+
+        groups.join(",")
+    }
+
+    fn format_cpu_range(first: usize, last: usize) -> String {
+        if first == last {
+            format!("{}", first)
+        } else {
+            format!("{}-{}", first, last)
+        }
+    }
+
+    /// Build a Cpumask object from the environment variable @var, which may
+    /// either hold a hexadecimal string starting with "0x", or a CPU list.
+    pub fn from_env(var: &str) -> Result<Cpumask> {
+        let val = std::env::var(var)
+            .with_context(|| format!("Failed to read cpumask from environment variable {}", var))?;
+        let val = val.trim();
+
+        if val.starts_with("0x") {
+            Cpumask::from_str(val)
+        } else {
+            Cpumask::from_cpu_list(val)
+        }
+        .with_context(|| format!("Invalid cpumask in environment variable {}", var))
+    }
+
+    /// Build a Cpumask object from a libc::cpu_set_t, e.g. as returned by
+    /// sched_getaffinity(2). CPUs set in @set which exceed the number of
+    /// possible CPUs on the host are ignored, as the kernel never reports
+    /// them.
+    pub fn from_affinity_t(set: &libc::cpu_set_t) -> Cpumask {
+        let nr_cpus = Cpumask::get_cpus_possible();
+        let mut mask = bitvec![u64, Lsb0; 0; nr_cpus];
+        for cpu in 0..nr_cpus.min(libc::CPU_SETSIZE as usize) {
+            if unsafe { libc::CPU_ISSET(cpu, set) } {
+                mask.set(cpu, true);
+            }
+        }
+
+        Cpumask { mask, nr_cpus }
+    }
+
+    /// Return a libc::cpu_set_t whose bits reflect the Cpumask, e.g. for use
+    /// with sched_setaffinity(2). As cpu_set_t is a fixed size bitmap,
+    /// returns an error if the Cpumask is larger than CPU_SETSIZE.
+    pub fn as_affinity_t(&self) -> Result<libc::cpu_set_t> {
+        if self.nr_cpus > libc::CPU_SETSIZE as usize {
+            bail!(
+                "Cpumask of {} CPUs does not fit in cpu_set_t of {} CPUs",
+                self.nr_cpus,
+                libc::CPU_SETSIZE
+            );
+        }
+
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        for cpu in self.mask.iter_ones() {
+            unsafe { libc::CPU_SET(cpu, &mut set) };
+        }
+
+        Ok(set)
+    }
+
+    /// Set the CPU affinity of @pid, or of the calling thread if @pid is 0,
+    /// to the Cpumask using sched_setaffinity(2). Unlike as_affinity_t(),
+    /// this isn't limited to CPU_SETSIZE CPUs. If the caller lacks the
+    /// permission to change the affinity of @pid, the returned error can be
+    /// downcast to ScxError::PermissionDenied.
+    pub fn apply_affinity(&self, pid: i32) -> Result<()> {
+        let mut words = vec![0u64; self.nr_cpus.div_ceil(64).max(1)];
+        for cpu in self.mask.iter_ones() {
+            words[cpu / 64] |= 1 << (cpu % 64);
+        }
+
+        let ret = unsafe {
+            libc::sched_setaffinity(
+                pid,
+                words.len() * 8,
+                words.as_ptr() as *const libc::cpu_set_t,
+            )
+        };
+        if ret < 0 {
+            return Err(affinity_error(
+                pid,
+                std::io::Error::last_os_error(),
+                format!("Failed to set CPU affinity of pid {} to {}", pid, self),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Get the CPU affinity of @pid, or of the calling thread if @pid is 0,
+    /// using sched_getaffinity(2). If the caller lacks the permission to
+    /// query the affinity of @pid, the returned error can be downcast to
+    /// ScxError::PermissionDenied.
+    pub fn current_affinity(pid: i32) -> Result<Cpumask> {
+        let mut mask = Cpumask::new()?;
+
+        // The kernel requires the buffer to cover all of nr_cpu_ids, which
+        // may exceed CPU_SETSIZE.
+        let mut words = vec![0u64; mask.nr_cpus.div_ceil(64).max(1)];
+        let ret = unsafe {
+            libc::sched_getaffinity(
+                pid,
+                words.len() * 8,
+                words.as_mut_ptr() as *mut libc::cpu_set_t,
+            )
+        };
+        if ret < 0 {
+            return Err(affinity_error(
+                pid,
+                std::io::Error::last_os_error(),
+                format!("Failed to get CPU affinity of pid {}", pid),
+            ));
+        }
+
+        for (i, word) in words.iter().enumerate() {
+            let mut w = *word;
+            while w != 0 {
+                let bit = w.trailing_zeros() as usize;
+                w &= w - 1;
+                mask.set_cpu(i * 64 + bit)?;
+            }
+        }
+
+        Ok(mask)
+    }
+
+    /// Get the CPU affinity of @pid from the Cpus_allowed field of
+    /// /proc/[pid]/status, which doesn't require the permission to query
+    /// the affinity with sched_getaffinity(2). Returns an error if the
+    /// process doesn't exist or the field is missing.
+    pub fn from_proc_status_cpus_allowed(pid: i32) -> Result<Cpumask> {
+        let path = format!("/proc/{}/status", pid);
+        let status =
+            std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))?;
+
+        let val = match status
+            .lines()
+            .find_map(|line| line.strip_prefix("Cpus_allowed:"))
+        {
+            Some(val) => val.trim(),
+            None => bail!("No Cpus_allowed in {}", path),
+        };
+        // The kernel separates the mask into comma-separated 32-bit words,
+        // e.g. "ff,ffffffff".
+        Cpumask::from_str(&val.replace(',', ""))
+            .with_context(|| format!("Failed to parse Cpus_allowed in {}", path))
+    }
+
+    /// Return a JSON array of the indices of the set CPUs, e.g. [0, 2, 4, 6].
+    ///
+    /// Note that this is lossy as the array doesn't encode the number of CPUs
+    /// of the Cpumask. It should be paired with an explicit nr_cpus in the
+    /// enclosing JSON object, to be passed back to from_json_array().
+    #[cfg(feature = "serde_json")]
+    pub fn to_json_array(&self) -> serde_json::Value {
+        serde_json::Value::Array(self.mask.iter_ones().map(serde_json::Value::from).collect())
+    }
+
+    /// Build a Cpumask object of @nr_cpus CPUs from a JSON array of CPU
+    /// indices as created by to_json_array(). Returns an error if @val isn't
+    /// an array of integers, or contains a CPU index >= @nr_cpus.
+    #[cfg(feature = "serde_json")]
+    pub fn from_json_array(val: &serde_json::Value, nr_cpus: usize) -> Result<Cpumask> {
+        let cpus = match val.as_array() {
+            Some(cpus) => cpus,
+            None => bail!("Expected a JSON array of CPUs, found {}", val),
+        };
+
+        let mut mask = Cpumask {
+            mask: bitvec![u64, Lsb0; 0; nr_cpus],
+            nr_cpus,
+        };
+        for cpu in cpus.iter() {
+            match cpu.as_u64() {
+                Some(cpu) => mask.set_cpu(cpu as usize)?,
+                None => bail!("Invalid CPU {} in JSON array {}", cpu, val),
+            }
+        }
+
+        Ok(mask)
+    }
+
+    /// Write the Cpumask to @w in a compact binary format: the number of
+    /// CPUs as a u32, followed by the raw u64 words of the mask, all in host
+    /// byte order. The format is meant for IPC between scheduler components
+    /// on the same host. See deserialize_from_reader() for the inverse.
+    pub fn serialize_to_writer<W: Write>(&self, mut w: W) -> Result<()> {
+        let nr_cpus = u32::try_from(self.nr_cpus)
+            .with_context(|| format!("Cpumask of {} CPUs is too large", self.nr_cpus))?;
+        w.write_all(&nr_cpus.to_ne_bytes())?;
+
+        // Bits past nr_cpus in the last word aren't guaranteed to be clear.
+        for (i, word) in self.mask.as_raw_slice().iter().enumerate() {
+            let nr_bits = (self.nr_cpus - i * 64).min(64);
+            let word = match nr_bits {
+                64 => *word,
+                _ => *word & ((1u64 << nr_bits) - 1),
+            };
+            w.write_all(&word.to_ne_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Read a Cpumask written by serialize_to_writer() from @r. Returns an
+    /// error if a CPU beyond the number of CPUs of the mask is set.
+    pub fn deserialize_from_reader<R: Read>(mut r: R) -> Result<Cpumask> {
+        let mut header = [0u8; 4];
+        r.read_exact(&mut header).context("Failed to read cpumask header")?;
+        let nr_cpus = u32::from_ne_bytes(header) as usize;
+
+        let mut words = vec![0u64; nr_cpus.div_ceil(64)];
+        for word in words.iter_mut() {
+            let mut bytes = [0u8; 8];
+            r.read_exact(&mut bytes).context("Failed to read cpumask words")?;
+            *word = u64::from_ne_bytes(bytes);
+        }
+
+        if let (Some(last), nr_bits @ 1..) = (words.last(), nr_cpus % 64) {
+            if last >> nr_bits != 0 {
+                bail!("Cpumask of {} CPUs has CPUs set beyond the last", nr_cpus);
+            }
+        }
+
+        let mut mask = BitVec::<u64, Lsb0>::from_vec(words);
+        mask.truncate(nr_cpus);
+        Ok(Cpumask { mask, nr_cpus })
+    }
+
+    /// Return the Cpumask as a hexadecimal string prefixed with "0x", as
+    /// accepted by from_str(), e.g. "0xff00ff00".
+    pub fn to_hex_string(&self) -> String {
+        let mut hex = String::new();
+        for nibble in (0..self.nr_cpus.div_ceil(4)).rev() {
+            let mut val = 0;
+            for bit in 0..4 {
+                let cpu = nibble * 4 + bit;
+                if cpu < self.nr_cpus && self.mask[cpu] {
+                    val |= 1 << bit;
+                }
+            }
+            hex.push(std::char::from_digit(val, 16).unwrap());
+        }
+        if hex.is_empty() {
+            hex.push('0');
+        }
+        format!("0x{}", hex)
+    }
+
+    /// Build a Cpumask object from a hex dump of the raw bytes of a mask in
+    /// little-endian order, such as the u64 words of a mask stored in a BPF
+    /// map on a little-endian host.
+    ///
+    /// Unlike from_str(), the string is interpreted as a sequence of bytes
+    /// of two hex digits each, lowest byte first: the first byte holds CPUs
+    /// 0-7, the second CPUs 8-15 and so on, e.g. "01" is CPU 0 and "0001" is
+    /// CPU 8. Within a byte, the digits are in the usual order, i.e. "10" is
+    /// CPU 4. Returns an error if the string has an odd number of digits.
+    pub fn from_hex_le(s: &str) -> Result<Cpumask> {
+        let hex_str = s.strip_prefix("0x").unwrap_or(s).replace('_', "");
+        let byte_vec =
+            hex::decode(&hex_str).with_context(|| format!("Failed to parse cpumask: {}", s))?;
+
+        let mut mask = Cpumask::new()?;
+        for (index, &val) in byte_vec.iter().enumerate() {
+            for bit in 0..8 {
+                if val & (1 << bit) != 0 {
+                    mask.set_cpu(index * 8 + bit)
+                        .with_context(|| format!("Failed to parse cpumask: {}", s))?;
+                }
+            }
+        }
+        Ok(mask)
+    }
+
+    /// Format the Cpumask as a hex dump of its raw bytes in little-endian
+    /// order, without a "0x" prefix. See from_hex_le() for the format, which
+    /// this is the inverse of.
+    pub fn to_hex_le(&self) -> String {
+        let mut bytes = vec![0u8; self.nr_cpus.div_ceil(8)];
+        for cpu in self.mask.iter_ones() {
+            bytes[cpu / 8] |= 1 << (cpu % 8);
+        }
+        hex::encode(bytes)
+    }
+
+    /// Write the Cpumask to @fd as a hexadecimal string as returned by
+    /// to_hex_string(), followed by a newline, using a single write(2).
+    pub fn write_to_fd<F: AsRawFd>(&self, fd: F) -> Result<()> {
+        let buf = format!("{}\n", self.to_hex_string());
+        let ret =
+            unsafe { libc::write(fd.as_raw_fd(), buf.as_ptr() as *const libc::c_void, buf.len()) };
+        if ret < 0 {
+            bail!(
+                "Failed to write cpumask to fd {} ({})",
+                fd.as_raw_fd(),
+                std::io::Error::last_os_error()
+            );
+        }
+        if ret as usize != buf.len() {
+            bail!(
+                "Short write of cpumask to fd {} ({}/{} bytes)",
+                fd.as_raw_fd(),
+                ret,
+                buf.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Read a Cpumask of @nr_cpus CPUs written by write_to_fd() from @fd.
+    /// Reads up to the first newline or end of file.
+    pub fn read_from_fd<F: AsRawFd>(fd: F, nr_cpus: usize) -> Result<Cpumask> {
+        let mut buf = vec![];
+        let mut byte = 0u8;
+        loop {
+            let ret =
+                unsafe { libc::read(fd.as_raw_fd(), &mut byte as *mut u8 as *mut libc::c_void, 1) };
+            if ret < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                bail!("Failed to read cpumask from fd {} ({})", fd.as_raw_fd(), err);
+            }
+            if ret == 0 || byte == b'\n' {
+                break;
+            }
+            buf.push(byte);
+        }
+
+        let hex = String::from_utf8(buf).context("Cpumask is not valid UTF-8")?;
+        Cpumask::from_hex_str(hex.trim(), nr_cpus)
+    }
+
+    /// Return the bytes of the Cpumask laid out as the running kernel's
+    /// cpumask_t, i.e. DECLARE_BITMAP(bits, NR_CPUS), zero-padded or
+    /// truncated to exactly ceil(NR_CPUS / 64) * 8 bytes. This is what e.g. a
+    /// BPF map value or global variable of type cpumask_t expects, whereas
+    /// as_raw_slice() is sized by the number of possible CPUs. NR_CPUS is
+    /// read from /sys/devices/system/cpu/kernel_max.
+    pub fn to_cpumask_t_bytes(&self) -> Result<Box<[u8]>> {
+        let path = "/sys/devices/system/cpu/kernel_max";
+        let kernel_max: usize = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path))?
+            .trim()
+            .parse()
+            .with_context(|| format!("Failed to parse {}", path))?;
+        let nr_words = (kernel_max + 1).div_ceil(64);
+
+        let mut words = vec![0u64; nr_words];
+        for cpu in self.mask.iter_ones().take_while(|cpu| *cpu <= kernel_max) {
+            words[cpu / 64] |= 1 << (cpu % 64);
+        }
+
+        Ok(words.iter().flat_map(|word| word.to_ne_bytes()).collect())
+    }
+
     /// Return a slice of u64's whose bits reflect the Cpumask.
     pub fn as_raw_slice(&self) -> &[u64] {
         self.mask.as_raw_slice()
     }
 
+    /// Return the raw words of the Cpumask as u32's, e.g. for BPF ABIs which
+    /// declare cpumasks as u32 arrays. The slice reinterprets the u64 words
+    /// in place, so it has twice as many words as as_raw_slice(). On
+    /// little-endian hosts, CPU N is bit N % 32 of word N / 32, which is the
+    /// layout of a u32 bitmap. On big-endian hosts, the two halves of each
+    /// u64 word are swapped, i.e. CPU N is in word (N / 32) ^ 1.
+    pub fn words_as_u32_slice(&self) -> &[u32] {
+        // A u64 slice is always sufficiently aligned for u32's, and
+        // cast_slice() checks it regardless.
+        bytemuck::cast_slice(self.mask.as_raw_slice())
+    }
+
+    /// Return the raw words of the Cpumask as mutable u32's. See
+    /// words_as_u32_slice() for the layout. Bits beyond the number of CPUs
+    /// of the Cpumask which are set through the returned CpumaskWordsMut are
+    /// cleared again when it is dropped.
+    pub fn words_as_u32_slice_mut(&mut self) -> CpumaskWordsMut<'_> {
+        CpumaskWordsMut { mask: self }
+    }
+
+    /// Copy the raw words of the Cpumask into an array of @N u64's, e.g. for
+    /// a BPF map value of a fixed size, padding it with zeroes. Returns an
+    /// error if the Cpumask needs more than @N words.
+    pub fn as_u64_array<const N: usize>(&self) -> Result<[u64; N]> {
+        let nr_words = self.nr_cpus.div_ceil(64);
+        if nr_words > N {
+            bail!(
+                "Cpumask of {} CPUs doesn't fit in {} u64 words",
+                self.nr_cpus,
+                N
+            );
+        }
+
+        let mut array = [0u64; N];
+        self.for_each_word(false, |i, word| array[i] = word);
+        Ok(array)
+    }
+
+    /// Copy the Cpumask into an array of @BITS bytes, e.g. for a BPF map
+    /// value of type __u8 cpumask[NR_CPU_IDS / 8], padding it with zeroes.
+    /// CPU N is bit N % 8 of byte N / 8, regardless of the host byte order.
+    /// Note that @BITS is the number of bytes of the array. Returns an error
+    /// if the Cpumask has more than @BITS * 8 CPUs.
+    pub fn cpu_bits<const BITS: usize>(&self) -> Result<[u8; BITS]> {
+        if self.nr_cpus > BITS * 8 {
+            bail!(
+                "Cpumask of {} CPUs doesn't fit in {} bytes",
+                self.nr_cpus,
+                BITS
+            );
+        }
+
+        let mut array = [0u8; BITS];
+        self.for_each_word(false, |i, word| {
+            for (j, byte) in word.to_le_bytes().iter().enumerate() {
+                if let Some(dst) = array.get_mut(i * 8 + j) {
+                    *dst = *byte;
+                }
+            }
+        });
+        Ok(array)
+    }
+
+    /// Test the bit at @bit_offset of the raw words returned by
+    /// as_raw_slice(), where bit offset N is bit N % 64 of word N / 64. As
+    /// the Cpumask uses an Lsb0 layout, the bit offset of a CPU is the same
+    /// as its index. Returns false for offsets beyond the raw words.
+    pub fn bit_at_offset(&self, bit_offset: usize) -> bool {
+        match self.mask.as_raw_slice().get(bit_offset / 64) {
+            Some(word) => word & (1 << (bit_offset % 64)) != 0,
+            None => false,
+        }
+    }
+
+    /// Set the bit at @bit_offset of the raw words to @val. See
+    /// bit_at_offset(). Returns an error if the offset doesn't correspond to
+    /// a CPU of the Cpumask, as the padding bits must remain clear.
+    pub fn set_bit_at_offset(&mut self, bit_offset: usize, val: bool) -> Result<()> {
+        if bit_offset >= self.nr_cpus {
+            bail!(
+                "Bit offset {} out of range of a Cpumask of {} CPUs",
+                bit_offset,
+                self.nr_cpus
+            );
+        }
+        let word = &mut self.mask.as_raw_mut_slice()[bit_offset / 64];
+        if val {
+            *word |= 1 << (bit_offset % 64);
+        } else {
+            *word &= !(1 << (bit_offset % 64));
+        }
+        Ok(())
+    }
+
     /// Return the mutable raw BitVec object backing the Cpumask.
     pub fn as_raw_bitvec_mut(&mut self) -> &mut BitVec<u64, Lsb0> {
         &mut self.mask
@@ -134,6 +751,32 @@ impl Cpumask {
         &self.mask
     }
 
+    /// Convert the Cpumask to a BitVec of u32 words, e.g. for 32-bit kernel
+    /// structs. Bit N of the BitVec is CPU N, as in the Cpumask, and the
+    /// BitVec is as long as the number of CPUs of the Cpumask.
+    pub fn to_bitvec_u32(&self) -> BitVec<u32, Lsb0> {
+        let words: Vec<u32> = self
+            .mask
+            .as_raw_slice()
+            .iter()
+            .flat_map(|word| [*word as u32, (*word >> 32) as u32])
+            .collect();
+        let mut bv = BitVec::<u32, Lsb0>::from_vec(words);
+        bv.truncate(self.nr_cpus);
+        bv
+    }
+
+    /// Build a Cpumask from a BitVec of u32 words, where bit N is CPU N. The
+    /// Cpumask has as many CPUs as the BitVec is long. See to_bitvec_u32().
+    pub fn from_bitvec_u32(bv: BitVec<u32, Lsb0>) -> Cpumask {
+        let nr_cpus = bv.len();
+        let mut mask = bitvec![u64, Lsb0; 0; nr_cpus];
+        for cpu in bv.iter_ones() {
+            mask.set(cpu, true);
+        }
+        Cpumask { mask, nr_cpus }
+    }
+
     /// Set all bits in the Cpumask to 1
     pub fn setall(&mut self) {
         self.mask.fill(true);
@@ -183,72 +826,1218 @@ impl Cpumask {
         self.nr_cpus
     }
 
-    /// Create a Cpumask that is the OR of the current Cpumask and another.
-    pub fn or(&self, other: &Cpumask) -> Result<Cpumask> {
-        let mut new = self.clone();
-        new.mask |= other.mask.clone();
-        Ok(new)
+    /// Get the fraction of the CPUs of the Cpumask which are set, between
+    /// 0.0 and 1.0. A Cpumask of 0 CPUs has a density of 0.0.
+    pub fn density(&self) -> f64 {
+        match self.nr_cpus {
+            0 => 0.0,
+            nr_cpus => self.weight() as f64 / nr_cpus as f64,
+        }
     }
 
-    /// Create a Cpumask that is the AND of the current Cpumask and another.
-    pub fn and(&self, other: &Cpumask) -> Result<Cpumask> {
-        let mut new = self.clone();
-        new.mask &= other.mask.clone();
-        Ok(new)
+    /// Get the fraction of the CPUs of the Cpumask which are clear, i.e.
+    /// 1.0 - density().
+    pub fn sparsity(&self) -> f64 {
+        1.0 - self.density()
     }
 
-    /// Create a Cpumask that is the XOR of the current Cpumask and another.
-    pub fn xor(&self, other: &Cpumask) -> Result<Cpumask> {
-        let mut new = self.clone();
-        new.mask ^= other.mask.clone();
-        Ok(new)
+    /// Test whether at least a @threshold fraction of the CPUs of the
+    /// Cpumask are set, e.g. is_dense(0.8).
+    pub fn is_dense(&self, threshold: f64) -> bool {
+        self.density() >= threshold
     }
-}
 
-impl fmt::Display for Cpumask {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}:<{}>", self.nr_cpus, self.mask)
+    /// Return the index of the lowest set CPU in the Cpumask, or None if the
+    /// Cpumask is empty.
+    pub fn first_set_cpu(&self) -> Option<usize> {
+        self.mask.first_one()
     }
-}
 
-pub struct CpumaskIntoIterator {
-    mask: Cpumask,
-    index: usize,
-}
+    /// Return the index of the highest set CPU in the Cpumask, or None if the
+    /// Cpumask is empty.
+    pub fn last_set_cpu(&self) -> Option<usize> {
+        self.mask.last_one()
+    }
 
-/// Iterate over each element of a Cpumask, and return the indices with bits
-/// set.
-///
-/// # Examples
-///
-/// ```
-/// let mask = Cpumask::from_str(cpumask_str)?;
-/// for cpu in mask.clone().into_iter() {
-///     info!("cpu {} was set", cpu);
-/// }
-/// ```
-impl IntoIterator for Cpumask {
-    type Item = usize;
-    type IntoIter = CpumaskIntoIterator;
+    // Get the word at @i of the raw mask with the bits beyond nr_cpus, which
+    // may be set, cleared.
+    fn masked_word(&self, i: usize) -> u64 {
+        let word = self.mask.as_raw_slice().get(i).copied().unwrap_or(0);
+        match self.nr_cpus.saturating_sub(i * 64) {
+            nr_bits if nr_bits >= 64 => word,
+            nr_bits => word & ((1u64 << nr_bits) - 1),
+        }
+    }
 
-    fn into_iter(self) -> CpumaskIntoIterator {
-        CpumaskIntoIterator { mask: self, index: 0 }
+    /// Count the clear CPUs below the lowest set CPU, as u64::trailing_zeros()
+    /// does for bits. This is the index of the lowest set CPU, or the number
+    /// of CPUs of the Cpumask if it is empty.
+    pub fn trailing_zeros(&self) -> usize {
+        for i in 0..self.mask.as_raw_slice().len() {
+            let word = self.masked_word(i);
+            if word != 0 {
+                return i * 64 + word.trailing_zeros() as usize;
+            }
+        }
+        self.nr_cpus
     }
-}
 
-impl Iterator for CpumaskIntoIterator {
-    type Item = usize;
+    /// Count the clear CPUs above the highest set CPU, up to and including
+    /// CPU nr_cpus - 1, as u64::leading_zeros() does for bits. This is the
+    /// number of CPUs of the Cpumask if it is empty.
+    pub fn leading_zeros(&self) -> usize {
+        for i in (0..self.mask.as_raw_slice().len()).rev() {
+            let word = self.masked_word(i);
+            if word != 0 {
+                let last = i * 64 + 63 - word.leading_zeros() as usize;
+                return self.nr_cpus - 1 - last;
+            }
+        }
+        self.nr_cpus
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        while self.index < self.mask.nr_cpus {
-            let index = self.index;
-            self.index += 1;
-            let bit_val = self.mask.test_cpu(index);
-            if bit_val {
-                return Some(index);
+    /// Test whether all set CPUs in the Cpumask form a single unbroken run,
+    /// e.g. CPUs 4-11. An empty Cpumask is considered contiguous.
+    pub fn is_contiguous(&self) -> bool {
+        match (self.first_set_cpu(), self.last_set_cpu()) {
+            (Some(first), Some(last)) => self.weight() == last - first + 1,
+            _ => true,
+        }
+    }
+
+    // Call @f with the start and length of each run of consecutive set CPUs
+    // in ascending order. Words are scanned whole, with the padding bits past
+    // the last CPU masked off and the current run carried over word
+    // boundaries until a clear bit ends it.
+    fn for_each_run<F: FnMut(usize, usize)>(&self, mut f: F) {
+        let mut run: Option<(usize, usize)> = None;
+
+        for i in 0..self.mask.as_raw_slice().len() {
+            let word = self.masked_word(i);
+            let mut pos = 0;
+            while pos < 64 {
+                let rest = word >> pos;
+                if rest == 0 {
+                    break;
+                }
+                pos += rest.trailing_zeros() as usize;
+                let len = (word >> pos).trailing_ones() as usize;
+                let start = i * 64 + pos;
+
+                run = match run {
+                    Some((run_start, run_len)) if run_start + run_len == start => {
+                        Some((run_start, run_len + len))
+                    }
+                    Some((run_start, run_len)) => {
+                        f(run_start, run_len);
+                        Some((start, len))
+                    }
+                    None => Some((start, len)),
+                };
+                pos += len;
             }
         }
 
-        None
+        if let Some((start, len)) = run {
+            f(start, len);
+        }
+    }
+
+    /// Find the longest run of consecutive set CPUs, e.g. to tell a well
+    /// clustered mask from a fragmented one. Returns (start, length) of the
+    /// lowest such run, or (0, 0) if the Cpumask is empty.
+    pub fn longest_set_run(&self) -> (usize, usize) {
+        let mut longest = (0, 0);
+        self.for_each_run(|start, len| {
+            if len > longest.1 {
+                longest = (start, len);
+            }
+        });
+        longest
+    }
+
+    /// Get the (start, length) of all runs of consecutive set CPUs in
+    /// ascending order, i.e. the run-length encoding of the Cpumask.
+    pub fn all_runs(&self) -> Vec<(usize, usize)> {
+        let mut runs = vec![];
+        self.for_each_run(|start, len| runs.push((start, len)));
+        runs
+    }
+
+    /// Count the 0->1 and 1->0 transitions between neighbouring CPUs for each
+    /// word of as_raw_slice(), e.g. to see how fragmented the mask is within
+    /// each NUMA node if a word holds the CPUs of a node. The transition
+    /// between the last CPU of a word and the first CPU of the next one is
+    /// counted for the latter word.
+    pub fn count_transitions_by_word(&self) -> Vec<u32> {
+        let words = self.mask.as_raw_slice();
+        let mut counts = Vec::with_capacity(words.len());
+        let mut prev_msb = None;
+
+        for (i, word) in words.iter().enumerate() {
+            let nr_bits = (self.nr_cpus - i * 64).min(64);
+            // Bit N of diff is set if bits N and N + 1 of the word differ.
+            let valid = if nr_bits == 64 {
+                u64::MAX >> 1
+            } else {
+                (1u64 << (nr_bits - 1)) - 1
+            };
+            let diff = (word ^ (word >> 1)) & valid;
+            let mut count = diff.count_ones();
+
+            if let Some(msb) = prev_msb {
+                if msb != word & 1 {
+                    count += 1;
+                }
+            }
+            prev_msb = Some((word >> 63) & 1);
+            counts.push(count);
+        }
+        counts
+    }
+
+    /// Get the domain of @domains whose CPUs include all CPUs of the Cpumask,
+    /// or None if the Cpumask is empty or isn't within a single domain.
+    pub fn containing_domain(&self, domains: &DomainSet) -> Option<LocalDomainId> {
+        let id = domains.domain_of(self.first_set_cpu()?)?;
+        match domains.cpumask(id) {
+            Some(mask) if mask.contains_all(self) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Test whether all CPUs set in @other are also set in the Cpumask, i.e.
+    /// whether the Cpumask is a superset of @other. By De Morgan's laws,
+    /// (other & self) == other is equivalent to (other & !self) == 0, which
+    /// is tested word by word without allocating. CPUs set in @other beyond
+    /// the number of CPUs of the Cpumask are never contained.
+    pub fn contains_all(&self, other: &Cpumask) -> bool {
+        let words = self.mask.as_raw_slice();
+        let mut contained = true;
+        other.for_each_word(false, |i, w| {
+            let mut mine = words.get(i).copied().unwrap_or(0);
+            if (i + 1) * 64 > self.nr_cpus {
+                mine &= match self.nr_cpus.checked_sub(i * 64) {
+                    Some(nr_bits) => (1u64 << nr_bits) - 1,
+                    None => 0,
+                };
+            }
+            if w & !mine != 0 {
+                contained = false;
+            }
+        });
+        contained
+    }
+
+    /// Count the CPUs set in the Cpumask but not in @other, i.e. the weight
+    /// of self & !other, which is the number of CPUs lost if the Cpumask was
+    /// replaced by @other. Counted word by word without allocating. CPUs
+    /// beyond the number of CPUs of @other count as not set in @other.
+    pub fn difference_count(&self, other: &Cpumask) -> usize {
+        let others = other.mask.as_raw_slice();
+        let mut count = 0;
+        self.for_each_word(false, |i, w| {
+            let theirs = others.get(i).copied().unwrap_or(0);
+            count += (w & !theirs).count_ones() as usize;
+        });
+        count
+    }
+
+    /// Test whether the Cpumask and @other set the same CPUs of @online,
+    /// i.e. whether (self & online) == (other & online), ignoring the CPUs
+    /// which differ only while offline. Compared word by word without
+    /// allocating. CPUs beyond the number of CPUs of either Cpumask count as
+    /// not set in it.
+    pub fn eq_ignoring_offline(&self, other: &Cpumask, online: &Cpumask) -> bool {
+        let mut equal = true;
+        online.for_each_word(false, |i, w| {
+            let diff = self.masked_word(i) ^ other.masked_word(i);
+            if diff & w != 0 {
+                equal = false;
+            }
+        });
+        equal
+    }
+
+    /// Return the index of the Nth (starting from 0) set CPU in the Cpumask,
+    /// or None if fewer than N + 1 CPUs are set. This is equivalent to
+    /// into_iter().nth(n) without consuming the Cpumask, and has the same
+    /// O(nr_cpus) worst case. Callers which index repeatedly into the same
+    /// Cpumask should build a Vec of the set CPUs instead.
+    pub fn nth_set_cpu(&self, n: usize) -> Option<usize> {
+        self.mask.iter_ones().nth(n)
+    }
+
+    /// Call @f with the index of each set CPU, in ascending order. This scans
+    /// the underlying words directly and can be inlined, avoiding the
+    /// overhead of the Iterator interface in hot paths.
+    #[inline]
+    pub fn for_each_set_cpu<F: FnMut(usize)>(&self, mut f: F) {
+        for (i, word) in self.mask.as_raw_slice().iter().enumerate() {
+            let mut w = *word;
+            while w != 0 {
+                let cpu = i * 64 + w.trailing_zeros() as usize;
+                if cpu >= self.nr_cpus {
+                    return;
+                }
+                f(cpu);
+                w &= w - 1;
+            }
+        }
+    }
+
+    /// Call @f with the index and value of each u64 word of the mask, in
+    /// ascending order, e.g. to copy the mask into a BPF map value array.
+    /// Word 0 holds CPUs 0-63 with CPU 0 in the least significant bit. Words
+    /// which are zero are skipped unless @include_zero is set. Bits beyond
+    /// the number of CPUs of the mask are always clear.
+    #[inline]
+    pub fn for_each_word<F: FnMut(usize, u64)>(&self, include_zero: bool, mut f: F) {
+        for (i, word) in self.mask.as_raw_slice().iter().enumerate() {
+            let mut w = *word;
+            if (i + 1) * 64 > self.nr_cpus {
+                if i * 64 >= self.nr_cpus {
+                    return;
+                }
+                w &= (1u64 << (self.nr_cpus - i * 64)) - 1;
+            }
+            if w != 0 || include_zero {
+                f(i, w);
+            }
+        }
+    }
+
+    /// Fold the indices of the set CPUs in ascending order into an
+    /// accumulator, like Iterator::fold(), e.g.
+    /// mask.fold(0u64, |acc, cpu| acc + loads[cpu]).
+    #[inline]
+    pub fn fold<A, F: FnMut(A, usize) -> A>(&self, init: A, mut f: F) -> A {
+        let mut acc = init;
+        for (i, word) in self.mask.as_raw_slice().iter().enumerate() {
+            let mut w = *word;
+            while w != 0 {
+                let cpu = i * 64 + w.trailing_zeros() as usize;
+                if cpu >= self.nr_cpus {
+                    return acc;
+                }
+                acc = f(acc, cpu);
+                w &= w - 1;
+            }
+        }
+        acc
+    }
+
+    /// Call @f with the index of each clear CPU, in ascending order. See
+    /// for_each_set_cpu().
+    #[inline]
+    pub fn for_each_clear_cpu<F: FnMut(usize)>(&self, mut f: F) {
+        for (i, word) in self.mask.as_raw_slice().iter().enumerate() {
+            let mut w = !*word;
+            while w != 0 {
+                let cpu = i * 64 + w.trailing_zeros() as usize;
+                if cpu >= self.nr_cpus {
+                    return;
+                }
+                f(cpu);
+                w &= w - 1;
+            }
+        }
+    }
+
+    /// Count the set CPUs which belong to NUMA node @node of @topology, or 0
+    /// if the node doesn't exist. This works on the underlying words directly
+    /// without creating a temporary Cpumask.
+    pub fn count_set_in_node(&self, node: usize, topology: &Topology) -> usize {
+        match topology.cpus_on_node(node) {
+            Some(node_mask) => self.count_set_in(node_mask),
+            None => 0,
+        }
+    }
+
+    /// Count the set CPUs which belong to the LLC with ID @llc_id of
+    /// @topology, or 0 if the LLC doesn't exist. See count_set_in_node().
+    pub fn count_set_in_llc(&self, llc_id: usize, topology: &Topology) -> usize {
+        match topology.cpus_sharing_llc_by_id(llc_id) {
+            Some(llc_mask) => self.count_set_in(llc_mask),
+            None => 0,
+        }
+    }
+
+    /// Return the ID of the LLC of @topology which contains the most set
+    /// CPUs, or None if no set CPU belongs to any LLC. Ties are broken in
+    /// favor of the lowest LLC ID.
+    pub fn dominant_llc(&self, topology: &Topology) -> Option<usize> {
+        let mut dominant: Option<(usize, usize)> = None;
+        for node in topology.nodes().iter() {
+            for llc_id in node.llcs().keys() {
+                let count = match topology.cpus_sharing_llc_by_id(*llc_id) {
+                    Some(llc_mask) => self.count_set_in(llc_mask),
+                    None => 0,
+                };
+                if count == 0 {
+                    continue;
+                }
+                match dominant {
+                    Some((id, max)) if max > count || (max == count && id < *llc_id) => {}
+                    _ => dominant = Some((*llc_id, count)),
+                }
+            }
+        }
+        dominant.map(|(llc_id, _)| llc_id)
+    }
+
+    /// Count the set CPUs on each NUMA node of @topology. Returns one
+    /// (node ID, count) pair per node, including nodes without set CPUs,
+    /// ordered by node ID. See count_set_in_node().
+    pub fn popcount_by_node(&self, topology: &Topology) -> Vec<(usize, usize)> {
+        let mut counts: Vec<(usize, usize)> = topology
+            .nodes()
+            .iter()
+            .map(|node| (node.id(), self.count_set_in_node(node.id(), topology)))
+            .collect();
+        counts.sort_by_key(|(node_id, _)| *node_id);
+        counts
+    }
+
+    /// Return the ID of the NUMA node of @topology which contains the most
+    /// set CPUs, or None if no set CPU belongs to any node. Ties are broken
+    /// in favor of the lowest node ID.
+    pub fn max_loaded_node(&self, topology: &Topology) -> Option<usize> {
+        let mut max: Option<(usize, usize)> = None;
+        for (node_id, count) in self.popcount_by_node(topology) {
+            if count > max.map_or(0, |(_, max_count)| max_count) {
+                max = Some((node_id, count));
+            }
+        }
+        max.map(|(node_id, _)| node_id)
+    }
+
+    // Count the CPUs set in both the Cpumask and @other, word by word.
+    fn count_set_in(&self, other: &Cpumask) -> usize {
+        let nr_cpus = self.nr_cpus.min(other.nr_cpus);
+        let mut count = 0;
+        for (i, (a, b)) in self
+            .mask
+            .as_raw_slice()
+            .iter()
+            .zip(other.mask.as_raw_slice().iter())
+            .enumerate()
+        {
+            let mut w = a & b;
+            if (i + 1) * 64 > nr_cpus {
+                if i * 64 >= nr_cpus {
+                    break;
+                }
+                w &= (1u64 << (nr_cpus - i * 64)) - 1;
+            }
+            count += w.count_ones() as usize;
+        }
+        count
+    }
+
+    /// Return the set CPU which is closest to @reference_cpu, or None if the
+    /// Cpumask is empty. @reference_cpu itself is the closest CPU, followed by
+    /// CPUs sharing its LLC, followed by CPUs in other LLCs in the order of
+    /// NUMA distance. Ties are broken in favor of the lowest CPU index.
+    pub fn nearest_cpu_in_mask(&self, reference_cpu: usize, topology: &Topology) -> Option<usize> {
+        if self.test_cpu(reference_cpu) {
+            return Some(reference_cpu);
+        }
+
+        let reference = topology.cpus().get(&reference_cpu)?;
+        let mut nearest: Option<(usize, usize)> = None;
+        for cpu_id in self.mask.iter_ones() {
+            let cpu = match topology.cpus().get(&cpu_id) {
+                Some(cpu) => cpu,
+                None => continue,
+            };
+
+            let cost = if cpu.llc_id() == reference.llc_id() {
+                0
+            } else {
+                match topology.numa_distance(reference.node_id(), cpu.node_id()) {
+                    Some(distance) => distance,
+                    None => continue,
+                }
+            };
+
+            match nearest {
+                Some((_, min_cost)) if min_cost <= cost => {}
+                _ => nearest = Some((cpu_id, cost)),
+            }
+        }
+
+        nearest.map(|(cpu_id, _)| cpu_id)
+    }
+
+    /// Return the set CPUs sorted in ascending order of @scorer(cpu), e.g. to
+    /// order candidate CPUs by utilization or distance. @scorer is called
+    /// once per set CPU. CPUs with the same score are sorted by index.
+    pub fn rank_cpus_by<F: Fn(usize) -> u64>(&self, scorer: F) -> Vec<usize> {
+        let mut scored: Vec<(u64, usize)> = vec![];
+        self.for_each_set_cpu(|cpu| scored.push((scorer(cpu), cpu)));
+        scored.sort_unstable();
+        scored.into_iter().map(|(_, cpu)| cpu).collect()
+    }
+
+    /// Create a Cpumask containing @n of the CPUs set in the current Cpumask,
+    /// selected according to @policy. Returns an error if fewer than @n CPUs
+    /// are set.
+    pub fn select_n_with_policy(&self, n: usize, policy: CpuSelectPolicy) -> Result<Cpumask> {
+        let cpus: Vec<usize> = self.mask.iter_ones().collect();
+        let weight = cpus.len();
+        if n > weight {
+            bail!("Can't select {} CPUs from a Cpumask of {} CPUs", n, weight);
+        }
+
+        let selected: Vec<usize> = match policy {
+            CpuSelectPolicy::FirstN => cpus[..n].to_vec(),
+            CpuSelectPolicy::LastN => cpus[weight - n..].to_vec(),
+            // Picking the (i * weight / n)'th CPU places the selected CPUs
+            // weight / n apart on average, without ever picking a CPU twice.
+            CpuSelectPolicy::EvenlySpaced => (0..n).map(|i| cpus[i * weight / n]).collect(),
+            CpuSelectPolicy::Alternating => cpus
+                .iter()
+                .step_by(2)
+                .chain(cpus.iter().skip(1).step_by(2))
+                .take(n)
+                .copied()
+                .collect(),
+        };
+
+        let mut new = self.clone();
+        new.clear();
+        for cpu in selected {
+            new.set_cpu(cpu)?;
+        }
+        Ok(new)
+    }
+
+    /// Group the set CPUs into clusters of @cluster_size consecutive CPU
+    /// indices aligned to @cluster_size, e.g. CPUs 0-7, 8-15, ... for a
+    /// cluster size of 8, and return a Cpumask for each cluster which has at
+    /// least one CPU set, in ascending order. Returns an empty Vec if
+    /// @cluster_size is 0.
+    pub fn cluster_cpus(&self, cluster_size: usize) -> Vec<Cpumask> {
+        let mut clusters: Vec<Cpumask> = vec![];
+        if cluster_size == 0 {
+            return clusters;
+        }
+
+        let mut cur_cluster = None;
+        for cpu in self.mask.iter_ones() {
+            let cluster = cpu / cluster_size;
+            if cur_cluster != Some(cluster) {
+                let mut new = self.clone();
+                new.clear();
+                clusters.push(new);
+                cur_cluster = Some(cluster);
+            }
+            if let Some(mask) = clusters.last_mut() {
+                mask.mask.set(cpu, true);
+            }
+        }
+
+        clusters
+    }
+
+    /// Iterate over the windows of @size consecutive CPUs of the Cpumask,
+    /// i.e. a Cpumask with CPUs 0..@size set, then 1..@size + 1 and so on up
+    /// to the last CPU. The windows don't depend on the set CPUs, intersect
+    /// them with the Cpumask to get the available CPUs of each window.
+    /// Returns an error if @size is 0 or larger than the number of CPUs.
+    pub fn windows(&self, size: usize) -> Result<impl Iterator<Item = Cpumask>> {
+        if size == 0 || size > self.nr_cpus {
+            bail!("Invalid window size {} for {} CPUs", size, self.nr_cpus);
+        }
+
+        let mut window = self.clone();
+        window.clear();
+        window.mask[..size].fill(true);
+        Ok((0..=self.nr_cpus - size).map(move |start| {
+            let mut new = window.clone();
+            new.mask.shift_end(start);
+            new
+        }))
+    }
+
+    /// Iterate over the chunks of @chunk_size consecutive CPUs of the
+    /// Cpumask, i.e. a Cpumask with the set CPUs of 0..@chunk_size, then of
+    /// @chunk_size..2 * @chunk_size and so on, nr_cpus.div_ceil(@chunk_size)
+    /// in total. Unlike cluster_cpus(), chunks without set CPUs are
+    /// included. Yields nothing if @chunk_size is 0.
+    pub fn chunked(&self, chunk_size: usize) -> impl Iterator<Item = Cpumask> + '_ {
+        let nr_chunks = match chunk_size {
+            0 => 0,
+            size => self.nr_cpus.div_ceil(size),
+        };
+        (0..nr_chunks).map(move |chunk| {
+            let start = chunk * chunk_size;
+            let end = (start + chunk_size).min(self.nr_cpus);
+            let mut new = self.clone();
+            new.mask[..start].fill(false);
+            new.mask[end..].fill(false);
+            new
+        })
+    }
+
+    /// Create a Cpumask with all set CPUs moved @n CPUs up, e.g. CPU 2 becomes
+    /// CPU 2 + @n. CPUs moved past the number of CPUs of the Cpumask are
+    /// dropped rather than wrapped around.
+    pub fn shift_left(&self, n: usize) -> Cpumask {
+        let mut new = self.clone();
+        if n >= self.nr_cpus {
+            new.clear();
+        } else {
+            // Moving bits toward higher indices is shifting toward the end of
+            // the BitVec.
+            new.mask.shift_end(n);
+        }
+        new
+    }
+
+    /// Create a Cpumask with all set CPUs moved @n CPUs down, e.g. CPU 2 + @n
+    /// becomes CPU 2. CPUs moved below CPU 0 are dropped rather than wrapped
+    /// around.
+    pub fn shift_right(&self, n: usize) -> Cpumask {
+        let mut new = self.clone();
+        if n >= self.nr_cpus {
+            new.clear();
+        } else {
+            new.mask.shift_start(n);
+        }
+        new
+    }
+
+    /// Create a Cpumask of the same number of CPUs where each set CPU is
+    /// renumbered to @f(cpu), e.g. to translate guest CPU IDs to host CPU
+    /// IDs. Returns an error if a CPU is mapped beyond the number of CPUs of
+    /// the Cpumask, or if two CPUs are mapped to the same CPU.
+    pub fn map<F: Fn(usize) -> usize>(&self, f: F) -> Result<Cpumask> {
+        let mut new = self.clone();
+        new.clear();
+        for cpu in self.mask.iter_ones() {
+            let mapped = f(cpu);
+            new.check_cpu(mapped)
+                .with_context(|| format!("Failed to map CPU {} to {}", cpu, mapped))?;
+            if new.test_cpu(mapped) {
+                bail!("CPU {} is mapped to CPU {} which is already set", cpu, mapped);
+            }
+            new.mask.set(mapped, true);
+        }
+        Ok(new)
+    }
+
+    /// Set each CPU of the Cpumask for which @predicate(cpu) returns true,
+    /// e.g. all CPUs with a capacity above a threshold. CPUs for which it
+    /// returns false are left unchanged.
+    pub fn set_if<F: Fn(usize) -> bool>(&mut self, predicate: F) {
+        for cpu in 0..self.nr_cpus {
+            if predicate(cpu) {
+                self.mask.set(cpu, true);
+            }
+        }
+    }
+
+    /// Clear each CPU of the Cpumask for which @predicate(cpu) returns true.
+    /// CPUs for which it returns false are left unchanged.
+    pub fn clear_if<F: Fn(usize) -> bool>(&mut self, predicate: F) {
+        for cpu in self.mask.iter_ones().collect::<Vec<_>>() {
+            if predicate(cpu) {
+                self.mask.set(cpu, false);
+            }
+        }
+    }
+
+    /// Keep only the set CPUs for which @predicate(cpu) returns true.
+    pub fn retain<F: Fn(usize) -> bool>(&mut self, predicate: F) {
+        self.clear_if(|cpu| !predicate(cpu));
+    }
+
+    /// Create a Cpumask of the set CPUs for which @predicate(cpu) returns
+    /// true.
+    pub fn filter<F: Fn(usize) -> bool>(&self, predicate: F) -> Cpumask {
+        let mut new = self.clone();
+        new.retain(predicate);
+        new
+    }
+
+    /// Create a Cpumask that is the OR of the current Cpumask and another.
+    pub fn or(&self, other: &Cpumask) -> Result<Cpumask> {
+        let mut new = self.clone();
+        new.mask |= other.mask.clone();
+        Ok(new)
+    }
+
+    /// Create a Cpumask that is the AND of the current Cpumask and another.
+    pub fn and(&self, other: &Cpumask) -> Result<Cpumask> {
+        let mut new = self.clone();
+        new.mask &= other.mask.clone();
+        Ok(new)
+    }
+
+    /// Create a Cpumask that is the XOR of the current Cpumask and another.
+    pub fn xor(&self, other: &Cpumask) -> Result<Cpumask> {
+        let mut new = self.clone();
+        new.mask ^= other.mask.clone();
+        Ok(new)
+    }
+
+    /// Create a Cpumask that is the union of the current Cpumask and
+    /// another. This is the same as or().
+    pub fn union(&self, other: &Cpumask) -> Result<Cpumask> {
+        self.or(other)
+    }
+
+    /// Create a Cpumask that is the intersection of the current Cpumask and
+    /// another. This is the same as and().
+    pub fn intersection(&self, other: &Cpumask) -> Result<Cpumask> {
+        self.and(other)
+    }
+
+    /// Create a Cpumask with the CPUs of the current Cpumask that are not in
+    /// another, i.e. the current Cpumask AND NOT the other.
+    pub fn difference(&self, other: &Cpumask) -> Result<Cpumask> {
+        let mut new = self.clone();
+        new.mask &= !other.mask.clone();
+        Ok(new)
+    }
+
+    /// Create a Cpumask with the CPUs that are in exactly one of the current
+    /// Cpumask and another. This is the same as xor().
+    pub fn symmetric_difference(&self, other: &Cpumask) -> Result<Cpumask> {
+        self.xor(other)
+    }
+
+    fn check_same_len(&self, other: &Cpumask) -> Result<()> {
+        if self.nr_cpus != other.nr_cpus {
+            bail!(
+                "Cpumask of {} CPUs doesn't match Cpumask of {} CPUs",
+                self.nr_cpus,
+                other.nr_cpus
+            );
+        }
+        Ok(())
+    }
+
+    /// OR another Cpumask into the current Cpumask in place. Returns an error
+    /// if the Cpumasks have a different number of CPUs.
+    pub fn or_in_place(&mut self, other: &Cpumask) -> Result<()> {
+        self.check_same_len(other)?;
+        for (a, b) in self
+            .mask
+            .as_raw_mut_slice()
+            .iter_mut()
+            .zip(other.mask.as_raw_slice().iter())
+        {
+            *a |= *b;
+        }
+        Ok(())
+    }
+
+    /// AND another Cpumask into the current Cpumask in place. Returns an
+    /// error if the Cpumasks have a different number of CPUs.
+    pub fn and_in_place(&mut self, other: &Cpumask) -> Result<()> {
+        self.check_same_len(other)?;
+        for (a, b) in self
+            .mask
+            .as_raw_mut_slice()
+            .iter_mut()
+            .zip(other.mask.as_raw_slice().iter())
+        {
+            *a &= *b;
+        }
+        Ok(())
+    }
+
+    /// XOR another Cpumask into the current Cpumask in place. Returns an
+    /// error if the Cpumasks have a different number of CPUs.
+    pub fn xor_in_place(&mut self, other: &Cpumask) -> Result<()> {
+        self.check_same_len(other)?;
+        for (a, b) in self
+            .mask
+            .as_raw_mut_slice()
+            .iter_mut()
+            .zip(other.mask.as_raw_slice().iter())
+        {
+            *a ^= *b;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Cpumask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:<{}>", self.nr_cpus, self.mask)
+    }
+}
+
+/// Mutable access to the raw words of a Cpumask as u32's, see
+/// Cpumask::words_as_u32_slice_mut(). Clears the bits beyond the number of
+/// CPUs of the Cpumask when dropped.
+#[derive(Debug)]
+pub struct CpumaskWordsMut<'a> {
+    mask: &'a mut Cpumask,
+}
+
+impl Deref for CpumaskWordsMut<'_> {
+    type Target = [u32];
+
+    fn deref(&self) -> &[u32] {
+        self.mask.words_as_u32_slice()
+    }
+}
+
+impl DerefMut for CpumaskWordsMut<'_> {
+    fn deref_mut(&mut self) -> &mut [u32] {
+        // A u64 slice is always sufficiently aligned for u32's.
+        bytemuck::cast_slice_mut(self.mask.mask.as_raw_mut_slice())
+    }
+}
+
+impl Drop for CpumaskWordsMut<'_> {
+    fn drop(&mut self) {
+        let nr_bits = self.mask.nr_cpus % 64;
+        if let (Some(last), 1..) = (self.mask.mask.as_raw_mut_slice().last_mut(), nr_bits) {
+            *last &= (1u64 << nr_bits) - 1;
+        }
+    }
+}
+
+pub struct CpumaskIntoIterator {
+    mask: Cpumask,
+    index: usize,
+}
+
+/// Iterate over each element of a Cpumask, and return the indices with bits
+/// set.
+///
+/// # Examples
+///
+/// ```
+/// let mask = Cpumask::from_str(cpumask_str)?;
+/// for cpu in mask.clone().into_iter() {
+///     info!("cpu {} was set", cpu);
+/// }
+/// ```
+impl IntoIterator for Cpumask {
+    type Item = usize;
+    type IntoIter = CpumaskIntoIterator;
+
+    fn into_iter(self) -> CpumaskIntoIterator {
+        CpumaskIntoIterator { mask: self, index: 0 }
+    }
+}
+
+impl Iterator for CpumaskIntoIterator {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < self.mask.nr_cpus {
+            let index = self.index;
+            self.index += 1;
+            let bit_val = self.mask.test_cpu(index);
+            if bit_val {
+                return Some(index);
+            }
+        }
+
+        None
+    }
+}
+
+/// A collection of masks of the same number of CPUs, stored packed
+/// end-to-end in a single Vec<u64> without any per-mask overhead.
+#[derive(Debug, Clone, Default)]
+pub struct CpumaskVec {
+    words: Vec<u64>,
+    nr_cpus: usize,
+}
+
+impl CpumaskVec {
+    /// Create an empty CpumaskVec for masks of @nr_cpus CPUs.
+    pub fn new(nr_cpus: usize) -> CpumaskVec {
+        CpumaskVec {
+            words: vec![],
+            nr_cpus,
+        }
+    }
+
+    fn words_per_mask(&self) -> usize {
+        self.nr_cpus.div_ceil(64)
+    }
+
+    fn range(&self, index: usize) -> Option<std::ops::Range<usize>> {
+        if index >= self.len() {
+            return None;
+        }
+        let nr_words = self.words_per_mask();
+        Some(index * nr_words..(index + 1) * nr_words)
+    }
+
+    /// The number of CPUs of each mask.
+    pub fn nr_cpus(&self) -> usize {
+        self.nr_cpus
+    }
+
+    /// The number of masks.
+    pub fn len(&self) -> usize {
+        match self.words_per_mask() {
+            0 => 0,
+            nr_words => self.words.len() / nr_words,
+        }
+    }
+
+    /// Test whether the CpumaskVec contains no masks.
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Append a copy of @mask. Returns an error if @mask doesn't have the
+    /// same number of CPUs as the CpumaskVec.
+    pub fn push(&mut self, mask: &Cpumask) -> Result<()> {
+        if mask.nr_cpus != self.nr_cpus {
+            bail!(
+                "Cpumask of {} CPUs doesn't match CpumaskVec of {} CPUs",
+                mask.nr_cpus,
+                self.nr_cpus
+            );
+        }
+        mask.for_each_word(true, |_, word| self.words.push(word));
+        Ok(())
+    }
+
+    /// Get a view of the mask at @index, or None if @index is out of range.
+    pub fn get(&self, index: usize) -> Option<CpumaskRef<'_>> {
+        let range = self.range(index)?;
+        Some(CpumaskRef {
+            words: &self.words[range],
+            nr_cpus: self.nr_cpus,
+        })
+    }
+
+    /// Get a mutable view of the mask at @index, or None if @index is out of
+    /// range.
+    pub fn get_mut(&mut self, index: usize) -> Option<CpumaskMut<'_>> {
+        let range = self.range(index)?;
+        Some(CpumaskMut {
+            words: &mut self.words[range],
+            nr_cpus: self.nr_cpus,
+        })
+    }
+}
+
+// Wrap the error @err of sched_{get,set}affinity(2) on @pid with @context,
+// mapping EPERM to ScxError::PermissionDenied.
+fn affinity_error(pid: i32, err: std::io::Error, context: String) -> anyhow::Error {
+    let err = match err.raw_os_error() {
+        Some(libc::EPERM) => anyhow::Error::new(ScxError::PermissionDenied { pid }),
+        _ => anyhow::Error::new(err),
+    };
+    err.context(context)
+}
+
+fn words_test_cpu(words: &[u64], cpu: usize) -> bool {
+    match words.get(cpu / 64) {
+        Some(word) => word & (1 << (cpu % 64)) != 0,
+        None => false,
+    }
+}
+
+fn words_to_cpumask(words: &[u64], nr_cpus: usize) -> Cpumask {
+    let mut mask = BitVec::<u64, Lsb0>::from_slice(words);
+    mask.truncate(nr_cpus);
+    Cpumask { mask, nr_cpus }
+}
+
+/// A read-only view of a mask stored in a CpumaskVec.
+#[derive(Debug, Clone, Copy)]
+pub struct CpumaskRef<'a> {
+    words: &'a [u64],
+    nr_cpus: usize,
+}
+
+impl CpumaskRef<'_> {
+    /// Test whether the specified CPU bit is set in the mask.
+    pub fn test_cpu(&self, cpu: usize) -> bool {
+        cpu < self.nr_cpus && words_test_cpu(self.words, cpu)
+    }
+
+    /// Count the number of bits set in the mask.
+    pub fn weight(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Return the raw words of the mask.
+    pub fn as_raw_slice(&self) -> &[u64] {
+        self.words
+    }
+
+    /// Copy the mask into a Cpumask.
+    pub fn to_cpumask(&self) -> Cpumask {
+        words_to_cpumask(self.words, self.nr_cpus)
+    }
+}
+
+/// A mutable view of a mask stored in a CpumaskVec.
+#[derive(Debug)]
+pub struct CpumaskMut<'a> {
+    words: &'a mut [u64],
+    nr_cpus: usize,
+}
+
+impl CpumaskMut<'_> {
+    fn check_cpu(&self, cpu: usize) -> Result<()> {
+        if cpu >= self.nr_cpus {
+            bail!("Invalid CPU {} passed, max {}", cpu, self.nr_cpus);
+        }
+        Ok(())
+    }
+
+    /// Set a bit in the mask, returning an error if it's out of range.
+    pub fn set_cpu(&mut self, cpu: usize) -> Result<()> {
+        self.check_cpu(cpu)?;
+        self.words[cpu / 64] |= 1 << (cpu % 64);
+        Ok(())
+    }
+
+    /// Clear a bit from the mask, returning an error if it's out of range.
+    pub fn clear_cpu(&mut self, cpu: usize) -> Result<()> {
+        self.check_cpu(cpu)?;
+        self.words[cpu / 64] &= !(1 << (cpu % 64));
+        Ok(())
+    }
+
+    /// Test whether the specified CPU bit is set in the mask.
+    pub fn test_cpu(&self, cpu: usize) -> bool {
+        cpu < self.nr_cpus && words_test_cpu(self.words, cpu)
+    }
+
+    /// Clear all bits in the mask.
+    pub fn clear(&mut self) {
+        self.words.fill(0);
+    }
+
+    /// Overwrite the mask with a copy of @mask. Returns an error if @mask
+    /// doesn't have the same number of CPUs.
+    pub fn copy_from(&mut self, mask: &Cpumask) -> Result<()> {
+        if mask.nr_cpus != self.nr_cpus {
+            bail!(
+                "Cpumask of {} CPUs doesn't match CpumaskVec of {} CPUs",
+                mask.nr_cpus,
+                self.nr_cpus
+            );
+        }
+        mask.for_each_word(true, |i, word| self.words[i] = word);
+        Ok(())
+    }
+
+    /// Copy the mask into a Cpumask.
+    pub fn to_cpumask(&self) -> Cpumask {
+        words_to_cpumask(self.words, self.nr_cpus)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SharedCpumask {
+    mask: Arc<RwLock<Cpumask>>,
+}
+
+impl SharedCpumask {
+    /// Create a SharedCpumask holding @mask.
+    pub fn new(mask: Cpumask) -> SharedCpumask {
+        SharedCpumask {
+            mask: Arc::new(RwLock::new(mask)),
+        }
+    }
+
+    /// Replace the mask with @new_mask.
+    pub fn update(&self, new_mask: Cpumask) {
+        *self.mask.write().unwrap() = new_mask;
+    }
+
+    /// Replace the mask with @new_mask if they differ. Only takes the write
+    /// lock if the mask changes. Returns whether the mask was replaced.
+    /// Concurrent updates race as with update(), the last one wins.
+    pub fn update_if_changed(&self, new_mask: Cpumask) -> bool {
+        if *self.mask.read().unwrap() == new_mask {
+            return false;
+        }
+        self.update(new_mask);
+        true
+    }
+
+    /// Get a copy of the current mask.
+    pub fn snapshot(&self) -> Cpumask {
+        self.mask.read().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::affinity_error;
+    use super::Cpumask;
+    use crate::DomainSet;
+    use crate::LocalDomainId;
+    use crate::ScxError;
+
+    #[test]
+    fn test_from_affinity_t() {
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        unsafe { libc::CPU_SET(0, &mut set) };
+        unsafe { libc::CPU_SET(libc::CPU_SETSIZE as usize - 1, &mut set) };
+
+        let mask = Cpumask::from_affinity_t(&set);
+        assert!(mask.test_cpu(0));
+        let nr_cpus = Cpumask::get_cpus_possible();
+        let expected = if nr_cpus == libc::CPU_SETSIZE as usize {
+            2
+        } else {
+            1
+        };
+        assert_eq!(mask.weight(), expected);
+    }
+
+    #[test]
+    fn test_affinity_error() {
+        let eperm = std::io::Error::from_raw_os_error(libc::EPERM);
+        let err = affinity_error(1, eperm, "Failed".to_string());
+        assert_eq!(
+            err.downcast_ref::<ScxError>(),
+            Some(&ScxError::PermissionDenied { pid: 1 })
+        );
+
+        let esrch = std::io::Error::from_raw_os_error(libc::ESRCH);
+        let err = affinity_error(1, esrch, "Failed".to_string());
+        assert_eq!(err.downcast_ref::<ScxError>(), None);
+        assert!(err.downcast_ref::<std::io::Error>().is_some());
+    }
+
+    #[test]
+    fn test_containing_domain() {
+        let mask = |cpus: &[usize]| Cpumask::from_iter_with_capacity(cpus.to_vec(), 8).unwrap();
+        let domains = DomainSet::new(vec![
+            (LocalDomainId(0), mask(&[0, 1, 2, 3])),
+            (LocalDomainId(1), mask(&[4, 5, 6])),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            mask(&[1, 3]).containing_domain(&domains),
+            Some(LocalDomainId(0))
+        );
+        assert_eq!(
+            mask(&[4, 6]).containing_domain(&domains),
+            Some(LocalDomainId(1))
+        );
+        assert_eq!(mask(&[3, 4]).containing_domain(&domains), None);
+        assert_eq!(mask(&[6, 7]).containing_domain(&domains), None);
+        assert_eq!(mask(&[]).containing_domain(&domains), None);
+    }
+
+    #[test]
+    fn test_serialize_roundtrip() {
+        let mask = Cpumask::from_iter_with_capacity([0, 63, 64, 69], 70).unwrap();
+        let mut buf = vec![];
+        mask.serialize_to_writer(&mut buf).unwrap();
+        assert_eq!(buf.len(), 4 + 2 * 8);
+
+        let restored = Cpumask::deserialize_from_reader(&buf[..]).unwrap();
+        assert_eq!(restored, mask);
+        assert_eq!(restored.len(), 70);
+    }
+
+    #[test]
+    fn test_deserialize_dirty_last_word() {
+        let mask = Cpumask::from_iter_with_capacity([1], 70).unwrap();
+        let mut buf = vec![];
+        mask.serialize_to_writer(&mut buf).unwrap();
+
+        // Set CPU 70, the first bit past the end of the mask.
+        let word = u64::from_ne_bytes(buf[12..20].try_into().unwrap()) | 1 << 6;
+        buf[12..20].copy_from_slice(&word.to_ne_bytes());
+        assert!(Cpumask::deserialize_from_reader(&buf[..]).is_err());
+    }
+
+    #[test]
+    fn test_trailing_leading_zeros() {
+        let mask = Cpumask::from_iter_with_capacity([3, 66], 70).unwrap();
+        assert_eq!(mask.trailing_zeros(), 3);
+        assert_eq!(mask.leading_zeros(), 3);
+
+        let empty = Cpumask::from_iter_with_capacity([], 70).unwrap();
+        assert_eq!(empty.trailing_zeros(), 70);
+        assert_eq!(empty.leading_zeros(), 70);
+    }
+
+    #[test]
+    fn test_trailing_leading_zeros_dirty_padding() {
+        // Set bits past the last CPU of the mask, which must be ignored.
+        let mut mask = Cpumask::from_iter_with_capacity([1], 4).unwrap();
+        mask.mask.as_raw_mut_slice()[0] |= 1 << 63;
+        assert_eq!(mask.trailing_zeros(), 1);
+        assert_eq!(mask.leading_zeros(), 2);
+
+        let mut empty = Cpumask::from_iter_with_capacity([], 4).unwrap();
+        empty.mask.as_raw_mut_slice()[0] |= 1 << 5;
+        assert_eq!(empty.trailing_zeros(), 4);
+        assert_eq!(empty.leading_zeros(), 4);
+    }
+
+    #[test]
+    fn test_runs_dirty_padding() {
+        // Set bits past the last CPU of the mask, which must not extend or
+        // add runs.
+        let mut mask = Cpumask::from_iter_with_capacity([2, 3], 4).unwrap();
+        mask.mask.as_raw_mut_slice()[0] |= 0xf0;
+        assert_eq!(mask.longest_set_run(), (2, 2));
+        assert_eq!(mask.all_runs(), vec![(2, 2)]);
+
+        let mut empty = Cpumask::from_iter_with_capacity([], 4).unwrap();
+        empty.mask.as_raw_mut_slice()[0] |= 1 << 63;
+        assert_eq!(empty.longest_set_run(), (0, 0));
+        assert!(empty.all_runs().is_empty());
+    }
+
+    #[test]
+    fn test_words_as_u32_slice_mut() {
+        let mut mask = Cpumask::from_iter_with_capacity([], 40).unwrap();
+        {
+            // Sets CPUs 0-63, of which only 0-39 exist.
+            let mut words = mask.words_as_u32_slice_mut();
+            assert_eq!(words.len(), 2);
+            words.fill(u32::MAX);
+        }
+        assert_eq!(mask.weight(), 40);
+        assert_eq!(mask.mask.as_raw_slice(), [(1u64 << 40) - 1]);
+    }
+
+    #[test]
+    fn test_eq_ignoring_offline() {
+        let mask = |cpus: &[usize], nr_cpus| {
+            Cpumask::from_iter_with_capacity(cpus.to_vec(), nr_cpus).unwrap()
+        };
+        let online = mask(&[0, 1, 2, 3, 4, 5], 8);
+
+        assert!(mask(&[1, 6], 8).eq_ignoring_offline(&mask(&[1, 7], 8), &online));
+        assert!(!mask(&[1], 8).eq_ignoring_offline(&mask(&[2], 8), &online));
+        // CPUs 4 and 5 are beyond the end of the 4 CPU mask.
+        assert!(!mask(&[1], 4).eq_ignoring_offline(&mask(&[1, 4], 8), &online));
+
+        // Bits past the end of a mask don't count as set CPUs.
+        let mut dirty = mask(&[1], 4);
+        dirty.mask.as_raw_mut_slice()[0] |= 1 << 5;
+        assert!(dirty.eq_ignoring_offline(&mask(&[1], 8), &online));
+        assert!(mask(&[1], 8).eq_ignoring_offline(&dirty, &online));
+    }
+
+    #[test]
+    fn test_from_hex_str_out_of_range() {
+        let mask = Cpumask::from_hex_str("0xf", 4).unwrap();
+        assert_eq!(mask.weight(), 4);
+
+        // CPU 4 is just past the last CPU of a 4 CPU mask.
+        assert!(Cpumask::from_hex_str("0x10", 4).is_err());
+        assert!(Cpumask::from_hex_str("0x1f", 4).is_err());
     }
 }
@@ -0,0 +1,298 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Metrics Dashboard
+//!
+//! A crate that allows scheduler operators to see the health metrics of a
+//! scheduler in one place, instead of collecting them from each source.
+//!
+//! ScxMetricsAggregator
+//! --------------------
+//!
+//! A ScxMetricsAggregator spawns a thread which periodically samples:
+//!
+//! - the run count and run time of the BPF programs of the scheduler, see
+//!   BpfProgramStats
+//! - the CPU pressure from /proc/pressure/cpu
+//! - the average time tasks waited on a runqueue before running, from
+//!   /proc/schedstat
+//!
+//! The most recent samples are kept in a circular buffer. The thread is
+//! stopped when the ScxMetricsAggregator is dropped:
+//!
+//!```
+//!     let prog_fds = skel
+//!         .object()
+//!         .progs_iter()
+//!         .map(|prog| prog.as_fd().try_clone_to_owned())
+//!         .collect::<Result<Vec<_>, _>>()?;
+//!     let metrics = ScxMetricsAggregator::spawn(Duration::from_secs(1), 60, prog_fds)?;
+//!
+//!     loop {
+//!         std::thread::sleep(Duration::from_secs(10));
+//!         metrics.print_table(&mut std::io::stdout())?;
+//!     }
+//!```
+//!
+//! A metric is None if its source isn't available, e.g. the BPF metrics
+//! require BPF statistics to be enabled, see BpfProgramStats::enable_stats(),
+//! and the runqueue wait time requires a kernel built with CONFIG_SCHEDSTATS.
+//! Except for the CPU pressure, which the kernel already averages, the
+//! metrics of a sample cover the interval since the previous sample.
+
+use crate::worker::PeriodicThread;
+use crate::BpfProgramStats;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::os::fd::AsFd;
+use std::os::fd::OwnedFd;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(Debug, Clone)]
+pub struct DashboardSnapshot {
+    /// When the metrics were sampled
+    pub timestamp: Instant,
+    /// The number of runs of the BPF programs
+    pub bpf_run_cnt: Option<u64>,
+    /// The average run time of the BPF programs in nanoseconds
+    pub bpf_avg_run_time_ns: Option<u64>,
+    /// The percentage of time some runnable tasks waited for a CPU over the
+    /// last 10 seconds
+    pub cpu_some_avg10: Option<f64>,
+    /// The percentage of time all non-idle tasks waited for a CPU over the
+    /// last 10 seconds
+    pub cpu_full_avg10: Option<f64>,
+    /// The average time a task waited on a runqueue before running in
+    /// microseconds
+    pub avg_run_delay_us: Option<f64>,
+}
+
+// Parse the avg10 value of the @kind ("some" or "full") line of a
+// /proc/pressure file.
+fn parse_pressure_avg10(pressure: &str, kind: &str) -> Option<f64> {
+    let line = pressure
+        .lines()
+        .find(|line| line.split_whitespace().next() == Some(kind))?;
+    line.split_whitespace()
+        .find_map(|field| field.strip_prefix("avg10="))?
+        .parse()
+        .ok()
+}
+
+// Parse the sum of the (run delay in ns, number of timeslices) of all CPUs
+// from the contents of /proc/schedstat.
+fn parse_schedstat(schedstat: &str) -> Result<(u64, u64)> {
+    let (mut run_delay, mut nr_slices) = (0, 0);
+    for line in schedstat.lines() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some(label) if label.starts_with("cpu") => {}
+            _ => continue,
+        }
+        let vals = fields
+            .map(|val| val.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Failed to parse /proc/schedstat line {:?}", line))?;
+        if vals.len() < 9 {
+            bail!("Too few fields in /proc/schedstat line {:?}", line);
+        }
+        run_delay += vals[7];
+        nr_slices += vals[8];
+    }
+    Ok((run_delay, nr_slices))
+}
+
+#[derive(Debug)]
+struct Sampler {
+    prog_fds: Vec<OwnedFd>,
+    // The cumulative values of the previous sample.
+    prev_bpf: (u64, u64),
+    prev_schedstat: (u64, u64),
+}
+
+impl Sampler {
+    fn sample_bpf(&mut self) -> (Option<u64>, Option<u64>) {
+        if self.prog_fds.is_empty() {
+            return (None, None);
+        }
+        let stats = match BpfProgramStats::from_fds(self.prog_fds.iter().map(|fd| fd.as_fd())) {
+            Ok(stats) => stats,
+            Err(_) => return (None, None),
+        };
+
+        let run_cnt = stats.run_cnt.saturating_sub(self.prev_bpf.0);
+        let run_time_ns = stats.run_time_ns.saturating_sub(self.prev_bpf.1);
+        self.prev_bpf = (stats.run_cnt, stats.run_time_ns);
+        let avg = match run_cnt {
+            0 => 0,
+            cnt => run_time_ns / cnt,
+        };
+        (Some(run_cnt), Some(avg))
+    }
+
+    fn sample_run_delay(&mut self) -> Option<f64> {
+        let schedstat = std::fs::read_to_string("/proc/schedstat").ok()?;
+        let (run_delay, nr_slices) = parse_schedstat(&schedstat).ok()?;
+
+        let delta_delay = run_delay.saturating_sub(self.prev_schedstat.0);
+        let delta_slices = nr_slices.saturating_sub(self.prev_schedstat.1);
+        self.prev_schedstat = (run_delay, nr_slices);
+        match delta_slices {
+            0 => Some(0.0),
+            slices => Some(delta_delay as f64 / slices as f64 / 1000.0),
+        }
+    }
+
+    fn sample(&mut self) -> DashboardSnapshot {
+        let timestamp = Instant::now();
+        let (bpf_run_cnt, bpf_avg_run_time_ns) = self.sample_bpf();
+        let pressure = std::fs::read_to_string("/proc/pressure/cpu").unwrap_or_default();
+
+        DashboardSnapshot {
+            timestamp,
+            bpf_run_cnt,
+            bpf_avg_run_time_ns,
+            cpu_some_avg10: parse_pressure_avg10(&pressure, "some"),
+            cpu_full_avg10: parse_pressure_avg10(&pressure, "full"),
+            avg_run_delay_us: self.sample_run_delay(),
+        }
+    }
+}
+
+type MetricFn = fn(&DashboardSnapshot) -> Option<f64>;
+
+#[derive(Debug)]
+pub struct ScxMetricsAggregator {
+    samples: Arc<Mutex<VecDeque<DashboardSnapshot>>>,
+    _thread: PeriodicThread,
+}
+
+impl ScxMetricsAggregator {
+    /// Spawn a thread which samples the metrics every @interval and keeps
+    /// the last @nr_samples samples. @prog_fds are the BPF programs of the
+    /// scheduler, which may be empty to skip the BPF metrics. The first
+    /// sample is taken before returning, so that there always is a current
+    /// one. It covers the time since the programs were loaded and since
+    /// boot respectively.
+    pub fn spawn(
+        interval: Duration,
+        nr_samples: usize,
+        prog_fds: Vec<OwnedFd>,
+    ) -> Result<ScxMetricsAggregator> {
+        if nr_samples == 0 {
+            bail!("The number of samples must be at least 1");
+        }
+
+        let mut sampler = Sampler {
+            prog_fds,
+            prev_bpf: (0, 0),
+            prev_schedstat: (0, 0),
+        };
+        let mut buf = VecDeque::with_capacity(nr_samples);
+        buf.push_back(sampler.sample());
+        let samples = Arc::new(Mutex::new(buf));
+
+        let thread_samples = samples.clone();
+        let thread = PeriodicThread::spawn("scx_dashboard".into(), interval, false, move || {
+            let snapshot = sampler.sample();
+            let mut samples = thread_samples.lock().unwrap();
+            if samples.len() == nr_samples {
+                samples.pop_front();
+            }
+            samples.push_back(snapshot);
+        })
+        .context("Failed to spawn metrics aggregator thread")?;
+
+        Ok(ScxMetricsAggregator {
+            samples,
+            _thread: thread,
+        })
+    }
+
+    /// Get the most recent sample.
+    pub fn current_snapshot(&self) -> DashboardSnapshot {
+        // spawn() takes the first sample and old ones are only evicted when
+        // adding new ones, so there always is one.
+        self.samples.lock().unwrap().back().unwrap().clone()
+    }
+
+    /// Get all samples kept, oldest first.
+    pub fn samples(&self) -> Vec<DashboardSnapshot> {
+        self.samples.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Write the most recent sample to @w as a human-readable table, along
+    /// with the minimum and maximum of each metric across all samples kept.
+    pub fn print_table(&self, w: &mut impl Write) -> Result<()> {
+        let samples = self.samples();
+        let rows: [(&str, MetricFn); 5] = [
+            ("BPF runs", |s| s.bpf_run_cnt.map(|v| v as f64)),
+            ("BPF avg run time (ns)", |s| {
+                s.bpf_avg_run_time_ns.map(|v| v as f64)
+            }),
+            ("CPU pressure some (%)", |s| s.cpu_some_avg10),
+            ("CPU pressure full (%)", |s| s.cpu_full_avg10),
+            ("Avg runqueue wait (us)", |s| s.avg_run_delay_us),
+        ];
+
+        let fmt = |val: Option<f64>| match val {
+            Some(val) => format!("{:.2}", val),
+            None => "-".to_string(),
+        };
+
+        writeln!(
+            w,
+            "{:<24} {:>14} {:>14} {:>14}",
+            "METRIC", "CURRENT", "MIN", "MAX"
+        )?;
+        for (name, get) in rows {
+            let vals: Vec<f64> = samples.iter().filter_map(get).collect();
+            let min = vals.iter().copied().reduce(f64::min);
+            let max = vals.iter().copied().reduce(f64::max);
+            writeln!(
+                w,
+                "{:<24} {:>14} {:>14} {:>14}",
+                name,
+                fmt(samples.last().and_then(get)),
+                fmt(min),
+                fmt(max)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_pressure_avg10;
+    use super::parse_schedstat;
+
+    #[test]
+    fn test_parse_sources() {
+        let pressure = concat!(
+            "some avg10=1.50 avg60=2.97 avg300=3.33 total=136444824\n",
+            "full avg10=0.25 avg60=0.00 avg300=0.00 total=0\n",
+        );
+        assert_eq!(parse_pressure_avg10(pressure, "some"), Some(1.5));
+        assert_eq!(parse_pressure_avg10(pressure, "full"), Some(0.25));
+        assert_eq!(parse_pressure_avg10("", "some"), None);
+
+        let schedstat = concat!(
+            "version 15\n",
+            "timestamp 4295000000\n",
+            "cpu0 0 0 0 0 0 0 1000 2000 10\n",
+            "domain0 MC 1 2 3\n",
+            "cpu1 0 0 0 0 0 0 3000 4000 30\n",
+        );
+        assert_eq!(parse_schedstat(schedstat).unwrap(), (6000, 40));
+        assert!(parse_schedstat("cpu0 1 2 3\n").is_err());
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Debug Dump
+//!
+//! A crate that allows schedulers to collect a human readable snapshot of
+//! their state, e.g. when they crash or exit with an error.
+//!
+//! ScxDebugDump
+//! ------------
+//!
+//! Types implementing ScxDebugDump can write a description of their state to
+//! any io::Write. It is implemented for Cpumask, Topology, DomainSet,
+//! ScxRunQueue, PlacementLog, BpfProgramStats and LoadLedger. A DumpAll
+//! collects several objects to dump them in order:
+//!
+//!```
+//!     let dump = DumpAll::new(vec![
+//!         Box::new(Topology::new()?),
+//!         Box::new(domains.clone()),
+//!         Box::new(stats),
+//!     ]);
+//!
+//!     if let Err(e) = run_scheduler() {
+//!         dump.dump(&mut std::io::stderr())?;
+//!     }
+//!```
+
+use crate::BpfProgramStats;
+use crate::Cpumask;
+use crate::DomainSet;
+use crate::LoadLedger;
+use crate::PlacementLog;
+use crate::Topology;
+use std::io;
+use std::io::Write;
+
+pub trait ScxDebugDump {
+    /// Write a human readable description of the object's state to @w.
+    fn dump(&self, w: &mut dyn Write) -> io::Result<()>;
+}
+
+impl ScxDebugDump for Cpumask {
+    fn dump(&self, w: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            w,
+            "cpumask: {} cpus={} weight={}",
+            self.to_hex_string(),
+            self.to_cgroup_cpuset_string(),
+            self.weight()
+        )
+    }
+}
+
+impl ScxDebugDump for Topology {
+    fn dump(&self, w: &mut dyn Write) -> io::Result<()> {
+        let span = self.span();
+        writeln!(
+            w,
+            "topology: nr_cpus={} online={}",
+            self.nr_cpus(),
+            span.to_cgroup_cpuset_string()
+        )?;
+        for node in self.nodes().iter() {
+            let distances: Vec<String> = node
+                .distances()
+                .iter()
+                .map(|(id, distance)| format!("{}={}", id, distance))
+                .collect();
+            writeln!(
+                w,
+                "  node {}: cpus={} distances=[{}]",
+                node.id(),
+                node.span().to_cgroup_cpuset_string(),
+                distances.join(" ")
+            )?;
+            for (llc_id, llc) in node.llcs().iter() {
+                writeln!(
+                    w,
+                    "    llc {}: cpus={}",
+                    llc_id,
+                    llc.span().to_cgroup_cpuset_string()
+                )?;
+                for (core_id, core) in llc.cores().iter() {
+                    writeln!(
+                        w,
+                        "      core {}: cpus={}",
+                        core_id,
+                        core.span().to_cgroup_cpuset_string()
+                    )?;
+                    for (cpu_id, cpu) in core.cpus().iter() {
+                        writeln!(
+                            w,
+                            "        cpu {}: freq={}-{} capacity={}",
+                            cpu_id,
+                            cpu.min_freq(),
+                            cpu.max_freq(),
+                            cpu.capacity()
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ScxDebugDump for DomainSet {
+    fn dump(&self, w: &mut dyn Write) -> io::Result<()> {
+        writeln!(w, "domains: nr_domains={}", self.len())?;
+        for (id, cpus) in self.domains().iter() {
+            writeln!(w, "  dom {}: cpus={}", id, cpus.to_cgroup_cpuset_string())?;
+        }
+        Ok(())
+    }
+}
+
+impl ScxDebugDump for PlacementLog {
+    fn dump(&self, w: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            w,
+            "placement log: nr_records={} avg_latency_ns={}",
+            self.len(),
+            self.avg_latency_ns()
+        )?;
+        for p in self.iter() {
+            writeln!(
+                w,
+                "  pid={} cpu={} dsq={:#x} reason={} latency_ns={}",
+                p.pid,
+                p.cpu,
+                p.dsq_id,
+                p.reason,
+                p.latency_ns()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl ScxDebugDump for BpfProgramStats {
+    fn dump(&self, w: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            w,
+            "bpf programs: run_cnt={} run_time_ns={} avg_run_time_ns={}",
+            self.run_cnt,
+            self.run_time_ns,
+            self.avg_run_time_ns()
+        )?;
+        let mut programs: Vec<_> = self.programs().iter().collect();
+        programs.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, stat) in programs {
+            writeln!(
+                w,
+                "  {}: run_cnt={} run_time_ns={} avg_run_time_ns={}",
+                name,
+                stat.run_cnt,
+                stat.run_time_ns,
+                stat.avg_run_time_ns()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl ScxDebugDump for LoadLedger {
+    fn dump(&self, w: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            w,
+            "load: global_load_sum={:.2} global_dcycle_sum={:.2} effective_max_weight={:.2}",
+            self.global_load_sum(),
+            self.global_dcycle_sum(),
+            self.effective_max_weight()
+        )?;
+        for (dom, (load, dcycle)) in self
+            .dom_load_sums()
+            .iter()
+            .zip(self.dom_dcycle_sums().iter())
+            .enumerate()
+        {
+            writeln!(
+                w,
+                "  dom {}: load_sum={:.2} dcycle_sum={:.2}",
+                dom, load, dcycle
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub struct DumpAll {
+    objs: Vec<Box<dyn ScxDebugDump>>,
+}
+
+impl DumpAll {
+    /// Create a DumpAll which dumps @objs in order.
+    pub fn new(objs: Vec<Box<dyn ScxDebugDump>>) -> DumpAll {
+        DumpAll { objs }
+    }
+
+    /// Append @obj to the objects to dump.
+    pub fn push(&mut self, obj: Box<dyn ScxDebugDump>) {
+        self.objs.push(obj);
+    }
+}
+
+impl ScxDebugDump for DumpAll {
+    fn dump(&self, w: &mut dyn Write) -> io::Result<()> {
+        for obj in self.objs.iter() {
+            obj.dump(w)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DumpAll;
+    use super::ScxDebugDump;
+    use crate::Cpumask;
+
+    #[test]
+    fn test_dump_all_in_order() {
+        let empty = Cpumask::new().unwrap();
+        let mut full = empty.clone();
+        full.setall();
+
+        let dump = DumpAll::new(vec![Box::new(empty), Box::new(full)]);
+        let mut buf = vec![];
+        dump.dump(&mut buf).unwrap();
+
+        let out = String::from_utf8(buf).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("weight=0"));
+        assert!(!lines[1].ends_with("weight=0"));
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Dispatch Hotness
+//!
+//! A crate that allows work-stealing schedulers to tell CPUs which recently
+//! dispatched many tasks from those which dispatched few, e.g. to prefer
+//! stealing from cold CPUs.
+//!
+//! ScxCpuHotnessMap
+//! ----------------
+//!
+//! A ScxCpuHotnessMap keeps a counter of dispatches per CPU. To only reflect
+//! recent dispatches, the counters are decayed periodically, which turns
+//! them into an exponentially weighted sliding window. The decay can either
+//! be applied explicitly with decay_all(), or at a fixed interval by calling
+//! tick() from the scheduler's main loop:
+//!
+//!```
+//!     let mut hotness = ScxCpuHotnessMap::new(nr_cpus);
+//!     hotness.set_decay(Duration::from_millis(100), 0.5);
+//!
+//!     loop {
+//!         for cpu in read_dispatch_events()? {
+//!             hotness.increment(cpu)?;
+//!         }
+//!         hotness.tick(Instant::now());
+//!
+//!         if let Some(victim) = hotness.coldest_in_mask(&dom_mask) {
+//!             steal_from(victim);
+//!         }
+//!     }
+//!```
+
+use crate::Cpumask;
+use anyhow::bail;
+use anyhow::Result;
+use std::time::Duration;
+use std::time::Instant;
+
+const DEFAULT_DECAY_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_DECAY_FACTOR: f32 = 0.5;
+
+#[derive(Debug, Clone)]
+pub struct ScxCpuHotnessMap {
+    counters: Vec<u32>,
+    decay_interval: Duration,
+    decay_factor: f32,
+    last_decay: Option<Instant>,
+}
+
+impl ScxCpuHotnessMap {
+    /// Create a ScxCpuHotnessMap for @nr_cpus CPUs with all counters at 0.
+    /// tick() halves the counters every second.
+    pub fn new(nr_cpus: usize) -> ScxCpuHotnessMap {
+        ScxCpuHotnessMap {
+            counters: vec![0; nr_cpus],
+            decay_interval: DEFAULT_DECAY_INTERVAL,
+            decay_factor: DEFAULT_DECAY_FACTOR,
+            last_decay: None,
+        }
+    }
+
+    /// Make tick() multiply the counters by @factor, between 0.0 and 1.0,
+    /// every @interval. An interval of 0 disables the decay by tick().
+    pub fn set_decay(&mut self, interval: Duration, factor: f32) {
+        self.decay_interval = interval;
+        self.decay_factor = factor.clamp(0.0, 1.0);
+    }
+
+    /// Count a dispatch on @cpu. Returns an error if the CPU doesn't exist.
+    pub fn increment(&mut self, cpu: usize) -> Result<()> {
+        match self.counters.get_mut(cpu) {
+            Some(cnt) => *cnt = cnt.saturating_add(1),
+            None => bail!("CPU {} out of range of {} CPUs", cpu, self.counters.len()),
+        }
+        Ok(())
+    }
+
+    /// Multiply all counters by @factor, between 0.0 and 1.0, rounding down.
+    pub fn decay_all(&mut self, factor: f32) {
+        let factor = factor.clamp(0.0, 1.0);
+        for cnt in self.counters.iter_mut() {
+            *cnt = (*cnt as f32 * factor) as u32;
+        }
+    }
+
+    /// Decay the counters once for each decay interval elapsed since the
+    /// last decay. See set_decay(). The first call only starts the first
+    /// interval.
+    pub fn tick(&mut self, now: Instant) {
+        if self.decay_interval.is_zero() {
+            return;
+        }
+        let last = match self.last_decay {
+            Some(last) => last,
+            None => {
+                self.last_decay = Some(now);
+                return;
+            }
+        };
+
+        let elapsed = now.saturating_duration_since(last);
+        let nr_intervals =
+            (elapsed.as_nanos() / self.decay_interval.as_nanos()).min(i32::MAX as u128) as i32;
+        if nr_intervals > 0 {
+            self.decay_all(self.decay_factor.powi(nr_intervals));
+            self.last_decay = Some(last + self.decay_interval * nr_intervals as u32);
+        }
+    }
+
+    /// Get the counter of @cpu.
+    pub fn hotness(&self, cpu: usize) -> Option<u32> {
+        self.counters.get(cpu).copied()
+    }
+
+    // Find the CPU of @mask whose counter is preferred by @better, breaking
+    // ties in favor of the lowest CPU.
+    fn find_in_mask<F: Fn(u32, u32) -> bool>(&self, mask: &Cpumask, better: F) -> Option<usize> {
+        let mut found: Option<(usize, u32)> = None;
+        mask.for_each_set_cpu(|cpu| {
+            if let Some(cnt) = self.hotness(cpu) {
+                match found {
+                    Some((_, best)) if !better(cnt, best) => {}
+                    _ => found = Some((cpu, cnt)),
+                }
+            }
+        });
+        found.map(|(cpu, _)| cpu)
+    }
+
+    /// Get the CPU of @mask with the lowest counter, or None if @mask has no
+    /// CPUs tracked by the ScxCpuHotnessMap. Ties are broken in favor of the
+    /// lowest CPU.
+    pub fn coldest_in_mask(&self, mask: &Cpumask) -> Option<usize> {
+        self.find_in_mask(mask, |cnt, best| cnt < best)
+    }
+
+    /// Get the CPU of @mask with the highest counter, or None if @mask has
+    /// no CPUs tracked by the ScxCpuHotnessMap. Ties are broken in favor of
+    /// the lowest CPU.
+    pub fn hottest_in_mask(&self, mask: &Cpumask) -> Option<usize> {
+        self.find_in_mask(mask, |cnt, best| cnt > best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScxCpuHotnessMap;
+    use crate::Cpumask;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    #[test]
+    fn test_hotness() {
+        let mut hotness = ScxCpuHotnessMap::new(8);
+        for (cpu, cnt) in [(1, 4), (2, 8), (3, 8), (5, 2)] {
+            for _ in 0..cnt {
+                hotness.increment(cpu).unwrap();
+            }
+        }
+        assert!(hotness.increment(8).is_err());
+
+        let mask = Cpumask::from_iter_with_capacity([1, 2, 3, 5], 8).unwrap();
+        assert_eq!(hotness.coldest_in_mask(&mask), Some(5));
+        assert_eq!(hotness.hottest_in_mask(&mask), Some(2));
+        let empty = Cpumask::from_iter_with_capacity([], 8).unwrap();
+        assert_eq!(hotness.coldest_in_mask(&empty), None);
+
+        hotness.decay_all(0.5);
+        assert_eq!(hotness.hotness(2), Some(4));
+        assert_eq!(hotness.hotness(5), Some(1));
+    }
+
+    #[test]
+    fn test_hotness_tick() {
+        let mut hotness = ScxCpuHotnessMap::new(1);
+        hotness.set_decay(Duration::from_millis(100), 0.5);
+        for _ in 0..64 {
+            hotness.increment(0).unwrap();
+        }
+
+        let start = Instant::now();
+        hotness.tick(start);
+        hotness.tick(start + Duration::from_millis(50));
+        assert_eq!(hotness.hotness(0), Some(64));
+        hotness.tick(start + Duration::from_millis(250));
+        assert_eq!(hotness.hotness(0), Some(16));
+        hotness.tick(start + Duration::from_millis(300));
+        assert_eq!(hotness.hotness(0), Some(8));
+    }
+}
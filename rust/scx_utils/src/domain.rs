@@ -0,0 +1,104 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Domains
+//!
+//! A crate that allows schedulers to keep track of the scheduling domains
+//! they partition the CPUs of the host into, e.g. one domain per LLC.
+//!
+//! DomainSet
+//! ---------
+//!
+//! A DomainSet pairs each LocalDomainId with the Cpumask of the CPUs in the
+//! domain. As schedulers frequently need to look up which domain a CPU
+//! belongs to, e.g. on every task migration, a DomainSet builds a per-CPU
+//! lookup table at construction time so that domain_of() is O(1):
+//!
+//!```
+//!     let set = DomainSet::new(vec![
+//!         (LocalDomainId(0), Cpumask::from_cpu_list("0-7")?),
+//!         (LocalDomainId(1), Cpumask::from_cpu_list("8-15")?),
+//!     ])?;
+//!
+//!     assert_eq!(set.domain_of(9), Some(LocalDomainId(1)));
+//!```
+
+use crate::Cpumask;
+use anyhow::bail;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LocalDomainId(pub u32);
+
+impl fmt::Display for LocalDomainId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DomainSet {
+    domains: BTreeMap<LocalDomainId, Cpumask>,
+    cpu_to_domain: Vec<Option<LocalDomainId>>,
+}
+
+impl DomainSet {
+    /// Build a DomainSet from a list of domains and their CPUs. Returns an
+    /// error if a domain ID is used more than once, or if a CPU is in more
+    /// than one domain. CPUs which aren't in any domain are allowed.
+    pub fn new(domains: Vec<(LocalDomainId, Cpumask)>) -> Result<DomainSet> {
+        let nr_cpus = domains
+            .iter()
+            .map(|(_, mask)| mask.len())
+            .max()
+            .unwrap_or(0);
+        let mut cpu_to_domain = vec![None; nr_cpus];
+        let mut map = BTreeMap::new();
+
+        for (id, mask) in domains.into_iter() {
+            for cpu in mask.clone().into_iter() {
+                if let Some(other) = cpu_to_domain[cpu] {
+                    bail!("CPU {} is in both domain {} and domain {}", cpu, other, id);
+                }
+                cpu_to_domain[cpu] = Some(id);
+            }
+            if map.insert(id, mask).is_some() {
+                bail!("Domain {} is specified more than once", id);
+            }
+        }
+
+        Ok(DomainSet {
+            domains: map,
+            cpu_to_domain,
+        })
+    }
+
+    /// Get the domain @cpu belongs to, or None if it isn't in any domain.
+    pub fn domain_of(&self, cpu: usize) -> Option<LocalDomainId> {
+        self.cpu_to_domain.get(cpu).copied().flatten()
+    }
+
+    /// Get the Cpumask of domain @id.
+    pub fn cpumask(&self, id: LocalDomainId) -> Option<&Cpumask> {
+        self.domains.get(&id)
+    }
+
+    /// Get the map of <domain ID, Cpumask> for all domains.
+    pub fn domains(&self) -> &BTreeMap<LocalDomainId, Cpumask> {
+        &self.domains
+    }
+
+    /// The number of domains.
+    pub fn len(&self) -> usize {
+        self.domains.len()
+    }
+
+    /// Test whether the DomainSet contains no domains.
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+}
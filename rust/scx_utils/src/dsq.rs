@@ -0,0 +1,189 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Dispatch Queues
+//!
+//! A crate that allows schedulers to manage the IDs of the dispatch queues
+//! (DSQs) they dispatch tasks to, rather than passing raw integers around.
+//!
+//! DsqId
+//! -----
+//!
+//! A DsqId is a typed DSQ ID as passed to e.g. scx_bpf_dispatch(). The
+//! built-in DSQs are available as constants, and the local DSQ of a specific
+//! CPU can be built with DsqId::local_on():
+//!
+//!```
+//!     assert!(DSQ_GLOBAL.is_builtin());
+//!     assert!(DsqId::local_on(3).is_local_for(3));
+//!```
+//!
+//! DsqRegistry
+//! -----------
+//!
+//! A DsqRegistry allocates IDs for the custom DSQs created by a scheduler,
+//! and makes sure that no two DSQs share an ID or a name:
+//!
+//!```
+//!     let mut registry = DsqRegistry::new();
+//!     let interactive = registry.create("interactive", 10)?;
+//!     let batch = registry.create("batch", 0)?;
+//!
+//!     // Custom DSQs, from highest to lowest priority.
+//!     for dsq in registry.by_priority() {
+//!         info!("{} id={} prio={}", dsq.name(), dsq.id(), dsq.priority());
+//!     }
+//!```
+
+// bindgen types the DSQ ID flags as c_ulong, which is only u64 on 64bit archs.
+#![allow(clippy::unnecessary_cast)]
+
+use crate::bindings;
+use anyhow::bail;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt;
+
+const DSQ_FLAG_BUILTIN: u64 = bindings::scx_dsq_id_flags_SCX_DSQ_FLAG_BUILTIN as u64;
+const DSQ_FLAG_LOCAL_ON: u64 = bindings::scx_dsq_id_flags_SCX_DSQ_FLAG_LOCAL_ON as u64;
+const DSQ_LOCAL_CPU_MASK: u64 = bindings::scx_dsq_id_flags_SCX_DSQ_LOCAL_CPU_MASK as u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DsqId(pub u64);
+
+/// The built-in global FIFO DSQ.
+pub const DSQ_GLOBAL: DsqId = DsqId(bindings::scx_dsq_id_flags_SCX_DSQ_GLOBAL as u64);
+
+/// The built-in local DSQ of the CPU the dispatch is performed on.
+pub const DSQ_LOCAL: DsqId = DsqId(bindings::scx_dsq_id_flags_SCX_DSQ_LOCAL as u64);
+
+impl DsqId {
+    /// Build the ID of the local DSQ of the specified CPU.
+    pub fn local_on(cpu: usize) -> DsqId {
+        DsqId(DSQ_FLAG_BUILTIN | DSQ_FLAG_LOCAL_ON | (cpu as u64 & DSQ_LOCAL_CPU_MASK))
+    }
+
+    /// Is this one of the DSQs built into the kernel?
+    pub fn is_builtin(&self) -> bool {
+        self.0 & DSQ_FLAG_BUILTIN != 0
+    }
+
+    /// Is this the local DSQ of the specified CPU, as built by local_on()?
+    /// Note that DSQ_LOCAL doesn't refer to any specific CPU and thus never
+    /// matches.
+    pub fn is_local_for(&self, cpu: usize) -> bool {
+        *self == Self::local_on(cpu)
+    }
+}
+
+impl fmt::Display for DsqId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScxDispatchQueue {
+    id: DsqId,
+    name: String,
+    priority: u32,
+}
+
+impl ScxDispatchQueue {
+    /// Get the ID of this DSQ
+    pub fn id(&self) -> DsqId {
+        self.id
+    }
+
+    /// Get the name of this DSQ
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the priority level of this DSQ. Higher values are to be consumed
+    /// first.
+    pub fn priority(&self) -> u32 {
+        self.priority
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DsqRegistry {
+    dsqs: BTreeMap<DsqId, ScxDispatchQueue>,
+    next_id: u64,
+}
+
+impl DsqRegistry {
+    /// Create an empty DsqRegistry. IDs are allocated starting from 0.
+    pub fn new() -> DsqRegistry {
+        Self::default()
+    }
+
+    /// Create a custom DSQ with the next free ID.
+    pub fn create(&mut self, name: &str, priority: u32) -> Result<ScxDispatchQueue> {
+        while self.dsqs.contains_key(&DsqId(self.next_id)) {
+            self.next_id += 1;
+        }
+        if self.next_id & DSQ_FLAG_BUILTIN != 0 {
+            bail!("Ran out of custom DSQ IDs");
+        }
+
+        self.register(DsqId(self.next_id), name, priority)
+    }
+
+    /// Register a custom DSQ with a caller-chosen ID, e.g. one that is
+    /// hard-coded on the BPF side. Returns an error if the ID is a built-in
+    /// DSQ ID, or if the ID or name is already in use.
+    pub fn register(&mut self, id: DsqId, name: &str, priority: u32) -> Result<ScxDispatchQueue> {
+        if id.is_builtin() {
+            bail!("DSQ ID {} of {:?} is a built-in DSQ ID", id, name);
+        }
+        if let Some(dsq) = self.dsqs.get(&id) {
+            bail!(
+                "DSQ ID {} of {:?} is already used by {:?}",
+                id,
+                name,
+                dsq.name
+            );
+        }
+        if self.dsqs.values().any(|dsq| dsq.name == name) {
+            bail!("DSQ name {:?} is already in use", name);
+        }
+
+        let dsq = ScxDispatchQueue {
+            id,
+            name: name.to_string(),
+            priority,
+        };
+        self.dsqs.insert(id, dsq.clone());
+        Ok(dsq)
+    }
+
+    /// Remove a custom DSQ from the registry, making its ID and name
+    /// available again.
+    pub fn unregister(&mut self, id: DsqId) -> Option<ScxDispatchQueue> {
+        let dsq = self.dsqs.remove(&id);
+        self.next_id = self.next_id.min(id.0);
+        dsq
+    }
+
+    /// Look up a custom DSQ by ID.
+    pub fn get(&self, id: DsqId) -> Option<&ScxDispatchQueue> {
+        self.dsqs.get(&id)
+    }
+
+    /// Look up a custom DSQ by name.
+    pub fn find(&self, name: &str) -> Option<&ScxDispatchQueue> {
+        self.dsqs.values().find(|dsq| dsq.name == name)
+    }
+
+    /// Get all custom DSQs sorted from highest to lowest priority. DSQs of
+    /// the same priority are sorted by ID.
+    pub fn by_priority(&self) -> Vec<&ScxDispatchQueue> {
+        let mut dsqs: Vec<&ScxDispatchQueue> = self.dsqs.values().collect();
+        dsqs.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.id.cmp(&b.id)));
+        dsqs
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX BPF Event Log
+//!
+//! A crate that allows schedulers to keep the last events leading up to a
+//! crash of the BPF scheduler, e.g. the events read from a BPF ring buffer,
+//! so that they can be dumped for post-mortem analysis.
+//!
+//! ScxBpfEventLog
+//! --------------
+//!
+//! A ScxBpfEventLog holds a fixed number of events of any plain-old-data
+//! type in a ring buffer. Recording an event never blocks and never
+//! allocates. Once the ring buffer is full, the oldest events are
+//! overwritten:
+//!
+//!```
+//!     #[repr(C)]
+//!     #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+//!     struct Event {
+//!         ts_ns: u64,
+//!         pid: u32,
+//!         cpu: u32,
+//!     }
+//!
+//!     let log = Arc::new(ScxBpfEventLog::<Event>::new(4096));
+//!     log.push(event);
+//!
+//!     // Later, e.g. after the BPF scheduler exited with an error.
+//!     log.write_to_file(Path::new("/var/tmp/scx_events.bin"))?;
+//!```
+//!
+//! Unlike ScxAuditLog, events can be read back while other threads keep
+//! pushing. Each slot of the ring buffer carries the sequence number of the
+//! event it holds, which readers check before and after copying the event
+//! out, and events which are being overwritten while being read are
+//! skipped. As with ScxAuditLog, writers claim a slot with a
+//! compare-and-swap before writing the event, and drop the event instead of
+//! waiting if another writer still holds the slot.
+
+use crate::fs::write_atomic;
+use anyhow::Context;
+use anyhow::Result;
+use std::cell::UnsafeCell;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::fence;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+// The sequence number of a slot which is being written.
+const SLOT_BUSY: usize = usize::MAX;
+
+struct Slot<E> {
+    // The sequence number of the event in the slot + 1, 0 if the slot was
+    // never written, or SLOT_BUSY.
+    seq: AtomicUsize,
+    event: UnsafeCell<E>,
+}
+
+pub struct ScxBpfEventLog<E: bytemuck::Pod + Send> {
+    slots: Box<[Slot<E>]>,
+    // The sequence number of the next event to push.
+    head: AtomicUsize,
+    // The sequence number of the oldest event not drained yet.
+    tail: AtomicUsize,
+}
+
+// Writers only write a slot after claiming it by swapping its sequence number
+// to SLOT_BUSY, and readers validate the slots they copy with their sequence
+// numbers. See the module documentation.
+unsafe impl<E: bytemuck::Pod + Send> Sync for ScxBpfEventLog<E> {}
+
+impl<E: bytemuck::Pod + Send> ScxBpfEventLog<E> {
+    /// Create a ScxBpfEventLog which holds up to @nr_events events.
+    pub fn new(nr_events: usize) -> ScxBpfEventLog<E> {
+        let nr_events = nr_events.max(1);
+        ScxBpfEventLog {
+            slots: (0..nr_events)
+                .map(|_| Slot {
+                    seq: AtomicUsize::new(0),
+                    event: UnsafeCell::new(E::zeroed()),
+                })
+                .collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// The maximum number of events held by the log.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Record an event, overwriting the oldest one if the log is full. The
+    /// event is dropped if another writer is still writing its slot.
+    pub fn push(&self, event: E) {
+        let seq = self.head.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[seq % self.capacity()];
+
+        // Don't wait for a concurrent writer, nor overwrite a newer event.
+        let cur = slot.seq.load(Ordering::Relaxed);
+        if cur == SLOT_BUSY || cur > seq + 1 {
+            return;
+        }
+        if slot
+            .seq
+            .compare_exchange(cur, SLOT_BUSY, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+        // Order the claim before the event for readers, as in a seqlock.
+        fence(Ordering::Release);
+        unsafe { std::ptr::write_volatile(slot.event.get(), event) };
+        slot.seq.store(seq + 1, Ordering::Release);
+    }
+
+    // Copy the events with sequence numbers @start..@end out of the ring
+    // buffer, skipping those which aren't in their slot (anymore).
+    fn read_range(&self, start: usize, end: usize) -> Vec<E> {
+        let start = start.max(end.saturating_sub(self.capacity()));
+        let mut events = Vec::with_capacity(end - start);
+        for seq in start..end {
+            let slot = &self.slots[seq % self.capacity()];
+            if slot.seq.load(Ordering::Acquire) != seq + 1 {
+                continue;
+            }
+            let event = unsafe { std::ptr::read_volatile(slot.event.get()) };
+            fence(Ordering::Acquire);
+            if slot.seq.load(Ordering::Relaxed) == seq + 1 {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Remove all events held by the log and return them, from oldest to
+    /// newest. Events pushed concurrently may or may not be included.
+    pub fn drain_to_vec(&self) -> Vec<E> {
+        let end = self.head.load(Ordering::Acquire);
+        let start = self.tail.fetch_max(end, Ordering::AcqRel);
+        self.read_range(start, end.max(start))
+    }
+
+    /// Write all events held by the log, from oldest to newest, to @path as
+    /// the raw bytes of each event back to back, replacing the file
+    /// atomically. The events are not removed from the log.
+    pub fn write_to_file(&self, path: &Path) -> Result<()> {
+        let end = self.head.load(Ordering::Acquire);
+        let start = self.tail.load(Ordering::Acquire);
+        let events = self.read_range(start, end.max(start));
+        write_atomic(path, |file| {
+            file.write_all(bytemuck::cast_slice(&events))
+                .with_context(|| format!("Failed to write events to {:?}", path))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScxBpfEventLog;
+
+    #[test]
+    fn test_event_log_drain() {
+        let log = ScxBpfEventLog::<u64>::new(4);
+        for ev in 0..6 {
+            log.push(ev);
+        }
+        assert_eq!(log.drain_to_vec(), vec![2, 3, 4, 5]);
+        assert!(log.drain_to_vec().is_empty());
+
+        log.push(6);
+        assert_eq!(log.drain_to_vec(), vec![6]);
+    }
+
+    #[test]
+    fn test_event_log_concurrent_push() {
+        let log = ScxBpfEventLog::<u64>::new(1);
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let log = &log;
+                s.spawn(move || {
+                    for ev in 0..1000 {
+                        log.push(t * 1000 + ev);
+                    }
+                });
+            }
+        });
+
+        // The last event may have been dropped if its slot was still busy.
+        let events = log.drain_to_vec();
+        assert!(events.len() <= 1);
+        assert!(events.iter().all(|ev| *ev < 4000));
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Topology Exporter
+//!
+//! A crate that allows schedulers to publish the topology of the host they
+//! run on, so that monitoring systems can make sense of per-CPU and
+//! per-domain stats without access to the host.
+//!
+//! ScxTopologyExporter
+//! -------------------
+//!
+//! ScxTopologyExporter writes JSON files which are replaced atomically, so
+//! that readers never see a partially written file:
+//!
+//!```
+//!     let top = Topology::new()?;
+//!     ScxTopologyExporter::export(&top, Path::new("/run/scx/topology.json"))?;
+//!     ScxTopologyExporter::export_cpumask(&domain_mask, "dom0", Path::new("/run/scx/domains.json"))?;
+//!```
+//!
+//! The topology is written in the snapshot format of Topology::serialize(),
+//! and can be loaded back with Topology::from_snapshot(). This requires the
+//! serde_json feature.
+
+use crate::fs::write_atomic;
+use crate::Cpumask;
+use crate::Topology;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct ScxTopologyExporter;
+
+impl ScxTopologyExporter {
+    /// Write @topology to @path as JSON, replacing the file atomically.
+    pub fn export(topology: &Topology, path: &Path) -> Result<()> {
+        write_atomic(path, |file| topology.serialize(file))
+    }
+
+    /// Set @key of the JSON object at @path to the CPUs of @mask, replacing
+    /// the file atomically. Other keys of the object are preserved, so that
+    /// several masks can be exported to the same file. If the file doesn't
+    /// exist, a new object is created. If it exists but doesn't hold a JSON
+    /// object, an error is returned and the file is left untouched.
+    pub fn export_cpumask(mask: &Cpumask, key: &str, path: &Path) -> Result<()> {
+        let mut obj = match std::fs::read_to_string(path) {
+            Ok(s) => match serde_json::from_str::<serde_json::Value>(&s)
+                .with_context(|| format!("Failed to parse {:?} as JSON", path))?
+            {
+                serde_json::Value::Object(obj) => obj,
+                _ => bail!("{:?} doesn't hold a JSON object", path),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => serde_json::Map::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        obj.insert("nr_cpus".to_string(), mask.len().into());
+        obj.insert(key.to_string(), mask.to_json_array());
+
+        write_atomic(path, |file| {
+            serde_json::to_writer_pretty(file, &obj)?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScxTopologyExporter;
+    use crate::Cpumask;
+
+    #[test]
+    fn test_export_cpumask_invalid_json() {
+        let dir = std::env::temp_dir().join(format!("scx_export.{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("domains.json");
+        let mask = Cpumask::from_iter_with_capacity(vec![0, 2], 4).unwrap();
+
+        ScxTopologyExporter::export_cpumask(&mask, "dom0", &path).unwrap();
+        ScxTopologyExporter::export_cpumask(&mask, "dom1", &path).unwrap();
+        let obj: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(obj.get("dom0").is_some() && obj.get("dom1").is_some());
+
+        std::fs::write(&path, "not json").unwrap();
+        let err = ScxTopologyExporter::export_cpumask(&mask, "dom0", &path).unwrap_err();
+        assert!(format!("{}", err).contains("domains.json"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not json");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,46 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX File Helpers
+//!
+//! Helpers for the files the crate writes on behalf of schedulers, e.g.
+//! exported topologies and dumped event logs.
+
+use anyhow::Context;
+use anyhow::Result;
+use std::io::Write;
+use std::path::Path;
+
+/// Write @path atomically by calling @f on a temporary file in the same
+/// directory and renaming it over @path, so that readers never see a
+/// partially written file. The temporary file is removed on failure.
+pub(crate) fn write_atomic<F>(path: &Path, f: F) -> Result<()>
+where
+    F: FnOnce(&mut std::fs::File) -> Result<()>,
+{
+    let mut tmp_name = path
+        .file_name()
+        .with_context(|| format!("Invalid path {:?}", path))?
+        .to_os_string();
+    tmp_name.push(format!(".tmp.{}", std::process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let res = std::fs::File::create(&tmp_path)
+        .with_context(|| format!("Failed to create {:?}", tmp_path))
+        .and_then(|mut file| {
+            f(&mut file)?;
+            file.flush()?;
+            file.sync_all()?;
+            Ok(())
+        })
+        .and_then(|_| {
+            std::fs::rename(&tmp_path, path)
+                .with_context(|| format!("Failed to rename {:?} to {:?}", tmp_path, path))
+        });
+    if res.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    res
+}
@@ -0,0 +1,127 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX BPF Map Reclaimer
+//!
+//! A crate that allows long-running schedulers to remove the entries of
+//! exited tasks from BPF maps keyed by PID, which would otherwise grow until
+//! they are full.
+//!
+//! ScxBpfMapReclaimer
+//! ------------------
+//!
+//! ScxBpfMapReclaimer::reclaim() walks a BPF_MAP_TYPE_HASH or
+//! BPF_MAP_TYPE_LRU_HASH map keyed by a u32 PID and deletes the entries of
+//! all PIDs which no longer have a /proc/[pid] directory.
+//! ScxBpfMapReclaimer::spawn_auto_reclaim() does the same from a background
+//! thread every interval. The thread is stopped when the returned Reclaimer
+//! is dropped:
+//!
+//!```
+//!     let _reclaimer = ScxBpfMapReclaimer::spawn_auto_reclaim(
+//!         Duration::from_secs(10),
+//!         skel.maps().task_ctxs(),
+//!     )?;
+//!```
+//!
+//! Both thread IDs and process IDs work as keys, as /proc has a directory
+//! for each thread. If a PID is reused before the map is reclaimed, the new
+//! task inherits the stale entry, so the BPF side should still initialize
+//! the entry of a new task, e.g. in ops.init_task(). Where possible, BPF
+//! task storage maps should be used instead, which the kernel cleans up on
+//! task exit.
+
+use crate::worker::PeriodicThread;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use libbpf_rs::ErrorKind;
+use libbpf_rs::Map;
+use libbpf_rs::MapHandle;
+use libbpf_rs::MapType;
+use std::path::Path;
+use std::time::Duration;
+
+/// Stops the reclaimer thread when dropped. See
+/// ScxBpfMapReclaimer::spawn_auto_reclaim().
+#[derive(Debug)]
+pub struct Reclaimer {
+    _thread: PeriodicThread,
+}
+
+#[derive(Debug)]
+pub struct ScxBpfMapReclaimer;
+
+fn check_map(map: &MapHandle) -> Result<()> {
+    if !matches!(map.map_type(), MapType::Hash | MapType::LruHash) {
+        bail!(
+            "Map {} is not a BPF_MAP_TYPE_HASH or BPF_MAP_TYPE_LRU_HASH",
+            map.name()
+        );
+    }
+    if map.key_size() as usize != std::mem::size_of::<u32>() {
+        bail!(
+            "Map {} has key size {}, expected {}",
+            map.name(),
+            map.key_size(),
+            std::mem::size_of::<u32>()
+        );
+    }
+    Ok(())
+}
+
+fn reclaim_stale(map: &MapHandle) -> Result<usize> {
+    // Collect the keys first, as deleting while walking the map may restart
+    // the walk from the beginning.
+    let stale: Vec<Vec<u8>> = map
+        .keys()
+        .filter(|key| {
+            let pid = u32::from_ne_bytes(key[..4].try_into().unwrap());
+            !Path::new(&format!("/proc/{}", pid)).exists()
+        })
+        .collect();
+
+    let mut nr_deleted = 0;
+    for key in stale.iter() {
+        match map.delete(key) {
+            Ok(()) => nr_deleted += 1,
+            // The BPF side may have deleted the entry in the meantime.
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to delete entry of {}", map.name()))
+            }
+        }
+    }
+    Ok(nr_deleted)
+}
+
+impl ScxBpfMapReclaimer {
+    /// Delete the entries of @map whose PID no longer exists and return the
+    /// number of deleted entries. Returns an error if @map isn't a hash map
+    /// keyed by u32.
+    pub fn reclaim(map: &Map) -> Result<usize> {
+        check_map(map)?;
+        reclaim_stale(map)
+    }
+
+    /// Spawn a thread which calls reclaim() on @map every @interval. Errors
+    /// are logged, and reclaiming continues with the next interval.
+    pub fn spawn_auto_reclaim(interval: Duration, map: &Map) -> Result<Reclaimer> {
+        check_map(map)?;
+        // Map can't be shared across threads, use a handle of its own instead.
+        let map = MapHandle::try_clone(map)
+            .with_context(|| format!("Failed to clone handle of map {}", map.name()))?;
+        let name = format!("scx_gc_{}", map.name());
+
+        let thread = PeriodicThread::spawn(name, interval, false, move || {
+            if let Err(e) = reclaim_stale(&map) {
+                log::warn!("Failed to reclaim entries of {}: {:#}", map.name(), e);
+            }
+        })
+        .context("Failed to spawn map reclaimer thread")?;
+
+        Ok(Reclaimer { _thread: thread })
+    }
+}
@@ -0,0 +1,119 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Task Group
+//!
+//! A crate that allows schedulers to manage a cohort of related processes
+//! as one, e.g. the processes of a game or of a batch job which should be
+//! scheduled together.
+//!
+//! ScxTaskGroup
+//! ------------
+//!
+//! A ScxTaskGroup is a set of PIDs, for which the CPU affinity and CPU time
+//! can be queried in aggregate, and whose CPU affinity can be set at once:
+//!
+//!```
+//!     let mut group = ScxTaskGroup::new();
+//!     for pid in game_pids {
+//!         group.add_pid(pid);
+//!     }
+//!
+//!     group.set_group_affinity(&game_cpus)?;
+//!     info!("game used {}us of CPU time", group.total_cpu_time_us());
+//!```
+//!
+//! Members are not removed automatically when they exit. Exited members are
+//! skipped when querying or setting the group, but should be removed with
+//! remove_pid(), as the PID may be reused by an unrelated process.
+
+use crate::task::ProcStat;
+use crate::Cpumask;
+use anyhow::Result;
+use std::collections::HashSet;
+
+// Read the user and system CPU time of @pid in microseconds.
+fn read_cpu_time_us(pid: u32) -> Result<u64> {
+    Ok(ProcStat::read(pid as i32)?.cpu_time_ns() / 1000)
+}
+
+// Test whether @e was caused by the process having exited.
+fn is_esrch(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<std::io::Error>() {
+        Some(e) => e.raw_os_error() == Some(libc::ESRCH),
+        None => false,
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ScxTaskGroup {
+    pids: HashSet<u32>,
+}
+
+impl ScxTaskGroup {
+    /// Create an empty ScxTaskGroup.
+    pub fn new() -> ScxTaskGroup {
+        Default::default()
+    }
+
+    /// Add @pid to the group. Returns whether it wasn't a member yet.
+    pub fn add_pid(&mut self, pid: u32) -> bool {
+        self.pids.insert(pid)
+    }
+
+    /// Remove @pid from the group. Returns whether it was a member.
+    pub fn remove_pid(&mut self, pid: u32) -> bool {
+        self.pids.remove(&pid)
+    }
+
+    /// Get the PIDs of the members.
+    pub fn pids(&self) -> &HashSet<u32> {
+        &self.pids
+    }
+
+    /// Get the union of the CPU affinities of all members. Exited members
+    /// are skipped. Returns an error if the affinity of a running member
+    /// can't be read.
+    pub fn current_affinity(&self) -> Result<Cpumask> {
+        let mut mask = Cpumask::new()?;
+        for &pid in self.pids.iter() {
+            match Cpumask::current_affinity(pid as i32) {
+                Ok(affinity) => mask.or_in_place(&affinity)?,
+                Err(e) if is_esrch(&e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(mask)
+    }
+
+    /// Get the sum of the user and system CPU time of all members in
+    /// microseconds, as accounted in USER_HZ ticks by the kernel. Exited
+    /// members are skipped.
+    pub fn total_cpu_time_us(&self) -> u64 {
+        self.pids
+            .iter()
+            .filter_map(|&pid| read_cpu_time_us(pid).ok())
+            .sum()
+    }
+
+    /// Set the CPU affinity of all members to @mask. Exited members are
+    /// skipped. If setting the affinity of a member fails, the remaining
+    /// members are still updated, and the first error is returned.
+    pub fn set_group_affinity(&self, mask: &Cpumask) -> Result<()> {
+        let mut result = Ok(());
+        for &pid in self.pids.iter() {
+            match mask.apply_affinity(pid as i32) {
+                Ok(()) => {}
+                Err(e) if is_esrch(&e) => {}
+                Err(e) => {
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Health Check
+//!
+//! A crate that allows schedulers to detect that their BPF side stopped
+//! making progress while still being loaded, e.g. because it stopped
+//! dispatching tasks.
+//!
+//! ScxHealthCheck
+//! --------------
+//!
+//! The BPF side periodically updates a u64 global variable, e.g. with
+//! bpf_ktime_get_ns() from ops.tick(). A ScxHealthCheck spawns a SCHED_FIFO
+//! thread, which therefore isn't scheduled by sched_ext and can run even if
+//! the BPF scheduler misbehaves, and which calls a callback whenever the
+//! variable doesn't change for longer than a deadline. The thread is stopped
+//! when the ScxHealthCheck is dropped:
+//!
+//!```
+//!     let heartbeat = unsafe {
+//!         BpfGlobalVar::new(&mut skel.bss_mut().heartbeat_ns as *mut u64)?
+//!     };
+//!     let _health = ScxHealthCheck::spawn(heartbeat, 1000, || {
+//!         error!("BPF scheduler stalled, exiting");
+//!         std::process::exit(1);
+//!     })?;
+//!```
+//!
+//! Only whether the variable changes matters, not its value, so any counter
+//! works as a heartbeat. Making the thread SCHED_FIFO requires CAP_SYS_NICE.
+//! Without it, the thread keeps running under the default policy, and a
+//! warning is logged.
+
+use crate::worker::PeriodicThread;
+use crate::BpfGlobalVar;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::time::Duration;
+use std::time::Instant;
+
+// How many times per deadline the heartbeat is checked
+const CHECKS_PER_DEADLINE: u32 = 4;
+
+#[derive(Debug)]
+pub struct ScxHealthCheck {
+    _thread: PeriodicThread,
+}
+
+impl ScxHealthCheck {
+    /// Spawn a thread which calls @on_stall if @heartbeat doesn't change for
+    /// longer than @max_stall_ms. @on_stall is called again every
+    /// @max_stall_ms for as long as the heartbeat stays stalled.
+    pub fn spawn<F>(
+        heartbeat: BpfGlobalVar<u64>,
+        max_stall_ms: u64,
+        on_stall: F,
+    ) -> Result<ScxHealthCheck>
+    where
+        F: Fn() + Send + 'static,
+    {
+        if max_stall_ms == 0 {
+            bail!("The maximum heartbeat stall must be at least 1ms");
+        }
+        let max_stall = Duration::from_millis(max_stall_ms);
+        let interval = max_stall / CHECKS_PER_DEADLINE;
+        let mut last_beat = heartbeat.get();
+        let mut last_change = Instant::now();
+
+        let thread =
+            PeriodicThread::spawn("scx_health_check".to_string(), interval, true, move || {
+                let beat = heartbeat.get();
+                let now = Instant::now();
+                if beat != last_beat {
+                    last_beat = beat;
+                    last_change = now;
+                } else if now.duration_since(last_change) > max_stall {
+                    on_stall();
+                    // Give the callback time to take effect before calling
+                    // it again.
+                    last_change = now;
+                }
+            })
+            .context("Failed to spawn health check thread")?;
+
+        Ok(ScxHealthCheck { _thread: thread })
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Kprobe Monitor
+//!
+//! A crate that allows scheduler developers to observe how often internal
+//! functions of the kernel scheduler are called, e.g. to see how a BPF
+//! scheduler changes the behavior of the core scheduler.
+//!
+//! ScxKprobeMonitor
+//! ----------------
+//!
+//! A ScxKprobeMonitor attaches a kprobe to each of a list of kernel
+//! functions. Each kprobe runs a small built-in BPF program which counts the
+//! calls of its function per CPU in a BPF_MAP_TYPE_PERCPU_ARRAY, so no BPF
+//! code is needed on the scheduler side. The kprobes are detached when the
+//! ScxKprobeMonitor is dropped:
+//!
+//!```
+//!     let monitor = ScxKprobeMonitor::new(&["enqueue_task", "dequeue_task"])?;
+//!     std::thread::sleep(Duration::from_secs(1));
+//!     for (func, counts) in monitor.invocation_counts()? {
+//!         info!("{}: {} calls on CPU 0", func, counts[0]);
+//!     }
+//!```
+//!
+//! Only functions which aren't inlined and aren't blacklisted for kprobes,
+//! as listed in /sys/kernel/debug/kprobes/blacklist, can be probed. This
+//! requires a kernel built with CONFIG_KPROBE_EVENTS, as well as CAP_BPF and
+//! CAP_PERFMON.
+
+use crate::sys::insn;
+use crate::sys::perf_event_open;
+use crate::sys::PerfEventAttr;
+use crate::sys::PERF_EVENT_IOC_ENABLE;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use libbpf_rs::libbpf_sys;
+use libbpf_rs::MapFlags;
+use libbpf_rs::MapHandle;
+use libbpf_rs::MapType;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+
+const KPROBE_PMU_TYPE_PATH: &str = "/sys/bus/event_source/devices/kprobe/type";
+
+// _IOW('$', 8, __u32)
+const PERF_EVENT_IOC_SET_BPF: libc::c_ulong = 0x40042408;
+
+// Build the program which counts the calls of the function at @idx of the
+// counts map. The equivalent C is:
+//
+//     u32 idx = IDX;
+//     u64 *cnt = bpf_map_lookup_elem(&counts, &idx);
+//     if (cnt)
+//         (*cnt)++;
+//     return 0;
+fn counter_prog(map_fd: i32, idx: u32) -> Vec<libbpf_sys::bpf_insn> {
+    use libbpf_sys::*;
+
+    vec![
+        insn(BPF_ST | BPF_W | BPF_MEM, 10, 0, -4, idx as i32),
+        insn(
+            BPF_LD | BPF_DW | BPF_IMM,
+            1,
+            BPF_PSEUDO_MAP_FD as u8,
+            0,
+            map_fd,
+        ),
+        insn(0, 0, 0, 0, 0),
+        insn(BPF_ALU64 | BPF_MOV | BPF_X, 2, 10, 0, 0),
+        insn(BPF_ALU64 | BPF_ADD | BPF_K, 2, 0, 0, -4),
+        insn(BPF_JMP | BPF_CALL, 0, 0, 0, BPF_FUNC_map_lookup_elem as i32),
+        // Skip the increment if NULL.
+        insn(BPF_JMP | BPF_JEQ | BPF_K, 0, 0, 2, 0),
+        insn(BPF_ALU64 | BPF_MOV | BPF_K, 1, 0, 0, 1),
+        // The map is per-CPU, but the kprobe may be hit in NMI context
+        // while the same CPU is already incrementing.
+        insn(BPF_STX | BPF_DW | BPF_ATOMIC, 0, 1, 0, BPF_ADD as i32),
+        insn(BPF_ALU64 | BPF_MOV | BPF_K, 0, 0, 0, 0),
+        insn(BPF_JMP | BPF_EXIT, 0, 0, 0, 0),
+    ]
+}
+
+#[derive(Debug)]
+struct Kprobe {
+    func: String,
+    // Closing the perf event detaches the program, so it's dropped first.
+    _perf: OwnedFd,
+    _prog: OwnedFd,
+}
+
+impl Kprobe {
+    fn attach(func: &str, idx: u32, counts: &MapHandle, pmu_type: u32) -> Result<Kprobe> {
+        let insns = counter_prog(counts.as_fd().as_raw_fd(), idx);
+        let name = CString::new("scx_kprobe").unwrap();
+        let license = CString::new("GPL").unwrap();
+        let fd = unsafe {
+            libbpf_sys::bpf_prog_load(
+                libbpf_sys::BPF_PROG_TYPE_KPROBE,
+                name.as_ptr(),
+                license.as_ptr(),
+                insns.as_ptr(),
+                insns.len() as _,
+                std::ptr::null_mut(),
+            )
+        };
+        if fd < 0 {
+            bail!(
+                "Failed to load kprobe program ({})",
+                std::io::Error::from_raw_os_error(-fd)
+            );
+        }
+        let prog = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let cfunc = CString::new(func)?;
+        let attr = PerfEventAttr {
+            type_: pmu_type,
+            // kprobe_func and probe_offset
+            config1: cfunc.as_ptr() as u64,
+            config2: 0,
+            ..Default::default()
+        };
+        let perf = perf_event_open(attr, -1, 0).context("Failed to create kprobe")?;
+
+        for (req, arg) in [
+            (PERF_EVENT_IOC_SET_BPF, prog.as_raw_fd()),
+            (PERF_EVENT_IOC_ENABLE, 0),
+        ] {
+            if unsafe { libc::ioctl(perf.as_raw_fd(), req as _, arg) } < 0 {
+                bail!(
+                    "Failed to enable kprobe ({})",
+                    std::io::Error::last_os_error()
+                );
+            }
+        }
+
+        Ok(Kprobe {
+            func: func.to_string(),
+            _perf: perf,
+            _prog: prog,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ScxKprobeMonitor {
+    counts: MapHandle,
+    kprobes: Vec<Kprobe>,
+}
+
+impl ScxKprobeMonitor {
+    /// Attach a kprobe to each of the kernel functions @funcs and start
+    /// counting their calls. Returns an error if any of the functions can't
+    /// be probed, in which case no kprobe remains attached.
+    pub fn new(funcs: &[&str]) -> Result<ScxKprobeMonitor> {
+        if funcs.is_empty() {
+            bail!("No functions to probe");
+        }
+        let pmu_type: u32 = std::fs::read_to_string(KPROBE_PMU_TYPE_PATH)
+            .with_context(|| {
+                format!(
+                    "Failed to read {}, are kprobes supported?",
+                    KPROBE_PMU_TYPE_PATH
+                )
+            })?
+            .trim()
+            .parse()
+            .with_context(|| format!("Failed to parse {}", KPROBE_PMU_TYPE_PATH))?;
+
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: std::mem::size_of::<libbpf_sys::bpf_map_create_opts>() as _,
+            ..Default::default()
+        };
+        let counts = MapHandle::create(
+            MapType::PercpuArray,
+            Some("scx_kprobes"),
+            std::mem::size_of::<u32>() as u32,
+            std::mem::size_of::<u64>() as u32,
+            funcs.len() as u32,
+            &opts,
+        )?;
+
+        let mut kprobes = vec![];
+        for (idx, func) in funcs.iter().enumerate() {
+            let kprobe = Kprobe::attach(func, idx as u32, &counts, pmu_type)
+                .with_context(|| format!("Failed to attach kprobe to {}", func))?;
+            kprobes.push(kprobe);
+        }
+
+        Ok(ScxKprobeMonitor { counts, kprobes })
+    }
+
+    /// Get the number of calls of each probed function per CPU since the
+    /// ScxKprobeMonitor was created, indexed by the function name and then
+    /// by CPU ID.
+    pub fn invocation_counts(&self) -> Result<HashMap<String, Vec<u64>>> {
+        let mut counts = HashMap::new();
+        for (idx, kprobe) in self.kprobes.iter().enumerate() {
+            let values = self
+                .counts
+                .lookup_percpu(&(idx as u32).to_ne_bytes(), MapFlags::ANY)
+                .with_context(|| format!("Failed to look up calls of {}", kprobe.func))?
+                .with_context(|| format!("Calls of {} missing", kprobe.func))?;
+            let per_cpu = values
+                .iter()
+                .map(|val| u64::from_ne_bytes(val[..8].try_into().unwrap()))
+                .collect();
+            counts.insert(kprobe.func.clone(), per_cpu);
+        }
+        Ok(counts)
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Latency Target
+//!
+//! A crate that allows schedulers to be tuned with a latency target, rather
+//! than by setting their time slice parameters directly.
+//!
+//! ScxLatencyTarget
+//! ----------------
+//!
+//! A ScxLatencyTarget derives the slice parameters of a scheduler from a
+//! target p99 scheduling latency using Little's Law, L = λW: for a CPU to
+//! serve a queue of L runnable tasks within the target latency W, it must
+//! dispatch at a rate of at least λ = L / W, i.e. a task may run for at most
+//! W / L before being preempted. The maximum slice is derived assuming a
+//! queue depth of 4 runnable tasks per CPU, and the minimum slice is an
+//! eighth of it. The latency buckets cover the range from the minimum slice
+//! to the target latency in powers of two:
+//!
+//!```
+//!     let target = ScxLatencyTarget::from_p99_us(opts.target_latency_us);
+//!     info!("slice {}-{}us", target.slice_min_us, target.slice_max_us);
+//!
+//!     let mut skel = skel_builder.open()?;
+//!     target.apply_to_bpf(&mut skel)?;
+//!```
+//!
+//! The skeleton has to implement BpfSkelMut, which maps the parameter names
+//! to the scheduler's global variables:
+//!
+//!```
+//!     impl BpfSkelMut for OpenBpfSkel<'_> {
+//!         fn set_u64(&mut self, name: &str, val: u64) -> Result<()> {
+//!             match name {
+//!                 "slice_min_us" => self.rodata_mut().slice_min_us = val,
+//!                 "slice_max_us" => self.rodata_mut().slice_max_us = val,
+//!                 _ => bail!("Unknown parameter {}", name),
+//!             }
+//!             Ok(())
+//!         }
+//!     }
+//!```
+
+use anyhow::Result;
+
+const QUEUE_DEPTH: u64 = 4;
+const SLICE_MIN_SHIFT: u32 = 3;
+const SLICE_FLOOR_US: u64 = 50;
+const SLICE_CEIL_US: u64 = 20_000;
+const MAX_LATENCY_BUCKETS: u64 = 32;
+
+/// A BPF skeleton whose parameters can be set by name, e.g. by writing the
+/// global variables of the BPF program. Implemented by schedulers for their
+/// own skeletons.
+pub trait BpfSkelMut {
+    /// Set the parameter @name to @val. Returns an error if the skeleton has
+    /// no such parameter.
+    fn set_u64(&mut self, name: &str, val: u64) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScxLatencyTarget {
+    pub target_p99_us: u64,
+    pub slice_min_us: u64,
+    pub slice_max_us: u64,
+    pub nr_latency_buckets: u64,
+}
+
+impl ScxLatencyTarget {
+    /// Compute the slice parameters for a target p99 scheduling latency of
+    /// @target microseconds. Slices are clamped to between 50us and 20ms.
+    pub fn from_p99_us(target: u64) -> ScxLatencyTarget {
+        let slice_max_us = (target / QUEUE_DEPTH).clamp(SLICE_FLOOR_US, SLICE_CEIL_US);
+        let slice_min_us = (slice_max_us >> SLICE_MIN_SHIFT).max(SLICE_FLOOR_US);
+
+        // One bucket for latencies up to the minimum slice, plus one for each
+        // power of two up to the target.
+        let ratio = (target / slice_min_us).max(1);
+        let nr_latency_buckets =
+            (1 + ratio.next_power_of_two().trailing_zeros() as u64).min(MAX_LATENCY_BUCKETS);
+
+        ScxLatencyTarget {
+            target_p99_us: target,
+            slice_min_us,
+            slice_max_us,
+            nr_latency_buckets,
+        }
+    }
+
+    /// The minimum rate at which each CPU has to dispatch tasks to meet the
+    /// target, in dispatches per second.
+    pub fn dispatch_rate_per_sec(&self) -> u64 {
+        1_000_000 / self.slice_max_us
+    }
+
+    /// Write the computed parameters to @skel as "slice_min_us",
+    /// "slice_max_us" and "nr_latency_buckets".
+    pub fn apply_to_bpf(&self, skel: &mut impl BpfSkelMut) -> Result<()> {
+        skel.set_u64("slice_min_us", self.slice_min_us)?;
+        skel.set_u64("slice_max_us", self.slice_max_us)?;
+        skel.set_u64("nr_latency_buckets", self.nr_latency_buckets)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BpfSkelMut;
+    use super::ScxLatencyTarget;
+    use anyhow::Result;
+    use std::collections::BTreeMap;
+
+    impl BpfSkelMut for BTreeMap<String, u64> {
+        fn set_u64(&mut self, name: &str, val: u64) -> Result<()> {
+            self.insert(name.to_string(), val);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_latency_target() {
+        let target = ScxLatencyTarget::from_p99_us(4000);
+        assert_eq!(target.slice_max_us, 1000);
+        assert_eq!(target.slice_min_us, 125);
+        assert_eq!(target.nr_latency_buckets, 6);
+        assert_eq!(target.dispatch_rate_per_sec(), 1000);
+
+        let mut params = BTreeMap::new();
+        target.apply_to_bpf(&mut params).unwrap();
+        assert_eq!(params.get("slice_max_us"), Some(&1000));
+        assert_eq!(params.len(), 3);
+    }
+
+    #[test]
+    fn test_latency_target_clamped() {
+        let target = ScxLatencyTarget::from_p99_us(10);
+        assert_eq!(target.slice_max_us, 50);
+        assert_eq!(target.slice_min_us, 50);
+        assert_eq!(target.nr_latency_buckets, 1);
+
+        let target = ScxLatencyTarget::from_p99_us(1_000_000);
+        assert_eq!(target.slice_max_us, 20_000);
+        assert_eq!(target.slice_min_us, 2500);
+    }
+}
@@ -53,10 +53,183 @@ pub use topology::Cpu;
 pub use topology::Core;
 pub use topology::Cache;
 pub use topology::Node;
+pub use topology::SmtControl;
+pub use topology::TopologyDiff;
 
 mod cpumask;
 pub use cpumask::Cpumask;
+pub use cpumask::CpuSelectPolicy;
+pub use cpumask::CpumaskMut;
+pub use cpumask::CpumaskRef;
+pub use cpumask::CpumaskWordsMut;
+pub use cpumask::CpumaskVec;
+pub use cpumask::SharedCpumask;
 
 mod infeasible;
 pub use infeasible::LoadAggregator;
 pub use infeasible::LoadLedger;
+
+mod numa;
+pub use numa::NumaMemoryStats;
+pub use numa::ScxNuma;
+
+mod pid;
+pub use pid::ScxPidList;
+
+mod audit;
+pub use audit::AuditEntry;
+pub use audit::ScxAuditLog;
+
+mod classify;
+pub use classify::ClassifierThresholds;
+pub use classify::TaskClass;
+pub use classify::TaskClassifier;
+
+mod dsq;
+pub use dsq::DsqId;
+pub use dsq::DsqRegistry;
+pub use dsq::ScxDispatchQueue;
+pub use dsq::DSQ_GLOBAL;
+pub use dsq::DSQ_LOCAL;
+
+mod bpf;
+pub use bpf::BpfCpumaskSyncMap;
+pub use bpf::BpfGlobalVar;
+pub use bpf::BpfPinHandle;
+pub use bpf::ScxBpfProgramLoader;
+pub use bpf::ScxBpfSkelBuilder;
+
+mod profiler;
+pub use profiler::ScxSamplingProfiler;
+
+mod bpf_stats;
+pub use bpf_stats::BpfProgramStats;
+pub use bpf_stats::BpfStatsEnabled;
+pub use bpf_stats::ProgramStat;
+
+mod throttle;
+pub use throttle::ScxThrottleDetector;
+
+mod verify;
+pub use verify::CompatReport;
+pub use verify::ScxVerifier;
+
+mod domain;
+pub use domain::DomainSet;
+pub use domain::LocalDomainId;
+
+mod stats;
+pub use stats::CollectorHandle;
+pub use stats::ScxStats;
+pub use stats::StatsSnapshot;
+
+mod timer;
+pub use timer::BpfTimerMap;
+
+mod priority;
+pub use priority::InversionEvent;
+pub use priority::TaskPriorityInverter;
+
+mod placement;
+pub use placement::PlacementLog;
+pub use placement::PlacementReason;
+pub use placement::ScxTaskPlacement;
+
+mod power;
+pub use power::PowerAwareDispatcher;
+pub use power::ScxPowerDomain;
+
+mod runqueue;
+pub use runqueue::ScxRunQueue;
+pub use runqueue::ScxTask;
+
+mod debug;
+pub use debug::DumpAll;
+pub use debug::ScxDebugDump;
+
+mod steal;
+pub use steal::ScxWorkStealing;
+
+mod latency;
+pub use latency::BpfSkelMut;
+pub use latency::ScxLatencyTarget;
+
+mod cpufreq;
+pub use cpufreq::ScxCpufreqGovernor;
+
+mod monitor;
+pub use monitor::AffinityViolation;
+pub use monitor::ScxAffinityMonitor;
+pub use monitor::ScxOnlineMonitor;
+
+mod config;
+pub use config::ScxConfig;
+pub use config::ScxOpts;
+
+mod trace;
+pub use trace::ScxEvent;
+pub use trace::ScxEventKind;
+pub use trace::ScxEventTrace;
+
+mod balance;
+pub use balance::DomainStats;
+pub use balance::ScxDomainBalancer;
+
+mod task;
+pub use task::ScxSchedPolicy;
+pub use task::ScxTaskInfo;
+
+mod btf;
+pub use btf::BpfStructAccess;
+
+mod registry;
+pub use registry::ScxError;
+pub use registry::ScxSchedulerRegistry;
+
+mod mem;
+pub use mem::ScxMmapStats;
+pub use mem::ScxMmapStatsDelta;
+
+#[cfg(feature = "serde_json")]
+mod export;
+#[cfg(feature = "serde_json")]
+pub use export::ScxTopologyExporter;
+
+mod health;
+pub use health::ScxHealthCheck;
+
+mod proc_events;
+pub use proc_events::ProcEvent;
+pub use proc_events::ProcEventKind;
+pub use proc_events::ScxProcEventMonitor;
+
+mod syscall;
+pub use syscall::ScxSyscallMonitor;
+
+mod cpu;
+pub use cpu::IdleState;
+pub use cpu::ScxCpuStats;
+
+mod dispatch;
+pub use dispatch::ScxCpuHotnessMap;
+
+mod kprobe;
+pub use kprobe::ScxKprobeMonitor;
+
+mod dashboard;
+pub use dashboard::DashboardSnapshot;
+pub use dashboard::ScxMetricsAggregator;
+
+mod group;
+pub use group::ScxTaskGroup;
+
+mod event_log;
+pub use event_log::ScxBpfEventLog;
+
+mod gc;
+pub use gc::Reclaimer;
+pub use gc::ScxBpfMapReclaimer;
+
+mod worker;
+mod sys;
+mod fs;
@@ -0,0 +1,187 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Memory Stats
+//!
+//! A crate that allows schedulers to track their own memory footprint, e.g.
+//! to detect leaks in their userspace component or mmap'd BPF maps.
+//!
+//! ScxMmapStats
+//! ------------
+//!
+//! ScxMmapStats::read() reads /proc/self/smaps_rollup, which sums up the
+//! memory usage of all mappings of the process. Samples taken over time can
+//! be checked for unbounded growth:
+//!
+//!```
+//!     let mut samples = vec![];
+//!     loop {
+//!         samples.push(ScxMmapStats::read()?);
+//!         if ScxMmapStats::is_growing_unbounded(&samples, 64.0) {
+//!             warn!("Anonymous memory keeps growing, leak?");
+//!         }
+//!         std::thread::sleep(Duration::from_secs(10));
+//!     }
+//!```
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::time::Duration;
+use std::time::Instant;
+
+const SMAPS_ROLLUP_PATH: &str = "/proc/self/smaps_rollup";
+
+#[derive(Debug, Clone)]
+pub struct ScxMmapStats {
+    /// Resident set size
+    pub rss_kb: u64,
+    /// Proportional set size, i.e. RSS with shared pages divided among the
+    /// processes sharing them
+    pub pss_kb: u64,
+    /// Anonymous memory, e.g. heap and mmap'd BPF maps
+    pub anonymous_kb: u64,
+    /// Memory locked with mlock(2)
+    pub locked_kb: u64,
+    /// When the stats were read
+    pub timestamp: Instant,
+}
+
+/// The change of ScxMmapStats between two samples. See ScxMmapStats::delta().
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScxMmapStatsDelta {
+    pub rss_kb: i64,
+    pub pss_kb: i64,
+    pub anonymous_kb: i64,
+    pub locked_kb: i64,
+    pub elapsed: Duration,
+}
+
+impl ScxMmapStats {
+    /// Read the memory stats of the current process.
+    pub fn read() -> Result<ScxMmapStats> {
+        let rollup = std::fs::read_to_string(SMAPS_ROLLUP_PATH)
+            .with_context(|| format!("Failed to read {}", SMAPS_ROLLUP_PATH))?;
+        Self::parse(&rollup, Instant::now())
+    }
+
+    fn parse(rollup: &str, timestamp: Instant) -> Result<ScxMmapStats> {
+        let field = |name: &str| -> Result<u64> {
+            for line in rollup.lines() {
+                if let Some(rest) = line.strip_prefix(name).and_then(|l| l.strip_prefix(':')) {
+                    let val = rest.trim().trim_end_matches("kB").trim();
+                    return val
+                        .parse::<u64>()
+                        .with_context(|| format!("Failed to parse {} in {}", name, line));
+                }
+            }
+            bail!("No {} in {}", name, SMAPS_ROLLUP_PATH);
+        };
+
+        Ok(ScxMmapStats {
+            rss_kb: field("Rss")?,
+            pss_kb: field("Pss")?,
+            anonymous_kb: field("Anonymous")?,
+            locked_kb: field("Locked")?,
+            timestamp,
+        })
+    }
+
+    /// Get the change of the stats since @old.
+    pub fn delta(&self, old: &ScxMmapStats) -> ScxMmapStatsDelta {
+        ScxMmapStatsDelta {
+            rss_kb: self.rss_kb as i64 - old.rss_kb as i64,
+            pss_kb: self.pss_kb as i64 - old.pss_kb as i64,
+            anonymous_kb: self.anonymous_kb as i64 - old.anonymous_kb as i64,
+            locked_kb: self.locked_kb as i64 - old.locked_kb as i64,
+            elapsed: self.timestamp.saturating_duration_since(old.timestamp),
+        }
+    }
+
+    /// Test whether anonymous memory grew by more than
+    /// @threshold_kb_per_sec across @samples, ordered from oldest to newest.
+    /// The growth rate is the least-squares slope of anonymous_kb over time,
+    /// so that a single spike doesn't count as unbounded growth. At least
+    /// three samples are needed.
+    pub fn is_growing_unbounded(samples: &[ScxMmapStats], threshold_kb_per_sec: f64) -> bool {
+        if samples.len() < 3 {
+            return false;
+        }
+
+        let first = samples[0].timestamp;
+        let points: Vec<(f64, f64)> = samples
+            .iter()
+            .map(|s| {
+                (
+                    s.timestamp.saturating_duration_since(first).as_secs_f64(),
+                    s.anonymous_kb as f64,
+                )
+            })
+            .collect();
+
+        let n = points.len() as f64;
+        let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+        let mean_kb = points.iter().map(|(_, kb)| kb).sum::<f64>() / n;
+        let mut cov = 0.0;
+        let mut var = 0.0;
+        for (t, kb) in points.iter() {
+            cov += (t - mean_t) * (kb - mean_kb);
+            var += (t - mean_t) * (t - mean_t);
+        }
+
+        var > 0.0 && cov / var > threshold_kb_per_sec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScxMmapStats;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    fn sample(start: Instant, secs: u64, anonymous_kb: u64) -> ScxMmapStats {
+        ScxMmapStats {
+            rss_kb: anonymous_kb,
+            pss_kb: anonymous_kb,
+            anonymous_kb,
+            locked_kb: 0,
+            timestamp: start + Duration::from_secs(secs),
+        }
+    }
+
+    #[test]
+    fn test_parse_smaps_rollup() {
+        let rollup = concat!(
+            "5592173b9000-7ffe51031000 ---p 00000000 00:00 0   [rollup]\n",
+            "Rss:                1308 kB\n",
+            "Pss:                 449 kB\n",
+            "Pss_Anon:            104 kB\n",
+            "Anonymous:           104 kB\n",
+            "Locked:                8 kB\n",
+        );
+        let stats = ScxMmapStats::parse(rollup, Instant::now()).unwrap();
+        assert_eq!(stats.rss_kb, 1308);
+        assert_eq!(stats.pss_kb, 449);
+        assert_eq!(stats.anonymous_kb, 104);
+        assert_eq!(stats.locked_kb, 8);
+    }
+
+    #[test]
+    fn test_growing_unbounded() {
+        let start = Instant::now();
+        let growing: Vec<_> = (0..5).map(|i| sample(start, i, 1000 + i * 100)).collect();
+        assert!(ScxMmapStats::is_growing_unbounded(&growing, 50.0));
+        assert!(!ScxMmapStats::is_growing_unbounded(&growing, 200.0));
+        assert_eq!(growing[4].delta(&growing[0]).anonymous_kb, 400);
+
+        let spike = vec![
+            sample(start, 0, 1000),
+            sample(start, 1, 5000),
+            sample(start, 2, 1000),
+            sample(start, 3, 1000),
+        ];
+        assert!(!ScxMmapStats::is_growing_unbounded(&spike, 50.0));
+    }
+}
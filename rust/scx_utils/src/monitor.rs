@@ -0,0 +1,292 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Monitors
+//!
+//! A crate of monitors which watch over tasks while a BPF scheduler is
+//! running, to recover from or to debug scheduler misbehavior.
+//!
+//! ScxOnlineMonitor
+//! ----------------
+//!
+//! A ScxOnlineMonitor spawns a SCHED_FIFO thread, which therefore isn't
+//! scheduled by sched_ext, and which periodically samples /proc/[pid]/stat of
+//! a set of registered PIDs. A task is considered stalled if it stays in the
+//! D (uninterruptible sleep) state for longer than a timeout, or stays in the
+//! R (runnable) state without accumulating any CPU time, i.e. without
+//! actually getting to run. Stalled tasks are sent SIGCONT, or passed to a
+//! recovery callback instead. The thread is stopped when the
+//! ScxOnlineMonitor is dropped:
+//!
+//!```
+//!     let monitor = ScxOnlineMonitor::spawn_with_recovery(
+//!         Duration::from_millis(100),
+//!         Duration::from_secs(5),
+//!         |pid, state| warn!("pid {} stalled in state {}", pid, state),
+//!     )?;
+//!     monitor.register(agent_pid);
+//!```
+//!
+//! This is a last-resort safety net for tasks which must keep making progress
+//! even if the BPF scheduler misbehaves, e.g. monitoring agents. Making the
+//! monitor thread SCHED_FIFO requires CAP_SYS_NICE. Without it, the thread
+//! keeps running under the default policy, and a warning is logged.
+//!
+//! ScxAffinityMonitor
+//! ------------------
+//!
+//! A ScxAffinityMonitor is a correctness debugging tool for new schedulers.
+//! Each sample() looks at a sample of the runnable threads on the host, and
+//! reports the ones whose last CPU is outside of their CPU affinity, i.e.
+//! which the BPF scheduler dispatched to a CPU they aren't allowed to run
+//! on. Successive samples rotate through all runnable threads:
+//!
+//!```
+//!     let mut monitor = ScxAffinityMonitor::new(64);
+//!     loop {
+//!         for v in monitor.sample()? {
+//!             error!("pid {} ran on CPU {} outside of {}", v.pid, v.scheduled_on, v.allowed);
+//!         }
+//!         std::thread::sleep(Duration::from_secs(1));
+//!     }
+//!```
+
+use crate::task::ProcStat;
+use crate::worker::PeriodicThread;
+use crate::Cpumask;
+use anyhow::Context;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy)]
+struct TaskWatch {
+    state: char,
+    cpu_ticks: u64,
+    // When the task was first seen in its current stuck condition
+    stuck_since: Option<Instant>,
+}
+
+type Watches = Arc<Mutex<BTreeMap<u32, Option<TaskWatch>>>>;
+
+fn read_task_stat(pid: u32) -> Result<ProcStat> {
+    ProcStat::read(pid as i32)
+}
+
+fn check_tasks<R: FnMut(u32, char)>(watches: &Watches, timeout: Duration, recovery: &mut R) {
+    let now = Instant::now();
+    let mut stalled = vec![];
+    let mut exited = vec![];
+
+    let mut watches = watches.lock().unwrap();
+    for (pid, watch) in watches.iter_mut() {
+        let (state, cpu_ticks) = match read_task_stat(*pid) {
+            Ok(cur) => (cur.state, cur.utime + cur.stime),
+            Err(_) => {
+                exited.push(*pid);
+                continue;
+            }
+        };
+
+        let stuck = match (state, *watch) {
+            ('D', Some(prev)) => prev.state == 'D',
+            ('R', Some(prev)) => prev.state == 'R' && prev.cpu_ticks == cpu_ticks,
+            _ => false,
+        };
+        let mut stuck_since = match (stuck, *watch) {
+            (true, Some(prev)) => prev.stuck_since.or(Some(now)),
+            _ => None,
+        };
+
+        if let Some(since) = stuck_since {
+            if now.duration_since(since) >= timeout {
+                stalled.push((*pid, state));
+                // Give the recovery time to take effect before acting again.
+                stuck_since = Some(now);
+            }
+        }
+
+        *watch = Some(TaskWatch {
+            state,
+            cpu_ticks,
+            stuck_since,
+        });
+    }
+
+    for pid in exited {
+        watches.remove(&pid);
+    }
+    drop(watches);
+
+    for (pid, state) in stalled {
+        recovery(pid, state);
+    }
+}
+
+#[derive(Debug)]
+pub struct ScxOnlineMonitor {
+    watches: Watches,
+    _thread: PeriodicThread,
+}
+
+impl ScxOnlineMonitor {
+    /// Spawn a monitor thread which checks the registered tasks every
+    /// @interval, and sends SIGCONT to tasks which have been stalled for
+    /// longer than @timeout.
+    pub fn spawn(interval: Duration, timeout: Duration) -> Result<ScxOnlineMonitor> {
+        Self::spawn_with_recovery(interval, timeout, |pid, _| unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGCONT);
+        })
+    }
+
+    /// Spawn a monitor thread which checks the registered tasks every
+    /// @interval, and calls @recovery with the PID and the state of tasks
+    /// which have been stalled for longer than @timeout. @recovery is called
+    /// again every @timeout for as long as a task stays stalled.
+    pub fn spawn_with_recovery<R>(
+        interval: Duration,
+        timeout: Duration,
+        mut recovery: R,
+    ) -> Result<ScxOnlineMonitor>
+    where
+        R: FnMut(u32, char) + Send + 'static,
+    {
+        let watches: Watches = Arc::new(Mutex::new(BTreeMap::new()));
+        let thread_watches = watches.clone();
+
+        let thread = PeriodicThread::spawn(
+            "scx_online_monitor".to_string(),
+            interval,
+            true,
+            move || check_tasks(&thread_watches, timeout, &mut recovery),
+        )
+        .context("Failed to spawn online monitor thread")?;
+
+        Ok(ScxOnlineMonitor {
+            watches,
+            _thread: thread,
+        })
+    }
+
+    /// Start monitoring @pid. Tasks which exit are unregistered
+    /// automatically.
+    pub fn register(&self, pid: u32) {
+        self.watches.lock().unwrap().entry(pid).or_insert(None);
+    }
+
+    /// Stop monitoring @pid.
+    pub fn unregister(&self, pid: u32) {
+        self.watches.lock().unwrap().remove(&pid);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AffinityViolation {
+    /// The thread ID of the task
+    pub pid: i32,
+    /// The CPU affinity of the task
+    pub allowed: Cpumask,
+    /// The CPU the task last ran on
+    pub scheduled_on: usize,
+}
+
+#[derive(Debug)]
+pub struct ScxAffinityMonitor {
+    sample_size: usize,
+    // The thread ID to start the next sample after
+    cursor: u32,
+}
+
+// List the IDs of all threads on the host in ascending order.
+fn list_tids() -> Result<Vec<u32>> {
+    let mut tids = vec![];
+    for proc_entry in std::fs::read_dir("/proc").context("Failed to read /proc")? {
+        let proc_entry = proc_entry?;
+        if proc_entry
+            .file_name()
+            .to_string_lossy()
+            .parse::<u32>()
+            .is_err()
+        {
+            continue;
+        }
+        // The process may have exited in the meantime.
+        let task_entries = match std::fs::read_dir(proc_entry.path().join("task")) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for task_entry in task_entries.filter_map(|entry| entry.ok()) {
+            if let Ok(tid) = task_entry.file_name().to_string_lossy().parse::<u32>() {
+                tids.push(tid);
+            }
+        }
+    }
+    tids.sort_unstable();
+    Ok(tids)
+}
+
+impl ScxAffinityMonitor {
+    /// Create a ScxAffinityMonitor which checks up to @sample_size runnable
+    /// threads on each sample().
+    pub fn new(sample_size: usize) -> ScxAffinityMonitor {
+        ScxAffinityMonitor {
+            sample_size: sample_size.max(1),
+            cursor: 0,
+        }
+    }
+
+    fn check(tid: u32) -> Option<AffinityViolation> {
+        let stat = read_task_stat(tid).ok()?;
+        if stat.state != 'R' {
+            return None;
+        }
+        let allowed = Cpumask::current_affinity(tid as i32).ok()?;
+        if allowed.test_cpu(stat.cpu) {
+            return None;
+        }
+
+        // The affinity may have changed while the task was running on the
+        // CPU, make sure the task is still there before reporting it.
+        match read_task_stat(tid) {
+            Ok(cur) if cur.cpu == stat.cpu && !allowed.test_cpu(cur.cpu) => {
+                Some(AffinityViolation {
+                    pid: tid as i32,
+                    allowed,
+                    scheduled_on: stat.cpu,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Check the next sample of runnable threads and return the ones which
+    /// last ran on a CPU outside of their affinity. Threads which exit or
+    /// can't be inspected during the sample are skipped.
+    pub fn sample(&mut self) -> Result<Vec<AffinityViolation>> {
+        let tids = list_tids()?;
+        let start = tids.partition_point(|tid| *tid <= self.cursor);
+
+        let mut violations = vec![];
+        let mut nr_sampled = 0;
+        for tid in tids[start..].iter().chain(tids[..start].iter()) {
+            if nr_sampled >= self.sample_size {
+                break;
+            }
+            match read_task_stat(*tid) {
+                Ok(stat) if stat.state == 'R' => {}
+                _ => continue,
+            }
+            nr_sampled += 1;
+            self.cursor = *tid;
+            if let Some(violation) = Self::check(*tid) {
+                violations.push(violation);
+            }
+        }
+        Ok(violations)
+    }
+}
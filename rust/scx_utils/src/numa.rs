@@ -0,0 +1,281 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX NUMA
+//!
+//! A crate that allows schedulers to inspect the per-node state of a NUMA
+//! host, e.g. to avoid placing tasks on nodes that are short on free memory.
+//!
+//! NumaMemoryStats
+//! ---------------
+//!
+//! A NumaMemoryStats object is a snapshot of the memory counters reported by
+//! /sys/devices/system/node/nodeN/meminfo. All values are in kilobytes:
+//!
+//!```
+//!     let stats = NumaMemoryStats::from_node(0)?;
+//!     info!("node 0: {} / {} kB free", stats.mem_free, stats.mem_total);
+//!```
+//!
+//! ScxNuma
+//! -------
+//!
+//! A ScxNuma object reads and caches the NUMA layout of the host at
+//! construction: the CPUs, free memory, L3 cache sizes and distances of all
+//! nodes. It can then be used to estimate the cost of migrating a task
+//! between two CPUs, and to pick the nodes to place tasks or memory on:
+//!
+//!```
+//!     let numa = ScxNuma::new()?;
+//!     if numa.migration_cost(prev_cpu, cpu) > numa.migration_cost(prev_cpu, other_cpu) {
+//!         ...
+//!     }
+//!
+//!     for node in numa.nodes_in_distance_order(0) {
+//!         info!("node {}: {} kB free", node, numa.node_mem_free_kb(node).unwrap());
+//!     }
+//!```
+
+use crate::Cpumask;
+use crate::Topology;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default)]
+pub struct NumaMemoryStats {
+    /// Total usable memory on the node
+    pub mem_total: u64,
+    /// Memory on the node which is not in use
+    pub mem_free: u64,
+    /// Memory on the node which is in use
+    pub mem_used: u64,
+    /// Recently used memory which is unlikely to be reclaimed
+    pub active: u64,
+    /// Less recently used memory which is eligible for reclaim
+    pub inactive: u64,
+    /// Non-file backed pages mapped into page tables
+    pub anon_pages: u64,
+    /// Pages in the page cache
+    pub file_pages: u64,
+}
+
+impl NumaMemoryStats {
+    /// Read the memory statistics of the specified NUMA node.
+    pub fn from_node(node: usize) -> Result<NumaMemoryStats> {
+        let path = format!("/sys/devices/system/node/node{}/meminfo", node);
+        let meminfo = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path))?;
+
+        let mut stats = NumaMemoryStats::default();
+        for line in meminfo.lines() {
+            // Each line looks like "Node 0 MemTotal:       32767436 kB".
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4 {
+                continue;
+            }
+
+            let field = match fields[2] {
+                "MemTotal:" => &mut stats.mem_total,
+                "MemFree:" => &mut stats.mem_free,
+                "MemUsed:" => &mut stats.mem_used,
+                "Active:" => &mut stats.active,
+                "Inactive:" => &mut stats.inactive,
+                "AnonPages:" => &mut stats.anon_pages,
+                "FilePages:" => &mut stats.file_pages,
+                _ => continue,
+            };
+
+            *field = match fields[3].parse::<u64>() {
+                Ok(val) => val,
+                Err(_) => {
+                    bail!("Failed to parse {:?} in {}", line, path);
+                }
+            };
+        }
+
+        Ok(stats)
+    }
+}
+
+// Migration cost of moving a task away from its LLC, in units of NUMA
+// distance. This makes a migration within the local node but across LLCs as
+// expensive as twice the local NUMA distance of 10.
+const CACHE_COLD_PENALTY: u32 = 10;
+
+// A node is considered low on memory if less than this ratio of its memory is
+// free.
+const LOW_MEM_FREE_RATIO: f64 = 0.05;
+
+#[derive(Debug, Clone)]
+struct NumaNode {
+    cpus: Cpumask,
+    distances: BTreeMap<usize, usize>,
+    mem: NumaMemoryStats,
+    l3_size_kb: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScxNuma {
+    nodes: BTreeMap<usize, NumaNode>,
+    // CPU ID -> (node ID, LLC ID)
+    cpus: BTreeMap<usize, (usize, usize)>,
+}
+
+fn read_l3_size_kb(cpu: usize) -> Result<u64> {
+    // Not all CPUs have an L3 cache.
+    let path = format!("/sys/devices/system/cpu/cpu{}/cache/index3/size", cpu);
+    if !Path::new(&path).exists() {
+        return Ok(0);
+    }
+    let size =
+        std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))?;
+    let size = size.trim();
+
+    let (val, mult) = match size.strip_suffix('K') {
+        Some(val) => (val, 1),
+        None => match size.strip_suffix('M') {
+            Some(val) => (val, 1024),
+            None => (size, 1),
+        },
+    };
+    match val.parse::<u64>() {
+        Ok(val) => Ok(val * mult),
+        Err(_) => bail!("Failed to parse cache size {:?} in {}", size, path),
+    }
+}
+
+impl ScxNuma {
+    /// Read the NUMA layout of the host.
+    pub fn new() -> Result<ScxNuma> {
+        let topo = Topology::new()?;
+
+        let mut nodes = BTreeMap::new();
+        let mut cpus = BTreeMap::new();
+        for node in topo.nodes().iter() {
+            let mut l3_size_kb = 0;
+            for (llc_id, llc) in node.llcs().iter() {
+                let span = llc.span();
+                if let Some(cpu) = span.first_set_cpu() {
+                    l3_size_kb += read_l3_size_kb(cpu)?;
+                }
+                for cpu in span.into_iter() {
+                    cpus.insert(cpu, (node.id(), *llc_id));
+                }
+            }
+
+            nodes.insert(
+                node.id(),
+                NumaNode {
+                    cpus: node.span(),
+                    distances: node.distances().clone(),
+                    mem: NumaMemoryStats::from_node(node.id())?,
+                    l3_size_kb,
+                },
+            );
+        }
+
+        Ok(ScxNuma { nodes, cpus })
+    }
+
+    /// Get the IDs of all NUMA nodes.
+    pub fn node_ids(&self) -> Vec<usize> {
+        self.nodes.keys().copied().collect()
+    }
+
+    /// Get the CPUs of NUMA node @node.
+    pub fn node_cpus(&self, node: usize) -> Option<&Cpumask> {
+        self.nodes.get(&node).map(|node| &node.cpus)
+    }
+
+    /// Get the free memory of NUMA node @node in kilobytes, as of the
+    /// construction of the ScxNuma.
+    pub fn node_mem_free_kb(&self, node: usize) -> Option<u64> {
+        self.nodes.get(&node).map(|node| node.mem.mem_free)
+    }
+
+    /// Get the total size of the L3 caches of NUMA node @node in kilobytes.
+    pub fn node_l3_size_kb(&self, node: usize) -> Option<u64> {
+        self.nodes.get(&node).map(|node| node.l3_size_kb)
+    }
+
+    /// Get the NUMA node @cpu belongs to.
+    pub fn node_of_cpu(&self, cpu: usize) -> Option<usize> {
+        self.cpus.get(&cpu).map(|(node, _)| *node)
+    }
+
+    /// Estimate the cost of migrating a task from @from_cpu to @to_cpu. The
+    /// cost is 0 for the same CPU, the NUMA distance between the CPUs' nodes
+    /// if they share an LLC, and the NUMA distance plus a cache-cold penalty
+    /// otherwise. Returns u32::MAX if either CPU is unknown.
+    pub fn migration_cost(&self, from_cpu: usize, to_cpu: usize) -> u32 {
+        if from_cpu == to_cpu {
+            return 0;
+        }
+
+        let ((from_node, from_llc), (to_node, to_llc)) =
+            match (self.cpus.get(&from_cpu), self.cpus.get(&to_cpu)) {
+                (Some(from), Some(to)) => (*from, *to),
+                _ => return u32::MAX,
+            };
+        let distance = self
+            .nodes
+            .get(&from_node)
+            .and_then(|node| node.distances.get(&to_node))
+            .copied()
+            .unwrap_or(u32::MAX as usize) as u32;
+
+        if from_llc == to_llc && from_node == to_node {
+            distance
+        } else {
+            distance.saturating_add(CACHE_COLD_PENALTY)
+        }
+    }
+
+    /// Get the IDs of all NUMA nodes sorted by their distance from
+    /// @from_node, starting with @from_node itself. Nodes at the same
+    /// distance are sorted by ID.
+    pub fn nodes_in_distance_order(&self, from_node: usize) -> Vec<usize> {
+        let distances = match self.nodes.get(&from_node) {
+            Some(node) => &node.distances,
+            None => return vec![],
+        };
+
+        let mut nodes: Vec<usize> = self.nodes.keys().copied().collect();
+        nodes.sort_by_key(|node| {
+            let distance = if *node == from_node {
+                0
+            } else {
+                distances.get(node).copied().unwrap_or(usize::MAX)
+            };
+            (distance, *node)
+        });
+        nodes
+    }
+
+    /// Get the node memory of tasks running on @cpu should preferably be
+    /// placed on. This is the node of @cpu unless it is low on free memory,
+    /// in which case it's the closest node which isn't. Returns None if @cpu
+    /// is unknown.
+    pub fn preferred_node_for_cpu(&self, cpu: usize) -> Option<usize> {
+        let local = self.node_of_cpu(cpu)?;
+
+        let has_free_mem = |node_id: &usize| match self.nodes.get(node_id) {
+            Some(node) if node.mem.mem_total > 0 => {
+                node.mem.mem_free as f64 / node.mem.mem_total as f64 >= LOW_MEM_FREE_RATIO
+            }
+            _ => false,
+        };
+
+        Some(
+            self.nodes_in_distance_order(local)
+                .into_iter()
+                .find(has_free_mem)
+                .unwrap_or(local),
+        )
+    }
+}
@@ -0,0 +1,128 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX PID List
+//!
+//! A crate that allows schedulers to maintain a set of PIDs shared with the
+//! BPF side of the scheduler, e.g. to give a set of "premium" latency-sensitive
+//! tasks preferential treatment.
+//!
+//! ScxPidList
+//! ----------
+//!
+//! A ScxPidList wraps a BPF_MAP_TYPE_HASH map which is keyed by a u32 PID. The
+//! BPF side of the scheduler only needs to test for the presence of a PID in
+//! the map; the value of each entry is zero-filled:
+//!
+//!```
+//!     let pids = ScxPidList::new(skel.maps().premium_pids())?;
+//!     pids.add(1234)?;
+//!     assert!(pids.contains(1234));
+//!
+//!     pids.remove(1234)?;
+//!     assert!(!pids.contains(1234));
+//!```
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use libbpf_rs::Map;
+use libbpf_rs::MapFlags;
+use libbpf_rs::MapType;
+use std::path::Path;
+
+pub struct ScxPidList<'a> {
+    map: &'a Map,
+    value: Vec<u8>,
+}
+
+impl<'a> ScxPidList<'a> {
+    /// Build a ScxPidList on top of a BPF hash map keyed by u32 PIDs.
+    /// Returns an error if the map is of a different type or key size.
+    pub fn new(map: &'a Map) -> Result<ScxPidList<'a>> {
+        if map.map_type() != MapType::Hash {
+            bail!("Map {} is not a BPF_MAP_TYPE_HASH", map.name());
+        }
+        if map.key_size() as usize != std::mem::size_of::<u32>() {
+            bail!(
+                "Map {} has key size {}, expected {}",
+                map.name(),
+                map.key_size(),
+                std::mem::size_of::<u32>()
+            );
+        }
+
+        Ok(ScxPidList {
+            map,
+            value: vec![0u8; map.value_size() as usize],
+        })
+    }
+
+    /// Build a ScxPidList and populate it with all PIDs listed in the
+    /// cgroup.procs file of the cgroup at @path.
+    pub fn from_cgroup_pids(map: &'a Map, path: &Path) -> Result<ScxPidList<'a>> {
+        let pids = Self::new(map)?;
+
+        let procs_path = path.join("cgroup.procs");
+        let procs = std::fs::read_to_string(&procs_path)
+            .with_context(|| format!("Failed to read {:?}", procs_path))?;
+        for line in procs.lines() {
+            let pid = match line.trim().parse::<u32>() {
+                Ok(pid) => pid,
+                Err(_) => {
+                    bail!("Failed to parse PID {:?} in {:?}", line, procs_path);
+                }
+            };
+            pids.add(pid)?;
+        }
+
+        Ok(pids)
+    }
+
+    /// Add a PID to the list. Adding a PID which is already on the list is
+    /// not an error.
+    pub fn add(&self, pid: u32) -> Result<()> {
+        self.map
+            .update(&pid.to_ne_bytes(), &self.value, MapFlags::ANY)
+            .with_context(|| format!("Failed to add PID {} to {}", pid, self.map.name()))
+    }
+
+    /// Remove a PID from the list. Returns an error if the PID is not on the
+    /// list.
+    pub fn remove(&self, pid: u32) -> Result<()> {
+        self.map
+            .delete(&pid.to_ne_bytes())
+            .with_context(|| format!("Failed to remove PID {} from {}", pid, self.map.name()))
+    }
+
+    /// Test whether a PID is on the list.
+    pub fn contains(&self, pid: u32) -> bool {
+        matches!(
+            self.map.lookup(&pid.to_ne_bytes(), MapFlags::ANY),
+            Ok(Some(_))
+        )
+    }
+
+    /// Count the number of PIDs on the list. This walks the whole map.
+    pub fn len(&self) -> usize {
+        self.map.keys().count()
+    }
+
+    /// Test whether the list is empty.
+    pub fn is_empty(&self) -> bool {
+        self.map.keys().next().is_none()
+    }
+
+    /// Remove all PIDs from the list.
+    pub fn clear(&self) -> Result<()> {
+        // Collect the keys first, as deleting while walking the map may
+        // restart the walk from the beginning.
+        let keys: Vec<Vec<u8>> = self.map.keys().collect();
+        for key in keys.iter() {
+            self.map.delete(key)?;
+        }
+        Ok(())
+    }
+}
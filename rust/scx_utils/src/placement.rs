@@ -0,0 +1,190 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Task Placement Log
+//!
+//! A crate that allows schedulers to record where tasks were dispatched to
+//! and why, for post-mortem analysis of scheduling decisions.
+//!
+//! ScxTaskPlacement
+//! ----------------
+//!
+//! A ScxTaskPlacement is a plain record which mirrors the following struct on
+//! the BPF side, so that records emitted through a BPF ring buffer can be
+//! used as is:
+//!
+//!```
+//!     struct scx_task_placement {
+//!         u32 pid;
+//!         u32 reason;
+//!         u64 cpu;
+//!         u64 dsq_id;
+//!         u64 enqueue_ts_ns;
+//!         u64 dispatch_ts_ns;
+//!     };
+//!```
+//!
+//! PlacementLog
+//! ------------
+//!
+//! A PlacementLog keeps the most recent records in a fixed-size ring:
+//!
+//!```
+//!     let log = RefCell::new(PlacementLog::new(4096));
+//!
+//!     let mut builder = libbpf_rs::RingBufferBuilder::new();
+//!     builder.add(skel.maps().placements(), |data| {
+//!         log.borrow_mut().push_bytes(data).map_or(-1, |_| 0)
+//!     })?;
+//!
+//!     // ...
+//!
+//!     info!("avg dispatch latency {}ns", log.borrow().avg_latency_ns());
+//!```
+
+use anyhow::bail;
+use anyhow::Result;
+use bytemuck::Pod;
+use bytemuck::Zeroable;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt;
+
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Pod, Zeroable)]
+pub struct PlacementReason(pub u32);
+
+impl PlacementReason {
+    /// No reason was recorded.
+    pub const UNKNOWN: PlacementReason = PlacementReason(0);
+    /// The task was kept on the CPU it previously ran on.
+    pub const PREV_CPU: PlacementReason = PlacementReason(1);
+    /// The task was dispatched to an idle CPU.
+    pub const IDLE_CPU: PlacementReason = PlacementReason(2);
+    /// The task was dispatched to the CPU of its waker.
+    pub const WAKER_CPU: PlacementReason = PlacementReason(3);
+    /// The task was queued on a shared DSQ and consumed by the CPU.
+    pub const SHARED_DSQ: PlacementReason = PlacementReason(4);
+    /// The task was placed by the userspace component of the scheduler.
+    pub const USERSPACE: PlacementReason = PlacementReason(5);
+}
+
+impl fmt::Display for PlacementReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::UNKNOWN => write!(f, "unknown"),
+            Self::PREV_CPU => write!(f, "prev_cpu"),
+            Self::IDLE_CPU => write!(f, "idle_cpu"),
+            Self::WAKER_CPU => write!(f, "waker_cpu"),
+            Self::SHARED_DSQ => write!(f, "shared_dsq"),
+            Self::USERSPACE => write!(f, "userspace"),
+            Self(reason) => write!(f, "custom({})", reason),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Pod, Zeroable)]
+pub struct ScxTaskPlacement {
+    pub pid: u32,
+    pub reason: PlacementReason,
+    /// The CPU the task was dispatched to. This is a u64 rather than a usize
+    /// to match the layout of the BPF side.
+    pub cpu: u64,
+    pub dsq_id: u64,
+    pub enqueue_ts_ns: u64,
+    pub dispatch_ts_ns: u64,
+}
+
+impl ScxTaskPlacement {
+    /// Deserialize a record as emitted by the BPF side, e.g. into a BPF ring
+    /// buffer. Returns an error if @data is too short.
+    pub fn from_bytes(data: &[u8]) -> Result<ScxTaskPlacement> {
+        let size = std::mem::size_of::<ScxTaskPlacement>();
+        if data.len() < size {
+            bail!(
+                "Task placement record of {} bytes is shorter than {} bytes",
+                data.len(),
+                size
+            );
+        }
+        Ok(bytemuck::pod_read_unaligned(&data[..size]))
+    }
+
+    /// The time between the task being enqueued and dispatched.
+    pub fn latency_ns(&self) -> u64 {
+        self.dispatch_ts_ns.saturating_sub(self.enqueue_ts_ns)
+    }
+}
+
+#[derive(Debug)]
+pub struct PlacementLog {
+    records: VecDeque<ScxTaskPlacement>,
+    capacity: usize,
+}
+
+impl PlacementLog {
+    /// Create a PlacementLog which keeps the @capacity most recent records.
+    pub fn new(capacity: usize) -> PlacementLog {
+        let capacity = capacity.max(1);
+        PlacementLog {
+            records: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Append a record, evicting the oldest one if the log is full.
+    pub fn push(&mut self, placement: ScxTaskPlacement) {
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(placement);
+    }
+
+    /// Deserialize a record as emitted by the BPF side and append it.
+    pub fn push_bytes(&mut self, data: &[u8]) -> Result<()> {
+        self.push(ScxTaskPlacement::from_bytes(data)?);
+        Ok(())
+    }
+
+    /// Iterate over the records from oldest to newest.
+    pub fn iter(&self) -> impl Iterator<Item = &ScxTaskPlacement> {
+        self.records.iter()
+    }
+
+    /// The number of records in the log.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Test whether the log contains no records.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Get the average enqueue to dispatch latency of the records in the
+    /// log, or 0 if the log is empty.
+    pub fn avg_latency_ns(&self) -> u64 {
+        if self.records.is_empty() {
+            return 0;
+        }
+
+        let total: u128 = self.records.iter().map(|p| p.latency_ns() as u128).sum();
+        (total / self.records.len() as u128) as u64
+    }
+
+    /// Group the records in the log by the CPU the tasks were dispatched to.
+    /// The records of each CPU are ordered from oldest to newest.
+    pub fn by_cpu(&self) -> HashMap<usize, Vec<ScxTaskPlacement>> {
+        let mut by_cpu: HashMap<usize, Vec<ScxTaskPlacement>> = HashMap::new();
+        for placement in self.records.iter() {
+            by_cpu
+                .entry(placement.cpu as usize)
+                .or_default()
+                .push(*placement);
+        }
+        by_cpu
+    }
+}
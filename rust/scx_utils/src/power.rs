@@ -0,0 +1,307 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Power Aware Dispatcher
+//!
+//! A crate that allows energy efficient schedulers to consolidate tasks onto
+//! fewer CPU packages, so that the remaining packages can stay in deep
+//! C-states.
+//!
+//! PowerAwareDispatcher
+//! --------------------
+//!
+//! A PowerAwareDispatcher periodically samples the idle residency of the
+//! deepest C-state of each CPU, as reported by the cpuidle driver (e.g.
+//! intel_idle or acpi_idle) in /sys/devices/system/cpu/cpuN/cpuidle. A
+//! package is considered to be in a deep C-state if its CPUs spent at least a
+//! threshold ratio of the last sampling interval in their deepest C-state:
+//!
+//!```
+//!     let mut power = PowerAwareDispatcher::new()?;
+//!
+//!     loop {
+//!         std::thread::sleep(Duration::from_secs(1));
+//!         power.update()?;
+//!
+//!         // Keep dispatching to the CPUs of the active packages, and leave
+//!         // the packages in deep C-states alone.
+//!         let target = power.consolidation_target_mask()?;
+//!         let avoid = power.deep_idle_package_mask()?;
+//!     }
+//!```
+//!
+//! Until update() has been called twice, no package is considered to be in a
+//! deep C-state.
+//!
+//! ScxPowerDomain
+//! --------------
+//!
+//! On some platforms, the CPUs of a cpufreq policy, as listed in
+//! /sys/devices/system/cpu/cpuN/cpufreq/related_cpus, always change their
+//! frequency together. A ScxPowerDomain groups the online CPUs into such
+//! power domains, e.g. so that a scheduler can consolidate load which should
+//! run at a high frequency onto the same domain:
+//!
+//!```
+//!     let power = ScxPowerDomain::new()?;
+//!     for (i, domain) in power.domains().iter().enumerate() {
+//!         info!("power domain {}: {}", i, domain);
+//!     }
+//!     let domain = power.domain_of(cpu);
+//!```
+//!
+//! CPUs without cpufreq support are each in a power domain of their own.
+
+use crate::topology::read_file_usize;
+use crate::Cpumask;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Instant;
+
+const DEFAULT_DEEP_THRESHOLD: f64 = 0.9;
+
+#[derive(Debug)]
+struct Package {
+    cpus: Cpumask,
+    deep_ratio: f64,
+}
+
+#[derive(Debug)]
+pub struct PowerAwareDispatcher {
+    packages: BTreeMap<usize, Package>,
+    // CPU ID -> path of the deepest cpuidle state
+    deepest_states: BTreeMap<usize, PathBuf>,
+    // CPU ID -> residency of the deepest state in usecs as of the last update
+    residency_us: BTreeMap<usize, u64>,
+    last_update: Option<Instant>,
+    deep_threshold: f64,
+}
+
+fn deepest_idle_state(cpu: usize) -> Option<PathBuf> {
+    let cpuidle_path = format!("/sys/devices/system/cpu/cpu{}/cpuidle", cpu);
+    let states = std::fs::read_dir(cpuidle_path).ok()?;
+    states
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let idx = name.strip_prefix("state")?.parse::<usize>().ok()?;
+            Some((idx, entry.path()))
+        })
+        .max_by_key(|(idx, _)| *idx)
+        .map(|(_, path)| path)
+}
+
+impl PowerAwareDispatcher {
+    /// Create a PowerAwareDispatcher for all online CPUs, which considers a
+    /// package to be in a deep C-state if its CPUs spent at least 90% of
+    /// the last sampling interval in their deepest C-state. Returns an error
+    /// if no cpuidle driver is loaded.
+    pub fn new() -> Result<PowerAwareDispatcher> {
+        let online = std::fs::read_to_string("/sys/devices/system/cpu/online")?;
+        let online = Cpumask::from_cpu_list(&online)?;
+
+        let mut packages = BTreeMap::new();
+        let mut deepest_states = BTreeMap::new();
+        for cpu in online.clone().into_iter() {
+            let pkg_path = format!(
+                "/sys/devices/system/cpu/cpu{}/topology/physical_package_id",
+                cpu
+            );
+            let pkg_id = read_file_usize(Path::new(&pkg_path))?;
+
+            let pkg = match packages.entry(pkg_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(Package {
+                    cpus: Cpumask::new()?,
+                    deep_ratio: 0.0,
+                }),
+            };
+            pkg.cpus.set_cpu(cpu)?;
+
+            if let Some(state) = deepest_idle_state(cpu) {
+                deepest_states.insert(cpu, state);
+            }
+        }
+
+        if deepest_states.is_empty() {
+            bail!("No cpuidle states found, is a cpuidle driver loaded?");
+        }
+
+        Ok(PowerAwareDispatcher {
+            packages,
+            deepest_states,
+            residency_us: BTreeMap::new(),
+            last_update: None,
+            deep_threshold: DEFAULT_DEEP_THRESHOLD,
+        })
+    }
+
+    /// Set the ratio of the sampling interval, between 0.0 and 1.0, which the
+    /// CPUs of a package must spend in their deepest C-state for the package
+    /// to be considered in a deep C-state.
+    pub fn set_deep_threshold(&mut self, threshold: f64) {
+        self.deep_threshold = threshold.clamp(0.0, 1.0);
+    }
+
+    /// Sample the idle residency of all CPUs, and update which packages are
+    /// in deep C-states.
+    pub fn update(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let mut residency_us = BTreeMap::new();
+        for (cpu, state) in self.deepest_states.iter() {
+            residency_us.insert(*cpu, read_file_usize(&state.join("time"))? as u64);
+        }
+
+        if let Some(last_update) = self.last_update {
+            let elapsed_us = now.duration_since(last_update).as_micros() as f64;
+            for pkg in self.packages.values_mut() {
+                let mut sum = 0.0;
+                let mut nr_cpus = 0;
+                for cpu in pkg.cpus.clone().into_iter() {
+                    if let (Some(cur), Some(prev)) =
+                        (residency_us.get(&cpu), self.residency_us.get(&cpu))
+                    {
+                        sum += (cur.saturating_sub(*prev) as f64 / elapsed_us).min(1.0);
+                        nr_cpus += 1;
+                    }
+                }
+                pkg.deep_ratio = match nr_cpus {
+                    0 => 0.0,
+                    nr => sum / nr as f64,
+                };
+            }
+        }
+
+        self.residency_us = residency_us;
+        self.last_update = Some(now);
+        Ok(())
+    }
+
+    fn is_deep(&self, pkg: &Package) -> bool {
+        pkg.deep_ratio >= self.deep_threshold
+    }
+
+    /// Get the CPUs of all packages which are not in a deep C-state.
+    pub fn active_package_mask(&self) -> Result<Cpumask> {
+        let mut mask = Cpumask::new()?;
+        for pkg in self.packages.values().filter(|pkg| !self.is_deep(pkg)) {
+            mask = mask.or(&pkg.cpus)?;
+        }
+        Ok(mask)
+    }
+
+    /// Get the CPUs of all packages which are in a deep C-state, i.e. the
+    /// CPUs which are recommended to be avoided.
+    pub fn deep_idle_package_mask(&self) -> Result<Cpumask> {
+        let mut mask = Cpumask::new()?;
+        for pkg in self.packages.values().filter(|pkg| self.is_deep(pkg)) {
+            mask = mask.or(&pkg.cpus)?;
+        }
+        Ok(mask)
+    }
+
+    /// Get the CPUs tasks should be consolidated onto. These are the CPUs of
+    /// the active packages or, if all packages are in deep C-states, the CPUs
+    /// of the package which is the least deeply idle.
+    pub fn consolidation_target_mask(&self) -> Result<Cpumask> {
+        let active = self.active_package_mask()?;
+        if active.weight() > 0 {
+            return Ok(active);
+        }
+
+        match self
+            .packages
+            .values()
+            .min_by(|a, b| a.deep_ratio.total_cmp(&b.deep_ratio))
+        {
+            Some(pkg) => Ok(pkg.cpus.clone()),
+            None => Cpumask::new(),
+        }
+    }
+
+    /// Get the ratio of the last sampling interval the CPUs of package
+    /// @pkg_id spent in their deepest C-state on average.
+    pub fn package_deep_ratio(&self, pkg_id: usize) -> Option<f64> {
+        self.packages.get(&pkg_id).map(|pkg| pkg.deep_ratio)
+    }
+}
+
+#[derive(Debug)]
+pub struct ScxPowerDomain {
+    domains: Vec<Cpumask>,
+    // CPU ID -> index of its domain in domains
+    cpu_domains: BTreeMap<usize, usize>,
+}
+
+impl ScxPowerDomain {
+    /// Group all online CPUs into power domains.
+    pub fn new() -> Result<ScxPowerDomain> {
+        let online = std::fs::read_to_string("/sys/devices/system/cpu/online")?;
+        let online = Cpumask::from_cpu_list(&online)?;
+
+        let mut domains: Vec<Cpumask> = vec![];
+        let mut cpu_domains = BTreeMap::new();
+        for cpu in online.into_iter() {
+            if cpu_domains.contains_key(&cpu) {
+                continue;
+            }
+
+            let path = format!("/sys/devices/system/cpu/cpu{}/cpufreq/related_cpus", cpu);
+            let mut domain = match std::fs::read_to_string(&path) {
+                Ok(related) => Cpumask::from_cpu_list(
+                    &related.split_whitespace().collect::<Vec<_>>().join(","),
+                )
+                .with_context(|| format!("Failed to parse {}", path))?,
+                Err(_) => Cpumask::new()?,
+            };
+            domain.set_cpu(cpu)?;
+
+            for related in domain.clone().into_iter() {
+                cpu_domains.insert(related, domains.len());
+            }
+            domains.push(domain);
+        }
+
+        Ok(ScxPowerDomain {
+            domains,
+            cpu_domains,
+        })
+    }
+
+    /// Get the CPUs of each power domain.
+    pub fn domains(&self) -> &[Cpumask] {
+        &self.domains
+    }
+
+    /// Get the index of the power domain of @cpu in domains(), or None if
+    /// @cpu wasn't online when the ScxPowerDomain was created.
+    pub fn domain_of(&self, cpu: usize) -> Option<usize> {
+        self.cpu_domains.get(&cpu).copied()
+    }
+
+    /// Test whether the online CPUs of each power domain currently run at
+    /// the same frequency, as reported by scaling_cur_freq. CPUs whose
+    /// frequency can't be read are ignored.
+    pub fn all_at_same_freq(&self) -> bool {
+        self.domains.iter().all(|domain| {
+            let mut freqs = domain.clone().into_iter().filter_map(|cpu| {
+                let path = format!(
+                    "/sys/devices/system/cpu/cpu{}/cpufreq/scaling_cur_freq",
+                    cpu
+                );
+                read_file_usize(Path::new(&path)).ok()
+            });
+            match freqs.next() {
+                Some(first) => freqs.all(|freq| freq == first),
+                None => true,
+            }
+        })
+    }
+}
@@ -0,0 +1,187 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Priority Inversion Detector
+//!
+//! A diagnostic crate that allows scheduler authors to spot priority
+//! inversions, i.e. lower priority tasks running while higher priority tasks
+//! in the same scheduling domain are kept waiting.
+//!
+//! TaskPriorityInverter
+//! --------------------
+//!
+//! A TaskPriorityInverter periodically samples /proc/[pid]/schedstat and
+//! /proc/[pid]/stat of a set of registered PIDs. Between two samples, a
+//! higher priority (lower nice) task is considered inverted by a lower
+//! priority task if both last ran in the same domain, the higher priority
+//! task spent time waiting for a CPU, and the lower priority task ran for
+//! longer than the higher priority task:
+//!
+//!```
+//!     let mut inverter = TaskPriorityInverter::new(domains);
+//!     inverter.register(high_pid);
+//!     inverter.register(low_pid);
+//!
+//!     loop {
+//!         inverter.sample()?;
+//!         for inv in inverter.inversions() {
+//!             warn!("{} waited {}ns while {} ran {}ns",
+//!                   inv.high_pid, inv.high_wait_ns, inv.low_pid, inv.low_run_ns);
+//!         }
+//!         std::thread::sleep(Duration::from_secs(1));
+//!     }
+//!```
+//!
+//! This is a diagnostic tool, not a scheduler component. Sampling is coarse
+//! and only approximates what actually happened between two samples.
+
+use crate::task::ProcStat;
+use crate::DomainSet;
+use crate::LocalDomainId;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InversionEvent {
+    /// The higher priority task which was kept waiting
+    pub high_pid: u32,
+    /// The lower priority task which ran instead
+    pub low_pid: u32,
+    /// The domain both tasks last ran in
+    pub domain: LocalDomainId,
+    /// The time the higher priority task spent waiting for a CPU
+    pub high_wait_ns: u64,
+    /// The time the lower priority task ran for
+    pub low_run_ns: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TaskSample {
+    run_ns: u64,
+    wait_ns: u64,
+    nice: i32,
+    cpu: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TaskDelta {
+    pid: u32,
+    run_ns: u64,
+    wait_ns: u64,
+    nice: i32,
+    domain: LocalDomainId,
+}
+
+fn read_task_sample(pid: u32) -> Result<TaskSample> {
+    let path = format!("/proc/{}/schedstat", pid);
+    let schedstat =
+        std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))?;
+    let fields: Vec<u64> = schedstat
+        .split_whitespace()
+        .map(|f| f.parse::<u64>())
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("Failed to parse {}", path))?;
+    if fields.len() < 2 {
+        bail!("Unexpected format of {}: {:?}", path, schedstat);
+    }
+    let stat = ProcStat::read(pid as i32)?;
+
+    Ok(TaskSample {
+        run_ns: fields[0],
+        wait_ns: fields[1],
+        nice: stat.nice,
+        cpu: stat.cpu,
+    })
+}
+
+#[derive(Debug)]
+pub struct TaskPriorityInverter {
+    domains: DomainSet,
+    tasks: BTreeMap<u32, Option<TaskSample>>,
+    inversions: Vec<InversionEvent>,
+}
+
+impl TaskPriorityInverter {
+    /// Create a TaskPriorityInverter which considers tasks to compete with
+    /// each other if they last ran on CPUs in the same domain of @domains.
+    pub fn new(domains: DomainSet) -> TaskPriorityInverter {
+        TaskPriorityInverter {
+            domains,
+            tasks: BTreeMap::new(),
+            inversions: vec![],
+        }
+    }
+
+    /// Start sampling @pid. Its first sample only establishes a baseline.
+    pub fn register(&mut self, pid: u32) {
+        self.tasks.entry(pid).or_insert(None);
+    }
+
+    /// Stop sampling @pid.
+    pub fn unregister(&mut self, pid: u32) {
+        self.tasks.remove(&pid);
+    }
+
+    /// Sample all registered PIDs and detect the priority inversions since
+    /// the previous sample. PIDs which exited are unregistered.
+    pub fn sample(&mut self) -> Result<()> {
+        let mut deltas = vec![];
+        let mut exited = vec![];
+
+        for (pid, last) in self.tasks.iter_mut() {
+            let cur = match read_task_sample(*pid) {
+                Ok(cur) => cur,
+                Err(_) if !std::path::Path::new(&format!("/proc/{}", pid)).exists() => {
+                    exited.push(*pid);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            if let (Some(prev), Some(domain)) = (*last, self.domains.domain_of(cur.cpu)) {
+                deltas.push(TaskDelta {
+                    pid: *pid,
+                    run_ns: cur.run_ns.saturating_sub(prev.run_ns),
+                    wait_ns: cur.wait_ns.saturating_sub(prev.wait_ns),
+                    nice: cur.nice,
+                    domain,
+                });
+            }
+            *last = Some(cur);
+        }
+
+        for pid in exited {
+            self.tasks.remove(&pid);
+        }
+
+        self.inversions = Self::detect(&deltas);
+        Ok(())
+    }
+
+    fn detect(deltas: &[TaskDelta]) -> Vec<InversionEvent> {
+        let mut inversions = vec![];
+        for high in deltas.iter().filter(|t| t.wait_ns > 0) {
+            for low in deltas.iter() {
+                if low.domain == high.domain && low.nice > high.nice && low.run_ns > high.run_ns {
+                    inversions.push(InversionEvent {
+                        high_pid: high.pid,
+                        low_pid: low.pid,
+                        domain: high.domain,
+                        high_wait_ns: high.wait_ns,
+                        low_run_ns: low.run_ns,
+                    });
+                }
+            }
+        }
+        inversions
+    }
+
+    /// Get the priority inversions detected by the last sample().
+    pub fn inversions(&self) -> Vec<InversionEvent> {
+        self.inversions.clone()
+    }
+}
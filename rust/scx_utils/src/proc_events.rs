@@ -0,0 +1,337 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Process Events
+//!
+//! A crate that allows schedulers which keep per-task state to learn about
+//! tasks being exec'd and exiting without polling /proc.
+//!
+//! ScxProcEventMonitor
+//! -------------------
+//!
+//! ScxProcEventMonitor::start() subscribes to the process events connector
+//! of the kernel (CONFIG_PROC_EVENTS) over netlink, and spawns a thread which
+//! sends the exec and exit events on a channel. The thread is stopped when
+//! the ScxProcEventMonitor is dropped:
+//!
+//!```
+//!     let (_monitor, events) = ScxProcEventMonitor::start()?;
+//!     for event in events.iter() {
+//!         if event.kind == ProcEventKind::Exit {
+//!             pids.remove(event.pid);
+//!         }
+//!     }
+//!```
+//!
+//! Subscribing requires CAP_NET_ADMIN. Exit events are reported for every
+//! thread, exec events for the thread which called exec.
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+
+const POLL_TIMEOUT_MS: i32 = 100;
+
+// From include/uapi/linux/connector.h and cn_proc.h
+const CN_IDX_PROC: u32 = 1;
+const CN_VAL_PROC: u32 = 1;
+const PROC_CN_MCAST_LISTEN: u32 = 1;
+const PROC_CN_MCAST_IGNORE: u32 = 2;
+const PROC_EVENT_EXEC: u32 = 0x0000_0002;
+const PROC_EVENT_EXIT: u32 = 0x8000_0000;
+
+const NLMSG_HDR_LEN: usize = 16;
+const CN_MSG_LEN: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProcEventKind {
+    Exec,
+    Exit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcEvent {
+    /// The thread which exec'd or exited
+    pub pid: u32,
+    /// The thread group, i.e. process, of the thread
+    pub tgid: u32,
+    pub kind: ProcEventKind,
+    /// The time of the event in nanoseconds since boot
+    pub ts_ns: u64,
+}
+
+fn read_u32(buf: &[u8], off: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(buf.get(off..off + 4)?.try_into().ok()?))
+}
+
+fn read_u64(buf: &[u8], off: usize) -> Option<u64> {
+    Some(u64::from_ne_bytes(buf.get(off..off + 8)?.try_into().ok()?))
+}
+
+impl ProcEvent {
+    // Parse the proc_event in the payload of a connector message. Returns
+    // None for events other than exec and exit.
+    fn parse(data: &[u8]) -> Option<ProcEvent> {
+        let kind = match read_u32(data, 0)? {
+            PROC_EVENT_EXEC => ProcEventKind::Exec,
+            PROC_EVENT_EXIT => ProcEventKind::Exit,
+            _ => return None,
+        };
+        Some(ProcEvent {
+            pid: read_u32(data, 16)?,
+            tgid: read_u32(data, 20)?,
+            kind,
+            ts_ns: read_u64(data, 8)?,
+        })
+    }
+
+    // Parse all events in the netlink messages in @buf.
+    fn parse_messages(mut buf: &[u8]) -> Vec<ProcEvent> {
+        let mut events = vec![];
+        while let Some(len) = read_u32(buf, 0) {
+            let len = len as usize;
+            if len < NLMSG_HDR_LEN || len > buf.len() {
+                break;
+            }
+
+            let msg = &buf[NLMSG_HDR_LEN..len];
+            let is_proc =
+                read_u32(msg, 0) == Some(CN_IDX_PROC) && read_u32(msg, 4) == Some(CN_VAL_PROC);
+            if is_proc {
+                if let Some(event) = msg.get(CN_MSG_LEN..).and_then(ProcEvent::parse) {
+                    events.push(event);
+                }
+            }
+
+            // Netlink messages are 4-byte aligned.
+            buf = buf.get(len.next_multiple_of(4)..).unwrap_or(&[]);
+        }
+        events
+    }
+}
+
+// Send a multicast subscription @op to the process events connector.
+fn send_mcast_op(sock: &OwnedFd, op: u32) -> Result<()> {
+    let len = NLMSG_HDR_LEN + CN_MSG_LEN + 4;
+    let mut msg = Vec::with_capacity(len);
+    // struct nlmsghdr
+    msg.extend_from_slice(&(len as u32).to_ne_bytes());
+    msg.extend_from_slice(&(libc::NLMSG_DONE as u16).to_ne_bytes());
+    msg.extend_from_slice(&0u16.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg.extend_from_slice(&std::process::id().to_ne_bytes());
+    // struct cn_msg
+    msg.extend_from_slice(&CN_IDX_PROC.to_ne_bytes());
+    msg.extend_from_slice(&CN_VAL_PROC.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg.extend_from_slice(&4u16.to_ne_bytes());
+    msg.extend_from_slice(&0u16.to_ne_bytes());
+    // enum proc_cn_mcast_op
+    msg.extend_from_slice(&op.to_ne_bytes());
+
+    let ret = unsafe {
+        libc::send(
+            sock.as_raw_fd(),
+            msg.as_ptr() as *const libc::c_void,
+            msg.len(),
+            0,
+        )
+    };
+    if ret < 0 {
+        bail!(
+            "Failed to send to the process events connector ({})",
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+fn read_events(sock: &OwnedFd, stop: &AtomicBool, tx: &mpsc::Sender<ProcEvent>) -> Result<()> {
+    let mut buf = vec![0u8; 64 * 1024];
+
+    while !stop.load(Ordering::Relaxed) {
+        let mut pollfd = libc::pollfd {
+            fd: sock.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut pollfd, 1, POLL_TIMEOUT_MS) } <= 0 {
+            continue;
+        }
+
+        let len = unsafe {
+            libc::recv(
+                sock.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                libc::MSG_DONTWAIT,
+            )
+        };
+        if len < 0 {
+            let err = std::io::Error::last_os_error();
+            match err.kind() {
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted => continue,
+                // The socket buffer overflowed and events were lost.
+                _ if err.raw_os_error() == Some(libc::ENOBUFS) => {
+                    log::warn!("Process events were dropped, the receiver is too slow");
+                    continue;
+                }
+                _ => return Err(err).context("Failed to read process events"),
+            }
+        }
+
+        for event in ProcEvent::parse_messages(&buf[..len as usize]) {
+            if tx.send(event).is_err() {
+                // The receiver is gone, nobody is interested anymore.
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct ScxProcEventMonitor {
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl ScxProcEventMonitor {
+    /// Subscribe to the process events and start reading them. Returns the
+    /// ScxProcEventMonitor, which must be kept alive for as long as events
+    /// should be read, and the channel the events are sent on.
+    pub fn start() -> Result<(ScxProcEventMonitor, mpsc::Receiver<ProcEvent>)> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_CONNECTOR,
+            )
+        };
+        if fd < 0 {
+            bail!(
+                "Failed to open netlink connector socket ({})",
+                std::io::Error::last_os_error()
+            );
+        }
+        let sock = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_pid = 0;
+        addr.nl_groups = CN_IDX_PROC;
+        let ret = unsafe {
+            libc::bind(
+                sock.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            bail!(
+                "Failed to bind to the process events connector ({}), is CAP_NET_ADMIN missing?",
+                std::io::Error::last_os_error()
+            );
+        }
+        send_mcast_op(&sock, PROC_CN_MCAST_LISTEN)
+            .context("Failed to subscribe to process events")?;
+
+        let (tx, rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::Builder::new()
+            .name("scx_proc_events".to_string())
+            .spawn(move || {
+                if let Err(e) = read_events(&sock, &thread_stop, &tx) {
+                    log::warn!("Stopped reading process events: {:#}", e);
+                }
+                // Older kernels count listeners globally and keep generating
+                // events until told otherwise.
+                let _ = send_mcast_op(&sock, PROC_CN_MCAST_IGNORE);
+            })
+            .context("Failed to spawn process events thread")?;
+
+        Ok((
+            ScxProcEventMonitor {
+                stop,
+                thread: Some(thread),
+            },
+            rx,
+        ))
+    }
+}
+
+impl Drop for ScxProcEventMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProcEvent;
+    use super::ProcEventKind;
+
+    fn message(what: u32, pid: u32, ts_ns: u64) -> Vec<u8> {
+        let mut event = vec![];
+        event.extend_from_slice(&what.to_ne_bytes());
+        event.extend_from_slice(&3u32.to_ne_bytes());
+        event.extend_from_slice(&ts_ns.to_ne_bytes());
+        event.extend_from_slice(&pid.to_ne_bytes());
+        event.extend_from_slice(&(pid - 1).to_ne_bytes());
+        event.extend_from_slice(&[0u8; 16]);
+
+        let len = 16 + 20 + event.len();
+        let mut msg = vec![];
+        msg.extend_from_slice(&(len as u32).to_ne_bytes());
+        msg.extend_from_slice(&[0u8; 12]);
+        msg.extend_from_slice(&1u32.to_ne_bytes());
+        msg.extend_from_slice(&1u32.to_ne_bytes());
+        msg.extend_from_slice(&[0u8; 8]);
+        msg.extend_from_slice(&(event.len() as u16).to_ne_bytes());
+        msg.extend_from_slice(&[0u8; 2]);
+        msg.extend_from_slice(&event);
+        msg
+    }
+
+    #[test]
+    fn test_parse_messages() {
+        let mut buf = message(0x2, 100, 1000);
+        // PROC_EVENT_FORK is ignored.
+        buf.extend(message(0x1, 200, 2000));
+        buf.extend(message(0x8000_0000, 300, 3000));
+
+        let events = ProcEvent::parse_messages(&buf);
+        assert_eq!(
+            events,
+            vec![
+                ProcEvent {
+                    pid: 100,
+                    tgid: 99,
+                    kind: ProcEventKind::Exec,
+                    ts_ns: 1000
+                },
+                ProcEvent {
+                    pid: 300,
+                    tgid: 299,
+                    kind: ProcEventKind::Exit,
+                    ts_ns: 3000
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Sampling Profiler
+//!
+//! A crate that allows scheduler authors to profile the userspace component
+//! of their scheduler without external tooling.
+//!
+//! ScxSamplingProfiler
+//! -------------------
+//!
+//! A ScxSamplingProfiler uses perf_event_open(2) with the PERF_TYPE_SOFTWARE /
+//! PERF_COUNT_SW_CPU_CLOCK event to sample the instruction pointer of a thread
+//! at the configured frequency while the thread is running:
+//!
+//!```
+//!     // Profile the calling thread at 999Hz.
+//!     let mut profiler = ScxSamplingProfiler::new(0, 999)?;
+//!     profiler.start()?;
+//!
+//!     do_work();
+//!
+//!     let ips = profiler.stop()?;
+//!     info!("collected {} samples", ips.len());
+//!```
+//!
+//! Samples are written by the kernel into a ring buffer shared with the
+//! profiler. If the profiled period is long, call collect() periodically to
+//! drain the ring buffer before it overflows; lost samples are accounted in
+//! nr_lost().
+//!
+//! Only userspace instruction pointers are sampled, so the profiler doesn't
+//! require elevated privileges beyond perf_event_paranoid <= 2, and doesn't
+//! interfere with a BPF scheduler running at the same time.
+//!
+//! If the addr2line feature is enabled, symbolize() can be used to resolve the
+//! collected instruction pointers to function names and source locations
+//! using the addr2line(1) tool.
+
+use crate::sys::perf_event_open;
+use crate::sys::PerfEventAttr;
+use crate::sys::PERF_EVENT_IOC_DISABLE;
+use crate::sys::PERF_EVENT_IOC_ENABLE;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::os::fd::IntoRawFd;
+use std::sync::atomic::fence;
+use std::sync::atomic::Ordering;
+
+const PERF_TYPE_SOFTWARE: u32 = 1;
+const PERF_COUNT_SW_CPU_CLOCK: u64 = 0;
+const PERF_SAMPLE_IP: u64 = 1 << 0;
+
+const PERF_ATTR_FLAG_DISABLED: u64 = 1 << 0;
+const PERF_ATTR_FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+const PERF_ATTR_FLAG_EXCLUDE_HV: u64 = 1 << 6;
+const PERF_ATTR_FLAG_FREQ: u64 = 1 << 10;
+
+const PERF_RECORD_LOST: u32 = 2;
+const PERF_RECORD_SAMPLE: u32 = 9;
+
+// Offsets of data_head and data_tail in struct perf_event_mmap_page.
+const MMAP_PAGE_DATA_HEAD: usize = 1024;
+const MMAP_PAGE_DATA_TAIL: usize = 1032;
+
+// Number of data pages in the ring buffer, must be a power of two.
+const NR_DATA_PAGES: usize = 64;
+
+#[derive(Debug)]
+pub struct ScxSamplingProfiler {
+    fd: libc::c_int,
+    mmap: *mut u8,
+    mmap_len: usize,
+    page_size: usize,
+    ips: Vec<usize>,
+    nr_lost: u64,
+}
+
+impl ScxSamplingProfiler {
+    /// Create a profiler which samples thread @tid, or the calling thread if
+    /// @tid is 0, @freq_hz times per second of CPU time. The profiler starts
+    /// out disabled.
+    pub fn new(tid: libc::pid_t, freq_hz: u64) -> Result<ScxSamplingProfiler> {
+        if freq_hz == 0 {
+            bail!("Sampling frequency must be positive");
+        }
+
+        let attr = PerfEventAttr {
+            type_: PERF_TYPE_SOFTWARE,
+            config: PERF_COUNT_SW_CPU_CLOCK,
+            sample_period: freq_hz,
+            sample_type: PERF_SAMPLE_IP,
+            flags: PERF_ATTR_FLAG_DISABLED
+                | PERF_ATTR_FLAG_EXCLUDE_KERNEL
+                | PERF_ATTR_FLAG_EXCLUDE_HV
+                | PERF_ATTR_FLAG_FREQ,
+            ..Default::default()
+        };
+
+        let fd = perf_event_open(attr, tid, -1)
+            .with_context(|| format!("perf_event_open() failed for tid {}", tid))?
+            .into_raw_fd();
+
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let mmap_len = page_size * (NR_DATA_PAGES + 1);
+        let mmap = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                mmap_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        if mmap == libc::MAP_FAILED {
+            let err = std::io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            bail!("Failed to mmap perf ring buffer ({})", err);
+        }
+
+        Ok(ScxSamplingProfiler {
+            fd,
+            mmap: mmap as *mut u8,
+            mmap_len,
+            page_size,
+            ips: vec![],
+            nr_lost: 0,
+        })
+    }
+
+    fn ioctl(&self, req: libc::c_ulong) -> Result<()> {
+        if unsafe { libc::ioctl(self.fd, req as _, 0) } < 0 {
+            bail!("perf ioctl failed ({})", std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Start sampling.
+    pub fn start(&mut self) -> Result<()> {
+        self.ioctl(PERF_EVENT_IOC_ENABLE)
+    }
+
+    /// Drain the samples written by the kernel so far into the profiler,
+    /// making room in the ring buffer.
+    pub fn collect(&mut self) {
+        let data = unsafe { self.mmap.add(self.page_size) };
+        let data_len = self.page_size * NR_DATA_PAGES;
+        let head_ptr = unsafe { self.mmap.add(MMAP_PAGE_DATA_HEAD) } as *const u64;
+        let tail_ptr = unsafe { self.mmap.add(MMAP_PAGE_DATA_TAIL) } as *mut u64;
+
+        let head = unsafe { std::ptr::read_volatile(head_ptr) } as usize;
+        fence(Ordering::Acquire);
+        let mut tail = unsafe { std::ptr::read_volatile(tail_ptr) } as usize;
+
+        // Records may wrap around the end of the ring buffer.
+        let read_u64 = |off: usize| -> u64 {
+            let mut bytes = [0u8; 8];
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte = unsafe { *data.add((off + i) % data_len) };
+            }
+            u64::from_ne_bytes(bytes)
+        };
+
+        while tail < head {
+            // struct perf_event_header { u32 type; u16 misc; u16 size; }
+            let header = read_u64(tail);
+            let rec_type = header as u32;
+            let rec_size = (header >> 48) as usize;
+            if rec_size == 0 {
+                break;
+            }
+
+            match rec_type {
+                PERF_RECORD_SAMPLE => self.ips.push(read_u64(tail + 8) as usize),
+                // struct { header; u64 id; u64 lost; }
+                PERF_RECORD_LOST => self.nr_lost += read_u64(tail + 16),
+                _ => {}
+            }
+            tail += rec_size;
+        }
+
+        fence(Ordering::Release);
+        unsafe { std::ptr::write_volatile(tail_ptr, head as u64) };
+    }
+
+    /// Stop sampling and return all instruction pointers sampled since the
+    /// profiler was created or last stopped.
+    pub fn stop(&mut self) -> Result<Vec<usize>> {
+        self.ioctl(PERF_EVENT_IOC_DISABLE)?;
+        self.collect();
+        Ok(std::mem::take(&mut self.ips))
+    }
+
+    /// The number of samples the kernel dropped because the ring buffer was
+    /// full.
+    pub fn nr_lost(&self) -> u64 {
+        self.nr_lost
+    }
+
+    /// Resolve instruction pointers sampled in the current process to
+    /// "function (file:line)" strings using addr2line(1). Instruction
+    /// pointers outside the main executable resolve to "??".
+    #[cfg(feature = "addr2line")]
+    pub fn symbolize(ips: &[usize]) -> Result<Vec<String>> {
+        use anyhow::Context;
+        use std::process::Command;
+
+        let exe = std::fs::read_link("/proc/self/exe")?;
+        let exe_str = exe.to_string_lossy().to_string();
+
+        // Translate the runtime addresses into file offsets of the
+        // executable, which may be loaded at a random base address.
+        let maps = std::fs::read_to_string("/proc/self/maps")?;
+        let mut ranges = vec![];
+        for line in maps.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 6 || fields[5] != exe_str {
+                continue;
+            }
+            let (start, end) = match fields[0].split_once('-') {
+                Some(range) => range,
+                None => continue,
+            };
+            let start = usize::from_str_radix(start, 16)?;
+            let end = usize::from_str_radix(end, 16)?;
+            let offset = usize::from_str_radix(fields[2], 16)?;
+            ranges.push((start, end, offset));
+        }
+
+        let offsets: Vec<Option<usize>> = ips
+            .iter()
+            .map(|ip| {
+                ranges
+                    .iter()
+                    .find(|(start, end, _)| ip >= start && ip < end)
+                    .map(|(start, _, offset)| ip - start + offset)
+            })
+            .collect();
+
+        let args: Vec<String> = offsets
+            .iter()
+            .flatten()
+            .map(|off| format!("{:#x}", off))
+            .collect();
+        let mut resolved = vec![];
+        if !args.is_empty() {
+            let output = Command::new("addr2line")
+                .args(["-f", "-C", "-e", &exe_str])
+                .args(&args)
+                .output()
+                .context("Failed to run addr2line")?;
+            let stdout = String::from_utf8(output.stdout)?;
+            let lines: Vec<&str> = stdout.lines().collect();
+            for pair in lines.chunks(2) {
+                match pair {
+                    [func, loc] => resolved.push(format!("{} ({})", func, loc)),
+                    _ => resolved.push("??".into()),
+                }
+            }
+        }
+
+        let mut resolved = resolved.into_iter();
+        Ok(offsets
+            .iter()
+            .map(|off| match off {
+                Some(_) => resolved.next().unwrap_or_else(|| "??".into()),
+                None => "??".into(),
+            })
+            .collect())
+    }
+}
+
+impl Drop for ScxSamplingProfiler {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.mmap as *mut libc::c_void, self.mmap_len);
+            libc::close(self.fd);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Scheduler Registry
+//!
+//! A crate that prevents two sched_ext schedulers from being loaded at the
+//! same time on the host.
+//!
+//! ScxSchedulerRegistry
+//! --------------------
+//!
+//! A ScxSchedulerRegistry holds an advisory lock on /run/scx/lock, which
+//! contains the PID of the scheduler holding it. Schedulers should acquire it
+//! before attaching their BPF scheduler, and keep it for as long as the BPF
+//! scheduler is attached:
+//!
+//!```
+//!     let _registry = ScxSchedulerRegistry::acquire()?;
+//!
+//!     let mut skel = skel_builder.open()?.load()?;
+//!     skel.attach()?;
+//!```
+//!
+//! The lock is released when the ScxSchedulerRegistry is dropped, or by the
+//! kernel when the scheduler process exits, even if it crashes.
+//!
+//! If another scheduler holds the lock, acquire() fails with
+//! ScxError::SchedulerAlreadyRunning, which can be told apart from other
+//! errors with downcast_ref():
+//!
+//!```
+//!     match ScxSchedulerRegistry::acquire() {
+//!         Ok(registry) => registry,
+//!         Err(e) => match e.downcast_ref::<ScxError>() {
+//!             Some(ScxError::SchedulerAlreadyRunning { pid }) => ...,
+//!             None => return Err(e),
+//!         },
+//!     }
+//!```
+
+use anyhow::Context;
+use anyhow::Result;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+
+const LOCK_PATH: &str = "/run/scx/lock";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScxError {
+    /// Another scheduler holds the lock. @pid is None if its PID couldn't be
+    /// read from the lock file, e.g. as it is still being written.
+    SchedulerAlreadyRunning { pid: Option<u32> },
+    /// The caller lacks the permission to access the process @pid, e.g. to
+    /// query or change its CPU affinity.
+    PermissionDenied { pid: i32 },
+}
+
+impl fmt::Display for ScxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScxError::SchedulerAlreadyRunning { pid: Some(pid) } => write!(
+                f,
+                "Another sched_ext scheduler is already running (pid {})",
+                pid
+            ),
+            ScxError::SchedulerAlreadyRunning { pid: None } => {
+                write!(f, "Another sched_ext scheduler is already running")
+            }
+            ScxError::PermissionDenied { pid } => {
+                write!(f, "Permission denied to access pid {}", pid)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScxError {}
+
+#[derive(Debug)]
+pub struct ScxSchedulerRegistry {
+    file: File,
+    path: PathBuf,
+}
+
+impl ScxSchedulerRegistry {
+    /// Register the current process as the running scheduler by locking
+    /// /run/scx/lock. Returns ScxError::SchedulerAlreadyRunning with the PID
+    /// of the running scheduler if another scheduler holds the lock.
+    pub fn acquire() -> Result<ScxSchedulerRegistry> {
+        Self::acquire_at(Path::new(LOCK_PATH))
+    }
+
+    /// Same as acquire(), but lock the file at @path instead of
+    /// /run/scx/lock, creating its parent directories if needed.
+    pub fn acquire_at(path: &Path) -> Result<ScxSchedulerRegistry> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("Failed to open {:?}", path))?;
+
+        let ret = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+                let mut pid = String::new();
+                let _ = file.read_to_string(&mut pid);
+                return Err(ScxError::SchedulerAlreadyRunning {
+                    pid: pid.trim().parse().ok(),
+                }
+                .into());
+            }
+            return Err(err).with_context(|| format!("Failed to lock {:?}", path));
+        }
+
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())
+            .with_context(|| format!("Failed to write to {:?}", path))?;
+
+        Ok(ScxSchedulerRegistry {
+            file,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// The path of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScxSchedulerRegistry {
+    fn drop(&mut self) {
+        // Clear the PID while still holding the lock. The lock itself is
+        // released when the file is closed. The file is left in place, as
+        // removing it would race with other schedulers locking it.
+        let _ = self.file.set_len(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScxError;
+    use super::ScxSchedulerRegistry;
+
+    #[test]
+    fn test_registry_already_running() {
+        let dir = std::env::temp_dir().join(format!("scx_registry.{}", std::process::id()));
+        let path = dir.join("lock");
+
+        let registry = ScxSchedulerRegistry::acquire_at(&path).unwrap();
+        let err = ScxSchedulerRegistry::acquire_at(&path).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ScxError>(),
+            Some(&ScxError::SchedulerAlreadyRunning {
+                pid: Some(std::process::id())
+            })
+        );
+
+        drop(registry);
+        assert!(ScxSchedulerRegistry::acquire_at(&path).is_ok());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
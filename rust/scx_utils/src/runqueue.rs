@@ -0,0 +1,198 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Run Queue
+//!
+//! A crate that allows scheduler authors to model the DSQs of their BPF
+//! scheduler in Rust, e.g. to unit test scheduling algorithms before
+//! deploying them in BPF.
+//!
+//! ScxRunQueue
+//! -----------
+//!
+//! A ScxRunQueue is a min-heap of ScxTasks ordered by vruntime, like a DSQ
+//! which tasks are dispatched to with scx_bpf_dispatch_vtime(). Tasks with
+//! the same vruntime are dequeued in FIFO order. Dequeueing on behalf of a
+//! CPU respects the CPU affinity of the tasks:
+//!
+//!```
+//!     let mut rq = ScxRunQueue::new();
+//!     rq.enqueue(ScxTask { pid: 1, weight: 100, vruntime: 20, cpu_affinity: all_cpus });
+//!     rq.enqueue(ScxTask { pid: 2, weight: 100, vruntime: 10, cpu_affinity: cpu_1_only });
+//!
+//!     // pid 2 has the lower vruntime, but can't run on CPU 0.
+//!     assert_eq!(rq.dequeue_for_cpu(0).unwrap().pid, 1);
+//!```
+
+use crate::Cpumask;
+use crate::ScxDebugDump;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::io;
+use std::io::Write;
+
+#[derive(Debug, Clone)]
+pub struct ScxTask {
+    pub pid: u32,
+    pub weight: u32,
+    pub vruntime: u64,
+    pub cpu_affinity: Cpumask,
+}
+
+#[derive(Debug)]
+struct QueuedTask {
+    task: ScxTask,
+    seq: u64,
+}
+
+impl QueuedTask {
+    fn key(&self) -> (u64, u64) {
+        (self.task.vruntime, self.seq)
+    }
+}
+
+impl PartialEq for QueuedTask {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for QueuedTask {}
+
+impl PartialOrd for QueuedTask {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedTask {
+    // BinaryHeap is a max-heap, reverse the order to pop the lowest vruntime
+    // and, among equal vruntimes, the earliest enqueued task first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key().cmp(&self.key())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ScxRunQueue {
+    heap: BinaryHeap<QueuedTask>,
+    seq: u64,
+}
+
+impl ScxRunQueue {
+    /// Create an empty ScxRunQueue.
+    pub fn new() -> ScxRunQueue {
+        Self::default()
+    }
+
+    /// Queue @task.
+    pub fn enqueue(&mut self, task: ScxTask) {
+        self.seq += 1;
+        self.heap.push(QueuedTask {
+            task,
+            seq: self.seq,
+        });
+    }
+
+    /// Dequeue the task with the lowest vruntime which is allowed to run on
+    /// @cpu, or None if there is no such task.
+    pub fn dequeue_for_cpu(&mut self, cpu: usize) -> Option<ScxTask> {
+        let mut skipped = vec![];
+        let mut found = None;
+
+        while let Some(queued) = self.heap.pop() {
+            if queued.task.cpu_affinity.test_cpu(cpu) {
+                found = Some(queued.task);
+                break;
+            }
+            skipped.push(queued);
+        }
+
+        self.heap.extend(skipped);
+        found
+    }
+
+    /// The number of queued tasks.
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    /// Test whether no task is queued.
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// Dequeue all tasks, in the order they would have been dequeued.
+    pub fn drain(&mut self) -> Vec<ScxTask> {
+        let mut tasks = Vec::with_capacity(self.heap.len());
+        while let Some(queued) = self.heap.pop() {
+            tasks.push(queued.task);
+        }
+        tasks
+    }
+}
+
+impl ScxDebugDump for ScxRunQueue {
+    fn dump(&self, w: &mut dyn Write) -> io::Result<()> {
+        writeln!(w, "runqueue: nr_tasks={}", self.len())?;
+        let mut queued: Vec<&QueuedTask> = self.heap.iter().collect();
+        queued.sort_by(|a, b| b.cmp(a));
+        for q in queued {
+            writeln!(
+                w,
+                "  pid={} weight={} vruntime={} cpus={}",
+                q.task.pid,
+                q.task.weight,
+                q.task.vruntime,
+                q.task.cpu_affinity.to_cgroup_cpuset_string()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScxRunQueue;
+    use super::ScxTask;
+    use crate::Cpumask;
+
+    fn task(pid: u32, vruntime: u64, allowed: bool) -> ScxTask {
+        let mut cpu_affinity = Cpumask::new().unwrap();
+        if allowed {
+            cpu_affinity.setall();
+        }
+        ScxTask {
+            pid,
+            weight: 100,
+            vruntime,
+            cpu_affinity,
+        }
+    }
+
+    #[test]
+    fn test_runqueue_vruntime_order() {
+        let mut rq = ScxRunQueue::new();
+        rq.enqueue(task(1, 30, true));
+        rq.enqueue(task(2, 10, true));
+        rq.enqueue(task(3, 20, true));
+        rq.enqueue(task(4, 10, true));
+
+        let pids: Vec<u32> = rq.drain().iter().map(|t| t.pid).collect();
+        assert_eq!(pids, vec![2, 4, 3, 1]);
+        assert!(rq.is_empty());
+    }
+
+    #[test]
+    fn test_runqueue_affinity() {
+        let mut rq = ScxRunQueue::new();
+        rq.enqueue(task(1, 10, false));
+        rq.enqueue(task(2, 20, true));
+
+        assert_eq!(rq.dequeue_for_cpu(0).map(|t| t.pid), Some(2));
+        assert!(rq.dequeue_for_cpu(0).is_none());
+        assert_eq!(rq.len(), 1);
+    }
+}
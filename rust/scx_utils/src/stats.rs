@@ -0,0 +1,120 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Stats Collector
+//!
+//! A crate that allows schedulers to periodically collect the statistics
+//! their BPF side accumulates in a BPF map, without having to spawn and
+//! manage a thread of their own.
+//!
+//! ScxStats
+//! --------
+//!
+//! ScxStats::spawn_collector() starts a background thread which reads all
+//! entries of a BPF map every interval, converts them into a statistics
+//! object of the scheduler's choice using a deserialization function, and
+//! passes the result to a callback. The thread is stopped when the returned
+//! CollectorHandle is dropped:
+//!
+//!```
+//!     let _collector = ScxStats::spawn_collector(
+//!         Duration::from_secs(1),
+//!         skel.maps().stats(),
+//!         |entries| {
+//!             // One u64 counter per array entry.
+//!             Ok(entries
+//!                 .iter()
+//!                 .map(|v| u64::from_ne_bytes(v[..8].try_into().unwrap()))
+//!                 .collect::<Vec<u64>>())
+//!         },
+//!         |snapshot| info!("{:?}", snapshot.stats),
+//!     )?;
+//!```
+
+use crate::worker::PeriodicThread;
+use anyhow::Context;
+use anyhow::Result;
+use libbpf_rs::Map;
+use libbpf_rs::MapFlags;
+use libbpf_rs::MapHandle;
+use libbpf_rs::MapType;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(Debug, Clone)]
+pub struct StatsSnapshot<T> {
+    /// When the statistics were read from the BPF map
+    pub timestamp: Instant,
+    /// The statistics as returned by the deserialization function
+    pub stats: T,
+}
+
+/// Stops the collector thread when dropped. See ScxStats::spawn_collector().
+#[derive(Debug)]
+pub struct CollectorHandle {
+    _thread: PeriodicThread,
+}
+
+#[derive(Debug)]
+pub struct ScxStats;
+
+fn read_entries(map: &MapHandle) -> Result<Vec<Vec<u8>>> {
+    let percpu = matches!(
+        map.map_type(),
+        MapType::PercpuArray | MapType::PercpuHash | MapType::LruPercpuHash
+    );
+
+    let mut entries = vec![];
+    for key in map.keys() {
+        let value = if percpu {
+            map.lookup_percpu(&key, MapFlags::ANY)?
+                .map(|values| values.concat())
+        } else {
+            map.lookup(&key, MapFlags::ANY)?
+        };
+        // The entry may have been deleted since the key was read.
+        if let Some(value) = value {
+            entries.push(value);
+        }
+    }
+    Ok(entries)
+}
+
+impl ScxStats {
+    /// Spawn a thread which reads all entries of @map every @interval and
+    /// calls @cb with the result of @deserialize on the entries.
+    ///
+    /// @deserialize is passed the value of each entry in key iteration order,
+    /// which is ascending index order for array maps. For per-CPU maps, each
+    /// value holds the per-CPU values of the entry concatenated, starting
+    /// with CPU 0. Errors reading the map or deserializing the entries are
+    /// logged, and collection continues with the next interval.
+    pub fn spawn_collector<T, D, C>(
+        interval: Duration,
+        map: &Map,
+        deserialize: D,
+        cb: C,
+    ) -> Result<CollectorHandle>
+    where
+        D: Fn(&[Vec<u8>]) -> Result<T> + Send + 'static,
+        C: Fn(StatsSnapshot<T>) + Send + 'static,
+    {
+        // Map can't be shared across threads, use a handle of its own instead.
+        let map = MapHandle::try_clone(map)
+            .with_context(|| format!("Failed to clone handle of map {}", map.name()))?;
+        let name = format!("scx_stats_{}", map.name());
+
+        let thread = PeriodicThread::spawn(name, interval, false, move || {
+            let timestamp = Instant::now();
+            match read_entries(&map).and_then(|entries| deserialize(&entries)) {
+                Ok(stats) => cb(StatsSnapshot { timestamp, stats }),
+                Err(e) => log::warn!("Failed to collect stats from {}: {:#}", map.name(), e),
+            }
+        })
+        .context("Failed to spawn stats collector thread")?;
+
+        Ok(CollectorHandle { _thread: thread })
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Work Stealing
+//!
+//! A crate that allows schedulers to balance load across scheduling domains
+//! by letting underloaded domains steal tasks from overloaded ones.
+//!
+//! ScxWorkStealing
+//! ---------------
+//!
+//! A ScxWorkStealing tracks the run queue length of each domain, as reported
+//! by the BPF side, and recommends which domain a domain should steal from.
+//! Like in Cilk, the victim is the most loaded domain. To avoid tasks
+//! bouncing back and forth between domains with similar loads, stealing is
+//! only recommended if the victim's run queue is at least a threshold longer
+//! than the thief's:
+//!
+//!```
+//!     let steal = RefCell::new(ScxWorkStealing::new(nr_doms));
+//!
+//!     let mut builder = libbpf_rs::RingBufferBuilder::new();
+//!     builder.add(skel.maps().dom_events(), |data| {
+//!         steal.borrow_mut().push_bytes(data).map_or(-1, |_| 0)
+//!     })?;
+//!
+//!     // ...
+//!
+//!     if let Some(src) = steal.borrow().steal_recommendation(dom) {
+//!         migrate_tasks(src, dom);
+//!     }
+//!```
+//!
+//! The events are expected to mirror the following struct on the BPF side:
+//!
+//!```
+//!     struct scx_dom_queue_event {
+//!         u32 dom_id;
+//!         u32 nr_queued;
+//!     };
+//!```
+
+use anyhow::bail;
+use anyhow::Result;
+use bytemuck::Pod;
+use bytemuck::Zeroable;
+
+const DEFAULT_STEAL_THRESHOLD: u32 = 2;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Pod, Zeroable)]
+struct DomQueueEvent {
+    dom_id: u32,
+    nr_queued: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScxWorkStealing {
+    queue_lens: Vec<u32>,
+    threshold: u32,
+}
+
+impl ScxWorkStealing {
+    /// Create a ScxWorkStealing for @nr_doms domains with empty run queues.
+    /// Stealing is recommended if the victim has at least 2 more queued tasks
+    /// than the thief.
+    pub fn new(nr_doms: usize) -> ScxWorkStealing {
+        ScxWorkStealing {
+            queue_lens: vec![0; nr_doms],
+            threshold: DEFAULT_STEAL_THRESHOLD,
+        }
+    }
+
+    /// Set the minimum difference in run queue length between the victim and
+    /// the thief for stealing to be recommended. A threshold of 0 is treated
+    /// as 1, as stealing from an equally loaded domain never helps.
+    pub fn set_threshold(&mut self, threshold: u32) {
+        self.threshold = threshold.max(1);
+    }
+
+    /// Get the steal threshold. See set_threshold().
+    pub fn threshold(&self) -> u32 {
+        self.threshold
+    }
+
+    /// Set the run queue length of domain @dom to @nr_queued. Returns an error
+    /// if the domain doesn't exist.
+    pub fn update(&mut self, dom: usize, nr_queued: u32) -> Result<()> {
+        match self.queue_lens.get_mut(dom) {
+            Some(len) => *len = nr_queued,
+            None => bail!(
+                "Domain {} out of range of {} domains",
+                dom,
+                self.queue_lens.len()
+            ),
+        }
+        Ok(())
+    }
+
+    /// Deserialize a run queue length update as emitted by the BPF side, e.g.
+    /// into a BPF ring buffer, and apply it.
+    pub fn push_bytes(&mut self, data: &[u8]) -> Result<()> {
+        let size = std::mem::size_of::<DomQueueEvent>();
+        if data.len() < size {
+            bail!(
+                "Domain queue event of {} bytes is shorter than {} bytes",
+                data.len(),
+                size
+            );
+        }
+        let event: DomQueueEvent = bytemuck::pod_read_unaligned(&data[..size]);
+        self.update(event.dom_id as usize, event.nr_queued)
+    }
+
+    /// Get the run queue length of domain @dom.
+    pub fn queue_len(&self, dom: usize) -> Option<u32> {
+        self.queue_lens.get(dom).copied()
+    }
+
+    /// Get the difference in run queue length between the most and the least
+    /// loaded domains.
+    pub fn imbalance(&self) -> u32 {
+        let max = self.queue_lens.iter().max().copied().unwrap_or(0);
+        let min = self.queue_lens.iter().min().copied().unwrap_or(0);
+        max - min
+    }
+
+    /// Get the domain @dst_dom should steal tasks from, i.e. the most loaded
+    /// other domain, or None if no domain is loaded heavily enough compared
+    /// to @dst_dom. Ties are broken in favor of the lowest domain ID.
+    pub fn steal_recommendation(&self, dst_dom: usize) -> Option<usize> {
+        let dst_len = self.queue_len(dst_dom)?;
+
+        let mut victim: Option<(usize, u32)> = None;
+        for (dom, len) in self.queue_lens.iter().enumerate() {
+            if dom == dst_dom {
+                continue;
+            }
+            match victim {
+                Some((_, max_len)) if max_len >= *len => {}
+                _ => victim = Some((dom, *len)),
+            }
+        }
+
+        match victim {
+            Some((dom, len)) if len >= dst_len.saturating_add(self.threshold) => Some(dom),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScxWorkStealing;
+
+    #[test]
+    fn test_steal_from_most_loaded() {
+        let mut steal = ScxWorkStealing::new(4);
+        steal.update(0, 1).unwrap();
+        steal.update(1, 5).unwrap();
+        steal.update(2, 5).unwrap();
+        steal.update(3, 2).unwrap();
+
+        assert_eq!(steal.imbalance(), 4);
+        assert_eq!(steal.steal_recommendation(0), Some(1));
+        assert_eq!(steal.steal_recommendation(3), Some(1));
+        assert_eq!(steal.steal_recommendation(1), None);
+        assert_eq!(steal.steal_recommendation(4), None);
+        assert!(steal.update(4, 0).is_err());
+    }
+
+    #[test]
+    fn test_steal_threshold() {
+        let mut steal = ScxWorkStealing::new(2);
+        steal.update(0, 3).unwrap();
+        steal.update(1, 4).unwrap();
+        assert_eq!(steal.steal_recommendation(0), None);
+
+        steal.set_threshold(0);
+        assert_eq!(steal.steal_recommendation(0), Some(1));
+        assert_eq!(steal.steal_recommendation(1), None);
+
+        steal.push_bytes(&[0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(steal.queue_len(0), Some(0));
+        assert!(steal.push_bytes(&[0, 0]).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Kernel Interfaces
+//!
+//! Definitions of the perf_event_open(2) and bpf(2) interfaces for the
+//! modules which use them directly, e.g. to load the small built-in BPF
+//! programs of the crate without going through a skeleton.
+
+use libbpf_rs::libbpf_sys;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+// _IO('$', 0) and _IO('$', 1)
+pub(crate) const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+pub(crate) const PERF_EVENT_IOC_DISABLE: libc::c_ulong = 0x2401;
+
+/// struct perf_event_attr up to PERF_ATTR_SIZE_VER5.
+#[repr(C)]
+#[derive(Default)]
+pub(crate) struct PerfEventAttr {
+    pub type_: u32,
+    pub size: u32,
+    pub config: u64,
+    /// Union of sample_period and sample_freq, the latter if the freq flag
+    /// is set.
+    pub sample_period: u64,
+    pub sample_type: u64,
+    pub read_format: u64,
+    pub flags: u64,
+    pub wakeup_events: u32,
+    pub bp_type: u32,
+    pub config1: u64,
+    pub config2: u64,
+    pub branch_sample_type: u64,
+    pub sample_regs_user: u64,
+    pub sample_stack_user: u32,
+    pub clockid: i32,
+    pub sample_regs_intr: u64,
+    pub aux_watermark: u32,
+    pub sample_max_stack: u16,
+    pub reserved_2: u16,
+}
+
+/// Open a perf event for @attr on thread @pid and CPU @cpu, where -1 means
+/// any thread and any CPU respectively. @attr.size is filled in.
+pub(crate) fn perf_event_open(
+    mut attr: PerfEventAttr,
+    pid: libc::pid_t,
+    cpu: libc::c_int,
+) -> std::io::Result<OwnedFd> {
+    attr.size = std::mem::size_of::<PerfEventAttr>() as u32;
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            &attr as *const PerfEventAttr,
+            pid,
+            cpu,
+            -1 as libc::c_int,
+            PERF_FLAG_FD_CLOEXEC,
+        )
+    } as libc::c_int;
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Encode a BPF instruction. A 64-bit immediate load takes two instructions,
+/// the second of which only holds the upper half of the immediate.
+pub(crate) fn insn(code: u32, dst: u8, src: u8, off: i16, imm: i32) -> libbpf_sys::bpf_insn {
+    libbpf_sys::bpf_insn {
+        code: code as u8,
+        _bitfield_1: libbpf_sys::bpf_insn::new_bitfield_1(dst, src),
+        off,
+        imm,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::insn;
+    use super::PerfEventAttr;
+
+    #[test]
+    fn test_perf_event_attr_size() {
+        // PERF_ATTR_SIZE_VER5
+        assert_eq!(std::mem::size_of::<PerfEventAttr>(), 112);
+    }
+
+    #[test]
+    fn test_insn() {
+        let insn = insn(0xb7, 1, 2, -4, 42);
+        assert_eq!(insn.code, 0xb7);
+        assert_eq!(insn.dst_reg(), 1);
+        assert_eq!(insn.src_reg(), 2);
+        assert_eq!(insn.off, -4);
+        assert_eq!(insn.imm, 42);
+    }
+}
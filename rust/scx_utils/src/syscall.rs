@@ -0,0 +1,244 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Syscall Monitor
+//!
+//! A crate that allows schedulers to find the tasks which frequently call
+//! scheduling related syscalls, e.g. to tune the treatment of tasks which
+//! spin on sched_yield(2).
+//!
+//! ScxSyscallMonitor
+//! -----------------
+//!
+//! ScxSyscallMonitor counts the calls of sched_yield(2),
+//! sched_getaffinity(2) and sched_setscheduler(2) per PID. It ships its own
+//! small BPF program, which is attached to the sys_enter raw tracepoint and
+//! counts the calls in a BPF hash map, so no BPF code is needed on the
+//! scheduler side. A ScxSyscallMonitor can be created without privileges,
+//! the program is only loaded and attached by attach(), which requires
+//! CAP_BPF and CAP_PERFMON. The program is detached when the
+//! ScxSyscallMonitor is dropped:
+//!
+//!```
+//!     let mut monitor = ScxSyscallMonitor::new(4096);
+//!     monitor.attach()?;
+//!     loop {
+//!         std::thread::sleep(Duration::from_secs(10));
+//!         for (pid, calls) in monitor.top_callers(5) {
+//!             info!("PID {} made {} scheduling syscalls", pid, calls);
+//!         }
+//!     }
+//!```
+//!
+//! Calls are counted per PID in the user space sense, i.e. per thread group,
+//! from the time of attach() on. Once the counts of @max_pids PIDs are
+//! tracked, calls of further PIDs are ignored.
+
+use crate::sys::insn;
+use anyhow::bail;
+use anyhow::Result;
+use libbpf_rs::libbpf_sys;
+use libbpf_rs::MapFlags;
+use libbpf_rs::MapHandle;
+use libbpf_rs::MapType;
+use std::ffi::CString;
+use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+
+const SYSCALLS: [libc::c_long; 3] = [
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_sched_setscheduler,
+];
+
+// Build the program for the sys_enter raw tracepoint, whose second argument
+// is the syscall number. The equivalent C is:
+//
+//     if (ctx->args[1] != SYS_sched_yield && ...)
+//         return 0;
+//     pid = bpf_get_current_pid_tgid() >> 32;
+//     cnt = bpf_map_lookup_elem(&counts, &pid);
+//     if (cnt)
+//         __sync_fetch_and_add(cnt, 1);
+//     else
+//         bpf_map_update_elem(&counts, &pid, &one, BPF_NOEXIST);
+//     return 0;
+//
+// A racing first call of another thread of the same PID may make the update
+// fail, which loses a single count.
+fn counter_prog(map_fd: i32) -> Vec<libbpf_sys::bpf_insn> {
+    use libbpf_sys::*;
+
+    let ld_map_fd = |dst| {
+        [
+            insn(
+                BPF_LD | BPF_DW | BPF_IMM,
+                dst,
+                BPF_PSEUDO_MAP_FD as u8,
+                0,
+                map_fd,
+            ),
+            insn(0, 0, 0, 0, 0),
+        ]
+    };
+    let mov_imm = |dst, imm| insn(BPF_ALU64 | BPF_MOV | BPF_K, dst, 0, 0, imm);
+    let stack_ptr = |dst, off| {
+        [
+            insn(BPF_ALU64 | BPF_MOV | BPF_X, dst, 10, 0, 0),
+            insn(BPF_ALU64 | BPF_ADD | BPF_K, dst, 0, 0, off),
+        ]
+    };
+    let call = |func| insn(BPF_JMP | BPF_CALL, 0, 0, 0, func as i32);
+    let exit = [mov_imm(0, 0), insn(BPF_JMP | BPF_EXIT, 0, 0, 0, 0)];
+
+    let mut prog = vec![insn(BPF_LDX | BPF_DW | BPF_MEM, 6, 1, 8, 0)];
+    // Jump over the remaining comparisons and the exit to the counting.
+    for (i, nr) in SYSCALLS.iter().enumerate() {
+        let off = (SYSCALLS.len() - i - 1 + exit.len()) as i16;
+        prog.push(insn(BPF_JMP | BPF_JEQ | BPF_K, 6, 0, off, *nr as i32));
+    }
+    prog.extend(exit);
+
+    prog.push(call(BPF_FUNC_get_current_pid_tgid));
+    prog.push(insn(BPF_ALU64 | BPF_RSH | BPF_K, 0, 0, 0, 32));
+    prog.push(insn(BPF_STX | BPF_W | BPF_MEM, 10, 0, -4, 0));
+    prog.extend(ld_map_fd(1));
+    prog.extend(stack_ptr(2, -4));
+    prog.push(call(BPF_FUNC_map_lookup_elem));
+
+    let mut insert = vec![insn(BPF_ST | BPF_DW | BPF_MEM, 10, 0, -16, 1)];
+    insert.extend(ld_map_fd(1));
+    insert.extend(stack_ptr(2, -4));
+    insert.extend(stack_ptr(3, -16));
+    insert.push(mov_imm(4, BPF_NOEXIST as i32));
+    insert.push(call(BPF_FUNC_map_update_elem));
+
+    // Skip the increment and the jump over the insertion if NULL.
+    prog.push(insn(BPF_JMP | BPF_JEQ | BPF_K, 0, 0, 3, 0));
+    prog.push(mov_imm(1, 1));
+    prog.push(insn(BPF_STX | BPF_DW | BPF_ATOMIC, 0, 1, 0, BPF_ADD as i32));
+    prog.push(insn(BPF_JMP | BPF_JA, 0, 0, insert.len() as i16, 0));
+    prog.extend(insert);
+    prog.extend(exit);
+    prog
+}
+
+#[derive(Debug)]
+struct SyscallCounter {
+    counts: MapHandle,
+    // Closing the link detaches the program.
+    _link: OwnedFd,
+    _prog: OwnedFd,
+}
+
+#[derive(Debug)]
+pub struct ScxSyscallMonitor {
+    max_pids: u32,
+    counter: Option<SyscallCounter>,
+}
+
+impl ScxSyscallMonitor {
+    /// Create a ScxSyscallMonitor which counts the calls of up to @max_pids
+    /// PIDs once attached.
+    pub fn new(max_pids: u32) -> ScxSyscallMonitor {
+        ScxSyscallMonitor {
+            max_pids,
+            counter: None,
+        }
+    }
+
+    /// Whether attach() succeeded.
+    pub fn is_attached(&self) -> bool {
+        self.counter.is_some()
+    }
+
+    /// Load the counting BPF program and attach it to the sys_enter raw
+    /// tracepoint. Does nothing if already attached.
+    pub fn attach(&mut self) -> Result<()> {
+        if self.counter.is_some() {
+            return Ok(());
+        }
+        if self.max_pids == 0 {
+            bail!("At least one PID must be tracked");
+        }
+
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: std::mem::size_of::<libbpf_sys::bpf_map_create_opts>() as _,
+            ..Default::default()
+        };
+        let counts = MapHandle::create(
+            MapType::Hash,
+            Some("scx_syscalls"),
+            std::mem::size_of::<u32>() as u32,
+            std::mem::size_of::<u64>() as u32,
+            self.max_pids,
+            &opts,
+        )?;
+
+        let insns = counter_prog(counts.as_fd().as_raw_fd());
+        let name = CString::new("scx_syscalls").unwrap();
+        let license = CString::new("GPL").unwrap();
+        let fd = unsafe {
+            libbpf_sys::bpf_prog_load(
+                libbpf_sys::BPF_PROG_TYPE_RAW_TRACEPOINT,
+                name.as_ptr(),
+                license.as_ptr(),
+                insns.as_ptr(),
+                insns.len() as _,
+                std::ptr::null_mut(),
+            )
+        };
+        if fd < 0 {
+            bail!(
+                "Failed to load syscall counting program ({})",
+                std::io::Error::from_raw_os_error(-fd)
+            );
+        }
+        let prog = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let tp = CString::new("sys_enter").unwrap();
+        let fd = unsafe { libbpf_sys::bpf_raw_tracepoint_open(tp.as_ptr(), prog.as_raw_fd()) };
+        if fd < 0 {
+            bail!(
+                "Failed to attach syscall counting program to sys_enter ({})",
+                std::io::Error::from_raw_os_error(-fd)
+            );
+        }
+        let link = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        self.counter = Some(SyscallCounter {
+            counts,
+            _link: link,
+            _prog: prog,
+        });
+        Ok(())
+    }
+
+    /// Get the @n PIDs with the most calls as (pid, calls), ordered from the
+    /// most calls to the least. Empty if not attached.
+    pub fn top_callers(&self, n: usize) -> Vec<(u32, u64)> {
+        let counter = match &self.counter {
+            Some(counter) => counter,
+            None => return vec![],
+        };
+
+        let mut callers: Vec<(u32, u64)> = counter
+            .counts
+            .keys()
+            .filter_map(|key| {
+                let val = counter.counts.lookup(&key, MapFlags::ANY).ok()??;
+                Some((
+                    u32::from_ne_bytes(key.as_slice().try_into().ok()?),
+                    u64::from_ne_bytes(val.as_slice().try_into().ok()?),
+                ))
+            })
+            .collect();
+        callers.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        callers.truncate(n);
+        callers
+    }
+}
@@ -0,0 +1,285 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Task Info
+//!
+//! A crate that allows schedulers to read the scheduling state of a task
+//! from procfs in one go.
+//!
+//! ScxTaskInfo
+//! -----------
+//!
+//! ScxTaskInfo::snapshot() reads /proc/[pid]/stat, status, schedstat,
+//! cpuset and, if the kernel has CONFIG_SCHED_DEBUG, sched, and exposes the
+//! scheduling related fields as typed values:
+//!
+//!```
+//!     let prev = ScxTaskInfo::snapshot(pid)?;
+//!     std::thread::sleep(Duration::from_secs(1));
+//!     let cur = ScxTaskInfo::snapshot(pid)?;
+//!
+//!     info!("{} ({:?}) nice={} cpus={} usage={:.1}%",
+//!           cur.comm, cur.policy, cur.nice, cur.cpus_allowed,
+//!           cur.cpu_usage_since(&prev) * 100.0);
+//!```
+//!
+//! The files are read one after the other, not atomically. Fields read from
+//! different files may therefore be slightly out of sync with each other if
+//! the task runs while the snapshot is taken.
+
+use crate::Cpumask;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The scheduling policy of a task, see sched(7).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScxSchedPolicy {
+    Normal,
+    Fifo,
+    Rr,
+    Batch,
+    Idle,
+    Deadline,
+    Ext,
+    Other(u32),
+}
+
+impl From<u32> for ScxSchedPolicy {
+    fn from(policy: u32) -> ScxSchedPolicy {
+        match policy {
+            0 => Self::Normal,
+            1 => Self::Fifo,
+            2 => Self::Rr,
+            3 => Self::Batch,
+            5 => Self::Idle,
+            6 => Self::Deadline,
+            7 => Self::Ext,
+            other => Self::Other(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScxTaskInfo {
+    pub pid: i32,
+    pub comm: String,
+    /// The state, e.g. 'R' for runnable or 'S' for sleeping
+    pub state: char,
+    pub policy: ScxSchedPolicy,
+    /// The kernel priority, see the priority field of proc(5)
+    pub priority: i32,
+    pub nice: i32,
+    pub rt_priority: u32,
+    /// The CPU the task last ran on
+    pub cpu: usize,
+    pub cpus_allowed: Cpumask,
+    /// The cgroup path of the task's cpuset
+    pub cpuset: String,
+    /// User and system CPU time consumed
+    pub cpu_time_ns: u64,
+    /// Time spent running and waiting for a CPU, and number of timeslices,
+    /// from schedstat
+    pub run_ns: u64,
+    pub wait_ns: u64,
+    pub nr_timeslices: u64,
+    pub voluntary_ctxt_switches: u64,
+    pub nonvoluntary_ctxt_switches: u64,
+    /// The fair class vruntime, if the kernel exposes /proc/[pid]/sched
+    pub vruntime_ns: Option<u64>,
+    /// When the snapshot was taken
+    pub timestamp: Instant,
+}
+
+fn read_proc_file(pid: i32, name: &str) -> Result<String> {
+    let path = format!("/proc/{}/{}", pid, name);
+    std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))
+}
+
+fn parse_field<T: std::str::FromStr>(fields: &[&str], idx: usize, name: &str) -> Result<T> {
+    match fields.get(idx).and_then(|f| f.parse::<T>().ok()) {
+        Some(val) => Ok(val),
+        None => bail!("Failed to parse {} from /proc/[pid]/stat", name),
+    }
+}
+
+// Get the number of clock ticks per second /proc reports CPU times in.
+fn clk_tck() -> u64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        tck if tck > 0 => tck as u64,
+        _ => 100,
+    }
+}
+
+/// The fields of /proc/[pid]/stat used by the crate.
+#[derive(Debug, Clone)]
+pub(crate) struct ProcStat {
+    pub comm: String,
+    pub state: char,
+    /// User and system CPU time in clock ticks
+    pub utime: u64,
+    pub stime: u64,
+    pub priority: i32,
+    pub nice: i32,
+    /// The CPU the task last ran on
+    pub cpu: usize,
+    pub rt_priority: u32,
+    pub policy: u32,
+}
+
+impl ProcStat {
+    /// Read /proc/@pid/stat.
+    pub(crate) fn read(pid: i32) -> Result<ProcStat> {
+        let stat = read_proc_file(pid, "stat")?;
+        Self::parse(&stat).with_context(|| format!("Unexpected format of /proc/{}/stat", pid))
+    }
+
+    fn parse(stat: &str) -> Result<ProcStat> {
+        // The comm field may contain spaces and parentheses, skip past it.
+        let (comm, fields) = match (stat.find('('), stat.rfind(')')) {
+            (Some(start), Some(end)) if start < end => (
+                stat[start + 1..end].to_string(),
+                stat[end + 1..].split_whitespace().collect::<Vec<&str>>(),
+            ),
+            _ => bail!("No comm field in {:?}", stat),
+        };
+        // Field N of proc(5) is at index N - 3, counting from the state.
+        Ok(ProcStat {
+            comm,
+            state: parse_field::<char>(&fields, 0, "state")?,
+            utime: parse_field::<u64>(&fields, 11, "utime")?,
+            stime: parse_field::<u64>(&fields, 12, "stime")?,
+            priority: parse_field::<i32>(&fields, 15, "priority")?,
+            nice: parse_field::<i32>(&fields, 16, "nice")?,
+            cpu: parse_field::<usize>(&fields, 36, "processor")?,
+            rt_priority: parse_field::<u32>(&fields, 37, "rt_priority")?,
+            policy: parse_field::<u32>(&fields, 38, "policy")?,
+        })
+    }
+
+    /// Get the user and system CPU time in nanoseconds.
+    pub(crate) fn cpu_time_ns(&self) -> u64 {
+        (self.utime + self.stime) * 1_000_000_000 / clk_tck()
+    }
+}
+
+// Parse "<secs>.<nsecs>" in milliseconds as printed in /proc/[pid]/sched.
+fn parse_sched_ms(val: &str) -> Option<u64> {
+    let (ms, frac) = val.trim().split_once('.')?;
+    let frac_ns = format!("{:0<6}", frac).get(..6)?.parse::<u64>().ok()?;
+    Some(ms.parse::<u64>().ok()? * 1_000_000 + frac_ns)
+}
+
+impl ScxTaskInfo {
+    /// Take a snapshot of the scheduling state of @pid.
+    pub fn snapshot(pid: i32) -> Result<ScxTaskInfo> {
+        let timestamp = Instant::now();
+
+        let stat = ProcStat::read(pid)?;
+        let cpu_time_ns = stat.cpu_time_ns();
+
+        let mut cpus_allowed = None;
+        let mut voluntary_ctxt_switches = 0;
+        let mut nonvoluntary_ctxt_switches = 0;
+        for line in read_proc_file(pid, "status")?.lines() {
+            let (key, val) = match line.split_once(':') {
+                Some((key, val)) => (key, val.trim()),
+                None => continue,
+            };
+            match key {
+                "Cpus_allowed_list" => cpus_allowed = Some(Cpumask::from_cpu_list(val)?),
+                "voluntary_ctxt_switches" => voluntary_ctxt_switches = val.parse()?,
+                "nonvoluntary_ctxt_switches" => nonvoluntary_ctxt_switches = val.parse()?,
+                _ => {}
+            }
+        }
+        let cpus_allowed = match cpus_allowed {
+            Some(mask) => mask,
+            None => bail!("No Cpus_allowed_list in /proc/{}/status", pid),
+        };
+
+        let schedstat: Vec<u64> = read_proc_file(pid, "schedstat")?
+            .split_whitespace()
+            .map(|f| f.parse::<u64>())
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("Failed to parse /proc/{}/schedstat", pid))?;
+        if schedstat.len() < 3 {
+            bail!("Unexpected format of /proc/{}/schedstat", pid);
+        }
+
+        // Only present with CONFIG_CPUSETS and CONFIG_SCHED_DEBUG respectively.
+        let cpuset = match read_proc_file(pid, "cpuset") {
+            Ok(cpuset) => cpuset.trim().to_string(),
+            Err(_) => String::new(),
+        };
+        let vruntime_ns = read_proc_file(pid, "sched").ok().and_then(|sched| {
+            sched
+                .lines()
+                .find_map(|line| line.strip_prefix("se.vruntime"))
+                .and_then(|line| line.split_once(':'))
+                .and_then(|(_, val)| parse_sched_ms(val))
+        });
+
+        Ok(ScxTaskInfo {
+            pid,
+            comm: stat.comm,
+            state: stat.state,
+            policy: ScxSchedPolicy::from(stat.policy),
+            priority: stat.priority,
+            nice: stat.nice,
+            rt_priority: stat.rt_priority,
+            cpu: stat.cpu,
+            cpus_allowed,
+            cpuset,
+            cpu_time_ns,
+            run_ns: schedstat[0],
+            wait_ns: schedstat[1],
+            nr_timeslices: schedstat[2],
+            voluntary_ctxt_switches,
+            nonvoluntary_ctxt_switches,
+            vruntime_ns,
+            timestamp,
+        })
+    }
+
+    /// Test whether the snapshot was taken more than @age ago.
+    pub fn is_stale(&self, age: Duration) -> bool {
+        self.timestamp.elapsed() > age
+    }
+
+    /// Get the ratio of a CPU the task used between the snapshot @prev and
+    /// the current snapshot, based on the schedstat run time.
+    pub fn cpu_usage_since(&self, prev: &ScxTaskInfo) -> f64 {
+        let elapsed_ns = self.timestamp.duration_since(prev.timestamp).as_nanos() as f64;
+        if elapsed_ns == 0.0 {
+            return 0.0;
+        }
+        self.run_ns.saturating_sub(prev.run_ns) as f64 / elapsed_ns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProcStat;
+
+    #[test]
+    fn test_proc_stat_parse() {
+        let stat = "42 (a) b (c)) S 1 42 42 0 -1 4194560 100 0 0 0 \
+                    250 50 0 0 20 0 1 0 1000 1000000 100 18446744073709551615 \
+                    1 1 0 0 0 0 0 0 0 0 0 0 17 3 0 0 0 0 0";
+        let stat = ProcStat::parse(stat).unwrap();
+        assert_eq!(stat.comm, "a) b (c)");
+        assert_eq!(stat.state, 'S');
+        assert_eq!((stat.utime, stat.stime), (250, 50));
+        assert_eq!((stat.priority, stat.nice), (20, 0));
+        assert_eq!(stat.cpu, 3);
+        assert_eq!((stat.rt_priority, stat.policy), (0, 0));
+
+        assert!(ProcStat::parse("42 no comm S 1").is_err());
+        assert!(ProcStat::parse("42 (short) S 1").is_err());
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Throttle Detector
+//!
+//! A crate that allows schedulers to detect when their BPF side is throttled,
+//! e.g. because it spends too long in its ops and holds up the CPUs.
+//!
+//! ScxThrottleDetector
+//! -------------------
+//!
+//! sched_ext doesn't export throttling statistics of its own, so the BPF side
+//! of the scheduler is expected to account throttle events in a single entry
+//! BPF_MAP_TYPE_ARRAY or BPF_MAP_TYPE_PERCPU_ARRAY map whose value starts
+//! with the following struct:
+//!
+//!```
+//!     struct throttle_stats {
+//!         u64 throttle_cnt;   /* number of throttle events */
+//!         u64 throttle_ns;    /* total time spent throttled */
+//!     };
+//!```
+//!
+//! A ScxThrottleDetector periodically polls the map, and invokes a callback
+//! whenever the rate of throttle events exceeds a threshold:
+//!
+//!```
+//!     let mut detector = ScxThrottleDetector::new(
+//!         skel.maps().throttle_stats(),
+//!         10.0,
+//!         |rate| warn!("BPF scheduler throttled {:.1} times/s", rate),
+//!     )?;
+//!
+//!     loop {
+//!         detector.poll()?;
+//!         std::thread::sleep(Duration::from_secs(1));
+//!     }
+//!```
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use libbpf_rs::Map;
+use libbpf_rs::MapFlags;
+use libbpf_rs::MapType;
+use std::time::Instant;
+
+const STATS_SIZE: usize = 2 * std::mem::size_of::<u64>();
+
+pub struct ScxThrottleDetector<'a> {
+    map: &'a Map,
+    threshold: f64,
+    callback: Box<dyn FnMut(f64) + 'a>,
+    last: Option<(Instant, u64, u64)>,
+    throttle_cnt: u64,
+    throttle_ns: u64,
+    rate: f64,
+    ns_rate: f64,
+}
+
+fn parse_stats(value: &[u8]) -> (u64, u64) {
+    let cnt = u64::from_ne_bytes(value[0..8].try_into().unwrap());
+    let ns = u64::from_ne_bytes(value[8..16].try_into().unwrap());
+    (cnt, ns)
+}
+
+impl<'a> ScxThrottleDetector<'a> {
+    /// Build a ScxThrottleDetector on top of a BPF array map holding the
+    /// throttle statistics at index 0. @callback is invoked with the current
+    /// rate by poll() whenever the rate exceeds @threshold events per
+    /// second.
+    pub fn new<F>(map: &'a Map, threshold: f64, callback: F) -> Result<ScxThrottleDetector<'a>>
+    where
+        F: FnMut(f64) + 'a,
+    {
+        match map.map_type() {
+            MapType::Array | MapType::PercpuArray => {}
+            _ => bail!(
+                "Map {} is not a BPF_MAP_TYPE_ARRAY or BPF_MAP_TYPE_PERCPU_ARRAY",
+                map.name()
+            ),
+        }
+        if (map.value_size() as usize) < STATS_SIZE {
+            bail!(
+                "Map {} has value size {}, expected at least {}",
+                map.name(),
+                map.value_size(),
+                STATS_SIZE
+            );
+        }
+
+        Ok(ScxThrottleDetector {
+            map,
+            threshold,
+            callback: Box::new(callback),
+            last: None,
+            throttle_cnt: 0,
+            throttle_ns: 0,
+            rate: 0.0,
+            ns_rate: 0.0,
+        })
+    }
+
+    fn read_stats(&self) -> Result<(u64, u64)> {
+        let key = 0u32.to_ne_bytes();
+        let name = self.map.name();
+
+        if self.map.map_type() == MapType::PercpuArray {
+            let values = self
+                .map
+                .lookup_percpu(&key, MapFlags::ANY)
+                .with_context(|| format!("Failed to look up throttle stats in {}", name))?
+                .with_context(|| format!("Throttle stats missing in {}", name))?;
+            Ok(values
+                .iter()
+                .map(|v| parse_stats(v))
+                .fold((0, 0), |(cnt, ns), (c, n)| (cnt + c, ns + n)))
+        } else {
+            let value = self
+                .map
+                .lookup(&key, MapFlags::ANY)
+                .with_context(|| format!("Failed to look up throttle stats in {}", name))?
+                .with_context(|| format!("Throttle stats missing in {}", name))?;
+            Ok(parse_stats(&value))
+        }
+    }
+
+    /// Read the throttle statistics from the map and update the rates. The
+    /// first poll only establishes the baseline for the following ones.
+    pub fn poll(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let (cnt, ns) = self.read_stats()?;
+
+        if let Some((last_at, last_cnt, last_ns)) = self.last {
+            let elapsed = now.duration_since(last_at).as_secs_f64();
+            if elapsed > 0.0 {
+                self.rate = cnt.saturating_sub(last_cnt) as f64 / elapsed;
+                self.ns_rate = ns.saturating_sub(last_ns) as f64 / elapsed;
+            }
+        }
+        self.last = Some((now, cnt, ns));
+        self.throttle_cnt = cnt;
+        self.throttle_ns = ns;
+
+        if self.rate > self.threshold {
+            (self.callback)(self.rate);
+        }
+        Ok(())
+    }
+
+    /// The number of throttle events per second between the last two polls.
+    pub fn throttle_rate_per_sec(&self) -> f64 {
+        self.rate
+    }
+
+    /// The time spent throttled in nanoseconds per second between the last
+    /// two polls.
+    pub fn throttle_ns_per_sec(&self) -> f64 {
+        self.ns_rate
+    }
+
+    /// The total number of throttle events as of the last poll.
+    pub fn throttle_cnt(&self) -> u64 {
+        self.throttle_cnt
+    }
+
+    /// The total time spent throttled in nanoseconds as of the last poll.
+    pub fn throttle_ns(&self) -> u64 {
+        self.throttle_ns
+    }
+
+    /// Get the threshold in throttle events per second.
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    /// Update the threshold in throttle events per second.
+    pub fn set_threshold(&mut self, threshold: f64) {
+        self.threshold = threshold;
+    }
+}
@@ -0,0 +1,239 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX BPF Timer Map
+//!
+//! A crate that allows schedulers to arm and cancel per-CPU BPF timers from
+//! the Rust userspace component.
+//!
+//! BpfTimerMap
+//! -----------
+//!
+//! BPF timers can only be started and cancelled by BPF programs, and the
+//! kernel cancels and frees the struct bpf_timer of a map value whenever
+//! user space updates the value. A BpfTimerMap therefore never touches the
+//! values of the timer map, and works together with a BPF_PROG_TYPE_SYSCALL
+//! "trampoline" program on the BPF side instead: arm() and cancel() run the
+//! trampoline with a struct timer_req as its context, and the trampoline
+//! calls bpf_timer_start() or bpf_timer_cancel() on the timer of the
+//! requested CPU in a BPF_MAP_TYPE_HASH keyed by u32 CPU. The BPF side looks
+//! as follows:
+//!
+//!```
+//!     struct cpu_timer {
+//!         struct bpf_timer timer;
+//!     };
+//!
+//!     struct {
+//!         __uint(type, BPF_MAP_TYPE_HASH);
+//!         __uint(max_entries, MAX_CPUS);
+//!         __type(key, u32);
+//!         __type(value, struct cpu_timer);
+//!     } cpu_timers SEC(".maps");
+//!
+//!     struct timer_req {
+//!         u64 delay_ns;
+//!         u32 cpu;
+//!         u32 op;         /* 1: arm, 2: cancel */
+//!     };
+//!
+//!     SEC("syscall")
+//!     int timer_trampoline(struct timer_req *req)
+//!     {
+//!         struct cpu_timer init = {}, *t;
+//!         u32 cpu = req->cpu;
+//!
+//!         /* Create the entry on first use, -EEXIST afterwards */
+//!         bpf_map_update_elem(&cpu_timers, &cpu, &init, BPF_NOEXIST);
+//!         t = bpf_map_lookup_elem(&cpu_timers, &cpu);
+//!         if (!t)
+//!             return -ENOENT;
+//!
+//!         /* -EBUSY if already initialized */
+//!         bpf_timer_init(&t->timer, &cpu_timers, CLOCK_MONOTONIC);
+//!         bpf_timer_set_callback(&t->timer, cpu_timer_fn);
+//!         if (req->op == 2)
+//!             return bpf_timer_cancel(&t->timer);
+//!         return bpf_timer_start(&t->timer, req->delay_ns, 0);
+//!     }
+//!```
+//!
+//! With the map and program either taken from the skeleton, or pinned to
+//! bpffs by the scheduler:
+//!
+//!```
+//!     let timers = BpfTimerMap::new(skel.maps().cpu_timers(), skel.progs().timer_trampoline())?;
+//!     timers.arm(3, 1_000_000)?;
+//!     timers.cancel(3)?;
+//!```
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use libbpf_rs::libbpf_sys;
+use libbpf_rs::Map;
+use libbpf_rs::MapHandle;
+use libbpf_rs::MapType;
+use libbpf_rs::Program;
+use std::ffi::CString;
+use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+// struct bpf_timer is 16 bytes and 8 byte aligned.
+const BPF_TIMER_SIZE: usize = 16;
+
+const TIMER_OP_ARM: u32 = 1;
+const TIMER_OP_CANCEL: u32 = 2;
+
+// The context of the trampoline, struct timer_req on the BPF side.
+#[repr(C)]
+struct TimerRequest {
+    delay_ns: u64,
+    cpu: u32,
+    op: u32,
+}
+
+#[derive(Debug)]
+pub struct BpfTimerMap {
+    // The trampoline references the timer map, keeping it alive.
+    trampoline: OwnedFd,
+}
+
+impl BpfTimerMap {
+    fn check_map(map: &MapHandle) -> Result<()> {
+        if map.map_type() != MapType::Hash {
+            bail!("Map {} is not a BPF_MAP_TYPE_HASH", map.name());
+        }
+        if map.key_size() as usize != std::mem::size_of::<u32>() {
+            bail!(
+                "Map {} has key size {}, expected {}",
+                map.name(),
+                map.key_size(),
+                std::mem::size_of::<u32>()
+            );
+        }
+        if map.value_size() as usize != BPF_TIMER_SIZE {
+            bail!(
+                "Map {} has value size {}, expected {}",
+                map.name(),
+                map.value_size(),
+                BPF_TIMER_SIZE
+            );
+        }
+        Ok(())
+    }
+
+    /// Build a BpfTimerMap from the timer map and trampoline program of a
+    /// loaded BPF skeleton.
+    pub fn new(map: &Map, trampoline: &Program) -> Result<BpfTimerMap> {
+        let map = MapHandle::try_clone(map)
+            .with_context(|| format!("Failed to clone handle of map {}", map.name()))?;
+        Self::check_map(&map)?;
+
+        let trampoline = trampoline
+            .as_fd()
+            .try_clone_to_owned()
+            .with_context(|| format!("Failed to dup fd of program {}", trampoline.name()))?;
+
+        Ok(BpfTimerMap { trampoline })
+    }
+
+    /// Build a BpfTimerMap from a timer map and trampoline program pinned
+    /// to bpffs at @map_path and @prog_path respectively.
+    pub fn from_pinned_path(map_path: &Path, prog_path: &Path) -> Result<BpfTimerMap> {
+        let map = MapHandle::from_pinned_path(map_path)
+            .with_context(|| format!("Failed to open pinned map {:?}", map_path))?;
+        Self::check_map(&map)?;
+
+        let cpath = CString::new(prog_path.as_os_str().as_bytes())?;
+        let fd = unsafe { libbpf_sys::bpf_obj_get(cpath.as_ptr()) };
+        if fd < 0 {
+            bail!(
+                "Failed to open pinned program {:?} ({})",
+                prog_path,
+                std::io::Error::last_os_error()
+            );
+        }
+        let trampoline = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        Ok(BpfTimerMap { trampoline })
+    }
+
+    fn request(&self, cpu: usize, op: u32, delay_ns: u64) -> Result<()> {
+        let req = TimerRequest {
+            delay_ns,
+            cpu: cpu as u32,
+            op,
+        };
+        let mut opts = libbpf_sys::bpf_test_run_opts {
+            sz: std::mem::size_of::<libbpf_sys::bpf_test_run_opts>() as _,
+            ctx_in: &req as *const TimerRequest as *const _,
+            ctx_size_in: std::mem::size_of::<TimerRequest>() as u32,
+            ..Default::default()
+        };
+        let ret =
+            unsafe { libbpf_sys::bpf_prog_test_run_opts(self.trampoline.as_raw_fd(), &mut opts) };
+        if ret < 0 {
+            bail!(
+                "Failed to run timer trampoline for CPU {} ({})",
+                cpu,
+                std::io::Error::from_raw_os_error(-ret)
+            );
+        }
+
+        let retval = opts.retval as i32;
+        if retval < 0 {
+            bail!(
+                "Timer trampoline failed for CPU {} ({})",
+                cpu,
+                std::io::Error::from_raw_os_error(-retval)
+            );
+        }
+        Ok(())
+    }
+
+    /// Arm the timer of @cpu to fire in @delay_ns nanoseconds. Re-arming a
+    /// pending timer moves its expiry.
+    pub fn arm(&self, cpu: usize, delay_ns: u64) -> Result<()> {
+        self.request(cpu, TIMER_OP_ARM, delay_ns)
+    }
+
+    /// Cancel the timer of @cpu. Cancelling a timer which isn't pending, e.g.
+    /// because it already fired or was never armed, is not an error.
+    pub fn cancel(&self, cpu: usize) -> Result<()> {
+        self.request(cpu, TIMER_OP_CANCEL, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimerRequest;
+    use super::TIMER_OP_ARM;
+
+    #[test]
+    fn test_timer_request_layout() {
+        // Must match struct timer_req { u64 delay_ns; u32 cpu; u32 op; }.
+        assert_eq!(std::mem::size_of::<TimerRequest>(), 16);
+
+        let req = TimerRequest {
+            delay_ns: 1_000_000,
+            cpu: 3,
+            op: TIMER_OP_ARM,
+        };
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                &req as *const TimerRequest as *const u8,
+                std::mem::size_of::<TimerRequest>(),
+            )
+        };
+        assert_eq!(bytes[0..8], 1_000_000u64.to_ne_bytes());
+        assert_eq!(bytes[8..12], 3u32.to_ne_bytes());
+        assert_eq!(bytes[12..16], TIMER_OP_ARM.to_ne_bytes());
+    }
+}
@@ -60,6 +60,13 @@
 //!     let top = Topology::new()?;
 //!```
 //!
+//! Tests which shouldn't depend on the host can create a synthetic Topology
+//! of 16 CPUs in 2 NUMA nodes and 4 LLCs instead:
+//!
+//!```
+//!     let top = Topology::from_mock(16, 2, 4);
+//!```
+//!
 //! Querying Topology
 //! -----------------
 //!
@@ -67,14 +74,44 @@
 //! set of accessor functions defined below. All objects in the topological
 //! hierarchy are entirely read-only. If the host topology were to change (due
 //! to e.g. hotplug), a new Topology object should be created.
+//!
+//! Snapshots
+//! ---------
+//!
+//! With the serde_json feature, a Topology can be saved as JSON and restored,
+//! e.g. on another host, to model the topology of a remote host for capacity
+//! planning or testing:
+//!
+//!```
+//!     Topology::new()?.serialize(&mut File::create("topo.json")?)?;
+//!     let remote = Topology::from_snapshot(&mut File::open("topo.json")?)?;
+//!```
+//!
+//! Visualizing Topology
+//! --------------------
+//!
+//! A Topology can be written as a Graphviz DOT graph, which can be rendered
+//! with e.g. dot -Tsvg topology.dot > topology.svg:
+//!
+//!```
+//!     Topology::new()?.export_graphviz(&mut File::create("topology.dot")?)?;
+//!```
 
 use crate::Cpumask;
 use anyhow::bail;
+use anyhow::Context;
 use anyhow::Result;
 use glob::glob;
 use sscanf::sscanf;
+#[cfg(feature = "serde_json")]
+use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+#[cfg(feature = "serde_json")]
+use std::io::Read;
+use std::io::Write;
 use std::path::Path;
+use std::sync::Mutex;
 
 #[derive(Debug, Clone)]
 pub struct Cpu {
@@ -82,6 +119,10 @@ pub struct Cpu {
     online: bool,
     min_freq: usize,
     max_freq: usize,
+    capacity: usize,
+    llc_id: usize,
+    node_id: usize,
+    package_id: usize,
 }
 
 impl Cpu {
@@ -104,6 +145,27 @@ impl Cpu {
     pub fn max_freq(&self) -> usize {
         self.max_freq
     }
+
+    /// Get the relative compute capacity of this CPU, normalized such that
+    /// the most capable CPU on the host has a capacity of 1024.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get the ID of the LLC this CPU belongs to
+    pub fn llc_id(&self) -> usize {
+        self.llc_id
+    }
+
+    /// Get the ID of the NUMA node this CPU belongs to
+    pub fn node_id(&self) -> usize {
+        self.node_id
+    }
+
+    /// Get the ID of the physical package this CPU belongs to
+    pub fn package_id(&self) -> usize {
+        self.package_id
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -159,6 +221,7 @@ pub struct Node {
     id: usize,
     llcs: BTreeMap<usize, Cache>,
     span: Cpumask,
+    distances: BTreeMap<usize, usize>,
 }
 
 impl Node {
@@ -176,6 +239,50 @@ impl Node {
     pub fn span(&self) -> Cpumask {
         self.span.clone()
     }
+
+    /// Get the map of <node ID, distance> from this NUMA node to all NUMA
+    /// nodes on the host, including itself.
+    pub fn distances(&self) -> &BTreeMap<usize, usize> {
+        &self.distances
+    }
+}
+
+/// The SMT control states of the host, as in
+/// /sys/devices/system/cpu/smt/control. See Topology::smt_control().
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtControl {
+    /// SMT is enabled
+    On,
+    /// SMT is disabled and can be enabled again
+    Off,
+    /// SMT is disabled until reboot
+    ForceOff,
+    /// The CPUs don't support SMT
+    NotSupported,
+    /// The kernel doesn't support SMT control
+    NotImplemented,
+}
+
+/// The difference between a Topology and the live topology of the host. See
+/// Topology::diff_from_kernel().
+#[derive(Debug, Clone)]
+pub struct TopologyDiff {
+    /// CPUs which came online since the Topology was built
+    pub added_cpus: Cpumask,
+    /// CPUs which went offline since the Topology was built
+    pub removed_cpus: Cpumask,
+    /// (CPU ID, new maximum frequency) pairs of the CPUs whose maximum
+    /// scaling frequency changed since the Topology was built
+    pub changed_frequencies: Vec<(usize, u32)>,
+}
+
+impl TopologyDiff {
+    /// Test whether the Topology still matches the live topology.
+    pub fn is_empty(&self) -> bool {
+        self.added_cpus.weight() == 0
+            && self.removed_cpus.weight() == 0
+            && self.changed_frequencies.is_empty()
+    }
 }
 
 #[derive(Debug)]
@@ -185,6 +292,8 @@ pub struct Topology {
     cpus: BTreeMap<usize, Cpu>,
     nr_cpus: usize,
     span: Cpumask,
+    // (CPU ID, max hops) -> CPUs within max hops, see reachable_from()
+    reachable: Mutex<BTreeMap<(usize, usize), Cpumask>>,
 }
 
 impl Topology {
@@ -192,8 +301,17 @@ impl Topology {
     pub fn new() -> Result<Topology> {
         let nr_cpus = libbpf_rs::num_possible_cpus()?;
         let span = cpus_online()?;
-        let nodes = create_numa_nodes(&span)?;
+        let mut nodes = create_numa_nodes(&span)?;
+        read_numa_distances(&mut nodes)?;
+
+        let topo = Self::from_nodes(nodes, nr_cpus, span)?;
+        if cfg!(debug_assertions) {
+            topo.verify_consistency()?;
+        }
+        Ok(topo)
+    }
 
+    fn from_nodes(nodes: Vec<Node>, nr_cpus: usize, span: Cpumask) -> Result<Topology> {
         // For convenient and efficient lookup from the root topology object,
         // create two BTreeMaps to the full set of Core and Cpu objects on the
         // system. We clone the objects that are located further down in the
@@ -216,7 +334,299 @@ impl Topology {
             }
         }
 
-        Ok(Topology { nodes, nr_cpus, cores, cpus, span })
+        Ok(Topology {
+            nodes,
+            nr_cpus,
+            cores,
+            cpus,
+            span,
+            reachable: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Write a snapshot of the Topology to @w as JSON, e.g. to model the
+    /// topology of a remote host. See from_snapshot() for the inverse.
+    #[cfg(feature = "serde_json")]
+    pub fn serialize(&self, w: &mut impl Write) -> Result<()> {
+        use serde_json::json;
+
+        let mut nodes = vec![];
+        let mut cpus = vec![];
+        for node in self.nodes.iter() {
+            let distances: Vec<[usize; 2]> = node
+                .distances
+                .iter()
+                .map(|(id, dist)| [*id, *dist])
+                .collect();
+            nodes.push(json!({ "id": node.id, "distances": distances }));
+
+            for core in node.llcs.values().flat_map(|llc| llc.cores.values()) {
+                for cpu in core.cpus.values() {
+                    cpus.push(json!({
+                        "id": cpu.id,
+                        "online": cpu.online,
+                        "node_id": cpu.node_id,
+                        "llc_id": cpu.llc_id,
+                        "core_id": core.id,
+                        "package_id": cpu.package_id,
+                        "capacity": cpu.capacity,
+                        "min_freq": cpu.min_freq,
+                        "max_freq": cpu.max_freq,
+                    }));
+                }
+            }
+        }
+
+        let snapshot = json!({
+            "nr_cpus": self.nr_cpus,
+            "nr_nodes": self.nodes.len(),
+            "nr_llcs": self.nodes.iter().map(|node| node.llcs.len()).sum::<usize>(),
+            "nodes": nodes,
+            "cpus": cpus,
+        });
+        serde_json::to_writer_pretty(w, &snapshot)?;
+        Ok(())
+    }
+
+    /// Build a Topology from a snapshot written by serialize(), without
+    /// looking at sysfs at all.
+    #[cfg(feature = "serde_json")]
+    pub fn from_snapshot(r: &mut impl Read) -> Result<Topology> {
+        let snapshot: serde_json::Value = serde_json::from_reader(r)?;
+        let nr_cpus = json_usize(&snapshot, "nr_cpus")?;
+        let empty_mask = || Cpumask::from_json_array(&serde_json::json!([]), nr_cpus);
+
+        let mut nodes = BTreeMap::new();
+        for val in json_array(&snapshot, "nodes")? {
+            let id = json_usize(val, "id")?;
+            let mut distances = BTreeMap::new();
+            for dist in json_array(val, "distances")? {
+                match (dist[0].as_u64(), dist[1].as_u64()) {
+                    (Some(to), Some(dist)) => distances.insert(to as usize, dist as usize),
+                    _ => bail!("Invalid distance {} of node {} in snapshot", dist, id),
+                };
+            }
+            let node = Node {
+                id,
+                llcs: BTreeMap::new(),
+                span: empty_mask()?,
+                distances,
+            };
+            if nodes.insert(id, node).is_some() {
+                bail!("Found duplicate node ID {} in snapshot", id);
+            }
+        }
+
+        let mut span = empty_mask()?;
+        for val in json_array(&snapshot, "cpus")? {
+            let cpu = Cpu {
+                id: json_usize(val, "id")?,
+                online: val["online"].as_bool().unwrap_or(true),
+                min_freq: json_usize(val, "min_freq")?,
+                max_freq: json_usize(val, "max_freq")?,
+                capacity: json_usize(val, "capacity")?,
+                llc_id: json_usize(val, "llc_id")?,
+                node_id: json_usize(val, "node_id")?,
+                package_id: json_usize(val, "package_id")?,
+            };
+            let core_id = json_usize(val, "core_id")?;
+
+            let node = match nodes.get_mut(&cpu.node_id) {
+                Some(node) => node,
+                None => bail!("CPU {} is on unknown node {}", cpu.id, cpu.node_id),
+            };
+            let cache = match node.llcs.entry(cpu.llc_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(Cache {
+                    id: cpu.llc_id,
+                    cores: BTreeMap::new(),
+                    span: empty_mask()?,
+                }),
+            };
+            let core = match cache.cores.entry(core_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(Core {
+                    id: core_id,
+                    cpus: BTreeMap::new(),
+                    span: empty_mask()?,
+                }),
+            };
+
+            if node.span.test_cpu(cpu.id) {
+                bail!("Node {} already had CPU {}", cpu.node_id, cpu.id);
+            }
+            core.span.set_cpu(cpu.id)?;
+            cache.span.set_cpu(cpu.id)?;
+            node.span.set_cpu(cpu.id)?;
+            if cpu.online {
+                span.set_cpu(cpu.id)?;
+            }
+            core.cpus.insert(cpu.id, cpu);
+        }
+
+        let topo = Self::from_nodes(nodes.into_values().collect(), nr_cpus, span)?;
+        let nr_llcs: usize = topo.nodes.iter().map(|node| node.llcs.len()).sum();
+        if topo.nodes.len() != json_usize(&snapshot, "nr_nodes")?
+            || nr_llcs != json_usize(&snapshot, "nr_llcs")?
+        {
+            bail!("Number of nodes or LLCs doesn't match the CPUs in snapshot");
+        }
+        Ok(topo)
+    }
+
+    /// Build a Topology from a snapshot. This is the same as from_snapshot().
+    #[cfg(feature = "serde_json")]
+    pub fn deserialize(r: &mut impl Read) -> Result<Topology> {
+        Self::from_snapshot(r)
+    }
+
+    /// Write the Topology to @w as a Graphviz DOT graph, e.g. to render it
+    /// with "dot -Tsvg topology.dot > topology.svg". Packages, NUMA nodes,
+    /// LLCs, cores and CPUs are drawn as nested clusters, with an edge from
+    /// each object to the objects it contains. Offline CPUs are dashed.
+    pub fn export_graphviz(&self, w: &mut impl Write) -> Result<()> {
+        // A node is drawn in the cluster of the package of its first CPU,
+        // but gets an edge from each package it spans.
+        let mut packages: BTreeMap<usize, Vec<&Node>> = BTreeMap::new();
+        let mut edges = vec![];
+        for node in self.nodes.iter() {
+            let mut node_pkgs: Vec<usize> = node
+                .llcs
+                .values()
+                .flat_map(|llc| llc.cores.values())
+                .flat_map(|core| core.cpus.values())
+                .map(|cpu| cpu.package_id)
+                .collect();
+            if let Some(&pkg) = node_pkgs.first() {
+                packages.entry(pkg).or_default().push(node);
+            }
+            node_pkgs.sort();
+            node_pkgs.dedup();
+            for pkg in node_pkgs {
+                edges.push((format!("pkg{}", pkg), format!("node{}", node.id)));
+            }
+        }
+
+        writeln!(w, "digraph topology {{")?;
+        writeln!(w, "    node [shape=box];")?;
+        for (pkg, nodes) in packages.iter() {
+            writeln!(w, "    subgraph cluster_pkg{} {{", pkg)?;
+            writeln!(w, "        pkg{} [label=\"Package {}\"];", pkg, pkg)?;
+            for node in nodes.iter() {
+                writeln!(w, "        subgraph cluster_node{} {{", node.id)?;
+                writeln!(
+                    w,
+                    "            node{} [label=\"Node {}\\n{}\"];",
+                    node.id,
+                    node.id,
+                    node.span.to_hex_string()
+                )?;
+                for llc in node.llcs.values() {
+                    edges.push((format!("node{}", node.id), format!("llc{}", llc.id)));
+                    writeln!(w, "            subgraph cluster_llc{} {{", llc.id)?;
+                    writeln!(
+                        w,
+                        "                llc{} [label=\"LLC {}\\n{}\"];",
+                        llc.id,
+                        llc.id,
+                        llc.span.to_hex_string()
+                    )?;
+                    for core in llc.cores.values() {
+                        edges.push((format!("llc{}", llc.id), format!("core{}", core.id)));
+                        writeln!(w, "                subgraph cluster_core{} {{", core.id)?;
+                        writeln!(
+                            w,
+                            "                    core{} [label=\"Core {}\"];",
+                            core.id, core.id
+                        )?;
+                        for cpu in core.cpus.values() {
+                            edges.push((format!("core{}", core.id), format!("cpu{}", cpu.id)));
+                            writeln!(
+                                w,
+                                "                    cpu{} [label=\"CPU {}\\n{}MHz\"{}];",
+                                cpu.id,
+                                cpu.id,
+                                cpu.max_freq / 1000,
+                                if cpu.online { "" } else { ", style=dashed" }
+                            )?;
+                        }
+                        writeln!(w, "                }}")?;
+                    }
+                    writeln!(w, "            }}")?;
+                }
+                writeln!(w, "        }}")?;
+            }
+            writeln!(w, "    }}")?;
+        }
+
+        for (from, to) in edges.iter() {
+            writeln!(w, "    {} -> {};", from, to)?;
+        }
+        writeln!(w, "}}")?;
+        Ok(())
+    }
+
+    /// Build a synthetic Topology of @nr_cpus online CPUs, e.g. to test
+    /// topology dependent logic without depending on the host. CPU N is in
+    /// LLC N % @nr_llcs, and LLC M is in node M % @nr_nodes. Each CPU is its
+    /// own core, and all CPUs have a capacity of 1024 and the same
+    /// frequencies. The distance between nodes is 10 for the local node and
+    /// 20 for all others. Panics unless @nr_cpus >= @nr_llcs >= @nr_nodes >=
+    /// 1, so that no LLC or node is empty.
+    pub fn from_mock(nr_cpus: usize, nr_nodes: usize, nr_llcs: usize) -> Topology {
+        assert!(
+            nr_cpus >= nr_llcs && nr_llcs >= nr_nodes && nr_nodes >= 1,
+            "Invalid mock topology of {} CPUs, {} nodes and {} LLCs",
+            nr_cpus,
+            nr_nodes,
+            nr_llcs
+        );
+        let empty_mask = || Cpumask::from_iter_with_capacity([], nr_cpus).unwrap();
+
+        let mut nodes: Vec<Node> = (0..nr_nodes)
+            .map(|id| Node {
+                id,
+                llcs: BTreeMap::new(),
+                span: empty_mask(),
+                distances: (0..nr_nodes)
+                    .map(|to| (to, if to == id { 10 } else { 20 }))
+                    .collect(),
+            })
+            .collect();
+
+        for id in 0..nr_cpus {
+            let llc_id = id % nr_llcs;
+            let node_id = llc_id % nr_nodes;
+            let cpu = Cpu {
+                id,
+                online: true,
+                min_freq: 1_000_000,
+                max_freq: 3_000_000,
+                capacity: 1024,
+                llc_id,
+                node_id,
+                package_id: node_id,
+            };
+
+            let node = &mut nodes[node_id];
+            let cache = node.llcs.entry(llc_id).or_insert_with(|| Cache {
+                id: llc_id,
+                cores: BTreeMap::new(),
+                span: empty_mask(),
+            });
+            let core = Core {
+                id,
+                cpus: BTreeMap::from([(id, cpu)]),
+                span: Cpumask::from_iter_with_capacity([id], nr_cpus).unwrap(),
+            };
+            cache.cores.insert(id, core);
+            cache.span.set_cpu(id).unwrap();
+            node.span.set_cpu(id).unwrap();
+        }
+
+        let span = Cpumask::from_iter_with_capacity(0..nr_cpus, nr_cpus).unwrap();
+        // Core and CPU IDs are unique by construction.
+        Self::from_nodes(nodes, nr_cpus, span).unwrap()
     }
 
     /// Get a slice of the NUMA nodes on the host
@@ -243,6 +653,331 @@ impl Topology {
     pub fn span(&self) -> Cpumask {
         self.span.clone()
     }
+
+    /// Get a reference to the Cpumask of all CPUs in NUMA node @node, or None
+    /// if the node doesn't exist. Unlike Node::span(), this doesn't clone
+    /// the Cpumask.
+    pub fn cpus_on_node(&self, node: usize) -> Option<&Cpumask> {
+        self.nodes.iter().find(|n| n.id == node).map(|n| &n.span)
+    }
+
+    /// Get the CPUs within @max_hops of @cpu in the topology tree, where
+    /// CPUs of the same core are 0 hops away, CPUs of the same LLC 1 hop,
+    /// CPUs of the same NUMA node 2 hops, and CPUs of other nodes 3 hops.
+    /// The result includes @cpu itself. Results are cached per (@cpu,
+    /// @max_hops). Returns an empty Cpumask if @cpu doesn't exist.
+    pub fn reachable_from(&self, cpu: usize, max_hops: usize) -> Cpumask {
+        let max_hops = max_hops.min(3);
+        let mut reachable = self.reachable.lock().unwrap();
+        if let Some(mask) = reachable.get(&(cpu, max_hops)) {
+            return mask.clone();
+        }
+
+        let mut empty = self.span.clone();
+        empty.clear();
+
+        let cpu_info = match self.cpus.get(&cpu) {
+            Some(cpu_info) => cpu_info,
+            None => return empty,
+        };
+        let node = self.nodes.iter().find(|node| node.id == cpu_info.node_id);
+        let mask = match max_hops {
+            0 => self
+                .cores
+                .values()
+                .find(|core| core.cpus.contains_key(&cpu))
+                .map(|core| core.span.clone()),
+            1 => node
+                .and_then(|node| node.llcs.get(&cpu_info.llc_id))
+                .map(|llc| llc.span.clone()),
+            2 => node.map(|node| node.span.clone()),
+            _ => {
+                let mut mask = empty.clone();
+                mask.set_if(|cpu| self.nodes.iter().any(|node| node.span.test_cpu(cpu)));
+                Some(mask)
+            }
+        };
+        let mask = mask.unwrap_or(empty);
+
+        reachable.insert((cpu, max_hops), mask.clone());
+        mask
+    }
+
+    /// Get a reference to the Cpumask of all CPUs sharing the LLC with ID
+    /// @llc_id, or None if the LLC doesn't exist.
+    pub fn cpus_sharing_llc_by_id(&self, llc_id: usize) -> Option<&Cpumask> {
+        self.nodes
+            .iter()
+            .find_map(|node| node.llcs.get(&llc_id))
+            .map(|llc| &llc.span)
+    }
+
+    /// Get the distance between two NUMA nodes as reported by the firmware,
+    /// where the distance from a node to itself is normally 10. Returns None
+    /// if either node doesn't exist.
+    pub fn numa_distance(&self, from_node: usize, to_node: usize) -> Option<usize> {
+        self.nodes
+            .iter()
+            .find(|node| node.id == from_node)
+            .and_then(|node| node.distances.get(&to_node).copied())
+    }
+
+    /// Get the highest boost (turbo) frequency of a CPU in MHz. Returns None
+    /// if the CPU doesn't exist, doesn't support boosting, or the cpufreq
+    /// driver doesn't report boost frequencies.
+    pub fn cpu_max_boost_mhz(&self, cpu: usize) -> Option<u32> {
+        if !self.cpus.contains_key(&cpu) {
+            return None;
+        }
+        let freq_path = Path::new("/sys/devices/system/cpu")
+            .join(format!("cpu{}", cpu))
+            .join("cpufreq");
+
+        // amd-pstate reports the highest boost frequency directly.
+        if let Ok(khz) = read_file_usize(&freq_path.join("amd_pstate_max_freq")) {
+            return Some((khz / 1000) as u32);
+        }
+
+        // acpi-cpufreq lists the boost frequencies separately from the
+        // regular ones.
+        if let Ok(freqs) = std::fs::read_to_string(freq_path.join("scaling_boost_frequencies")) {
+            return freqs
+                .split_whitespace()
+                .filter_map(|khz| khz.parse::<usize>().ok())
+                .max()
+                .map(|khz| (khz / 1000) as u32);
+        }
+
+        // intel_pstate reports the highest turbo frequency as the maximum
+        // frequency, and the highest non-turbo frequency as the base.
+        let max_khz = read_file_usize(&freq_path.join("cpuinfo_max_freq")).ok()?;
+        let base_khz = read_file_usize(&freq_path.join("base_frequency")).ok()?;
+        if max_khz > base_khz {
+            return Some((max_khz / 1000) as u32);
+        }
+
+        None
+    }
+
+    /// Is frequency boosting (turbo) currently enabled on the host? Returns
+    /// false if the cpufreq driver doesn't support boosting.
+    pub fn is_boost_enabled(&self) -> bool {
+        let cpu_path = Path::new("/sys/devices/system/cpu");
+
+        if let Ok(boost) = read_file_usize(&cpu_path.join("cpufreq/boost")) {
+            return boost != 0;
+        }
+
+        // intel_pstate doesn't implement the generic knob.
+        match read_file_usize(&cpu_path.join("intel_pstate/no_turbo")) {
+            Ok(no_turbo) => no_turbo == 0,
+            Err(_) => false,
+        }
+    }
+
+    /// Is SMT (hyperthreading) currently active on the host, rather than
+    /// just supported? Falls back to checking whether any core has more
+    /// than one online CPU if the kernel doesn't report the SMT state.
+    pub fn hyperthreading_enabled(&self) -> bool {
+        if let Ok(active) = read_file_usize(Path::new(SMT_PATH).join("active").as_path()) {
+            return active != 0;
+        }
+
+        self.cores.values().any(|core| {
+            core.cpus
+                .keys()
+                .filter(|cpu| self.span.test_cpu(**cpu))
+                .count()
+                > 1
+        })
+    }
+
+    /// Get the SMT control state of the host. Returns
+    /// SmtControl::NotImplemented if the kernel doesn't provide SMT control.
+    pub fn smt_control(&self) -> SmtControl {
+        match std::fs::read_to_string(Path::new(SMT_PATH).join("control")) {
+            Ok(control) => match control.trim() {
+                "on" => SmtControl::On,
+                "off" => SmtControl::Off,
+                "forceoff" => SmtControl::ForceOff,
+                "notsupported" => SmtControl::NotSupported,
+                _ => SmtControl::NotImplemented,
+            },
+            Err(_) => SmtControl::NotImplemented,
+        }
+    }
+
+    /// Set the SMT control state of the host, e.g. to turn SMT off to save
+    /// power. Only SmtControl::On, Off and ForceOff can be set, and
+    /// ForceOff can't be undone until reboot. This onlines or offlines CPUs,
+    /// after which the Topology no longer matches the host, see
+    /// diff_from_kernel().
+    pub fn set_smt(&self, control: SmtControl) -> Result<()> {
+        let val = match control {
+            SmtControl::On => "on",
+            SmtControl::Off => "off",
+            SmtControl::ForceOff => "forceoff",
+            _ => bail!("Can't set SMT control to {:?}", control),
+        };
+
+        let path = Path::new(SMT_PATH).join("control");
+        if let Err(e) = std::fs::write(&path, val) {
+            bail!("Failed to write {} to {:?} ({})", val, path, e);
+        }
+        Ok(())
+    }
+
+    /// Build a fresh Topology from sysfs and compare it against this one,
+    /// e.g. to detect CPU hotplug in a long-running scheduler. The caller can
+    /// then decide whether the Topology needs to be rebuilt.
+    pub fn diff_from_kernel(&self) -> Result<TopologyDiff> {
+        let live = Topology::new()?;
+
+        let changed_frequencies = live
+            .cpus
+            .iter()
+            .filter_map(|(cpu_id, cpu)| match self.cpus.get(cpu_id) {
+                Some(old) if old.max_freq != cpu.max_freq => Some((*cpu_id, cpu.max_freq as u32)),
+                _ => None,
+            })
+            .collect();
+
+        Ok(TopologyDiff {
+            added_cpus: live.span.difference(&self.span)?,
+            removed_cpus: self.span.difference(&live.span)?,
+            changed_frequencies,
+        })
+    }
+
+    /// Does the host have CPUs of different compute capacities, as is the
+    /// case with e.g. big.LITTLE or P/E core designs?
+    pub fn is_heterogeneous(&self) -> bool {
+        let mut capacities = self.cpus.values().map(|cpu| cpu.capacity);
+        match capacities.next() {
+            Some(first) => capacities.any(|cap| cap != first),
+            None => false,
+        }
+    }
+
+    /// Group the CPUs on the host by their compute capacity. Returns a
+    /// (capacity, Cpumask) pair for each capacity class, sorted in ascending
+    /// order of capacity. Homogeneous hosts have a single class.
+    pub fn capacity_classes(&self) -> Vec<(u32, Cpumask)> {
+        let capacities: BTreeSet<usize> = self.cpus.values().map(|cpu| cpu.capacity).collect();
+        capacities
+            .into_iter()
+            .map(|capacity| {
+                let mut mask = self.span.clone();
+                mask.clear();
+                mask.set_if(|cpu| {
+                    self.cpus
+                        .get(&cpu)
+                        .is_some_and(|info| info.capacity == capacity)
+                });
+                (capacity as u32, mask)
+            })
+            .collect()
+    }
+
+    /// Check that the sysfs data the Topology was built from is
+    /// self-consistent: thread sibling and LLC sharing relationships must be
+    /// symmetric, every CPU listed in a node's cpulist must belong to that
+    /// node, and the number of CPUs must match the number of possible CPUs.
+    /// Returns an error listing all inconsistencies found. This is done
+    /// automatically by Topology::new() in debug builds.
+    pub fn verify_consistency(&self) -> Result<()> {
+        let mut errors = vec![];
+
+        match libbpf_rs::num_possible_cpus() {
+            Ok(nr_possible) if nr_possible != self.nr_cpus => errors.push(format!(
+                "nr_cpus {} doesn't match {} possible CPUs",
+                self.nr_cpus, nr_possible
+            )),
+            Ok(_) => {}
+            Err(e) => errors.push(format!("Failed to read the number of possible CPUs: {}", e)),
+        }
+
+        let online: Vec<usize> = self
+            .cpus
+            .values()
+            .filter(|cpu| cpu.online)
+            .map(|cpu| cpu.id)
+            .collect();
+        for (name, file) in [
+            (
+                "thread sibling",
+                "topology/thread_siblings_list".to_string(),
+            ),
+            (
+                "LLC sharing",
+                format!("cache/index{}/shared_cpu_list", CACHE_LEVEL),
+            ),
+        ] {
+            let mut masks = BTreeMap::new();
+            for cpu in online.iter() {
+                let path = format!("/sys/devices/system/cpu/cpu{}/{}", cpu, file);
+                match std::fs::read_to_string(&path).map(|list| Cpumask::from_cpu_list(&list)) {
+                    Ok(Ok(mask)) => {
+                        masks.insert(*cpu, mask);
+                    }
+                    Ok(Err(e)) => errors.push(format!("Failed to parse {}: {}", path, e)),
+                    Err(e) => errors.push(format!("Failed to read {}: {}", path, e)),
+                }
+            }
+
+            for (cpu, mask) in masks.iter() {
+                for other in mask.clone().into_iter() {
+                    match masks.get(&other) {
+                        Some(other_mask) if !other_mask.test_cpu(*cpu) => errors.push(format!(
+                            "{} relationship is asymmetric: CPU {} lists CPU {} but not vice versa",
+                            name, cpu, other
+                        )),
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        for node in self.nodes.iter() {
+            let path = format!("/sys/devices/system/node/node{}/cpulist", node.id);
+            let cpulist = match std::fs::read_to_string(&path) {
+                Ok(list) => list,
+                Err(e) => {
+                    errors.push(format!("Failed to read {}: {}", path, e));
+                    continue;
+                }
+            };
+            let mask = match Cpumask::from_cpu_list(&cpulist) {
+                Ok(mask) => mask,
+                Err(e) => {
+                    errors.push(format!("Failed to parse {}: {}", path, e));
+                    continue;
+                }
+            };
+            for cpu_id in mask.into_iter() {
+                match self.cpus.get(&cpu_id) {
+                    Some(cpu) if cpu.node_id != node.id => errors.push(format!(
+                        "CPU {} is listed in node {} but belongs to node {}",
+                        cpu_id, node.id, cpu.node_id
+                    )),
+                    Some(_) => {}
+                    None => errors.push(format!(
+                        "CPU {} is listed in node {} but is missing from the topology",
+                        cpu_id, node.id
+                    )),
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            bail!(
+                "Found {} topology inconsistencies:\n  {}",
+                errors.len(),
+                errors.join("\n  ")
+            );
+        }
+        Ok(())
+    }
 }
 
 
@@ -251,8 +986,26 @@ impl Topology {
  **********************************************/
 
 const CACHE_LEVEL: usize = 3;
+const SCHED_CAPACITY_SCALE: usize = 1024;
+const SMT_PATH: &str = "/sys/devices/system/cpu/smt";
+
+#[cfg(feature = "serde_json")]
+fn json_usize(val: &serde_json::Value, key: &str) -> Result<usize> {
+    match val[key].as_u64() {
+        Some(v) => Ok(v as usize),
+        None => bail!("Missing or invalid {} in snapshot: {}", key, val),
+    }
+}
+
+#[cfg(feature = "serde_json")]
+fn json_array<'a>(val: &'a serde_json::Value, key: &str) -> Result<&'a Vec<serde_json::Value>> {
+    match val[key].as_array() {
+        Some(v) => Ok(v),
+        None => bail!("Missing or invalid {} in snapshot: {}", key, val),
+    }
+}
 
-fn read_file_usize(path: &Path) -> Result<usize> {
+pub(crate) fn read_file_usize(path: &Path) -> Result<usize> {
     let val = match std::fs::read_to_string(&path) {
         Ok(val) => val,
         Err(_) => {
@@ -305,6 +1058,7 @@ fn create_numa_nodes(online_mask: &Cpumask) -> Result<Vec<Node>> {
             id: node_id,
             llcs: BTreeMap::new(),
             span: Cpumask::new()?,
+            distances: BTreeMap::new(),
         };
 
         let cpu_pattern = numa_path.join("cpu[0-9]*");
@@ -321,6 +1075,7 @@ fn create_numa_nodes(online_mask: &Cpumask) -> Result<Vec<Node>> {
             // Physical core ID
             let top_path = cpu_path.join("topology");
             let core_id = read_file_usize(&top_path.join("core_id"))?;
+            let package_id = read_file_usize(&top_path.join("physical_package_id"))?;
 
             // L3 cache ID
             let cache_path = cpu_path.join("cache");
@@ -333,6 +1088,12 @@ fn create_numa_nodes(online_mask: &Cpumask) -> Result<Vec<Node>> {
             let min_freq = read_file_usize(&freq_path.join("scaling_min_freq")).unwrap_or(0);
             let max_freq = read_file_usize(&freq_path.join("scaling_max_freq")).unwrap_or(0);
 
+            // Compute capacity. The file only exists on architectures with
+            // asymmetric CPU capacities. Elsewhere, all CPUs have the same
+            // maximum capacity.
+            let capacity = read_file_usize(&cpu_path.join("cpu_capacity"))
+                .unwrap_or(SCHED_CAPACITY_SCALE);
+
             // Hotplug information
             let online = online_mask.test_cpu(cpu_id);
 
@@ -363,6 +1124,10 @@ fn create_numa_nodes(online_mask: &Cpumask) -> Result<Vec<Node>> {
                     online: online,
                     min_freq: min_freq,
                     max_freq: max_freq,
+                    capacity,
+                    llc_id,
+                    node_id,
+                    package_id,
                 },
             );
 
@@ -380,3 +1145,111 @@ fn create_numa_nodes(online_mask: &Cpumask) -> Result<Vec<Node>> {
     }
     Ok(nodes)
 }
+
+fn read_numa_distances(nodes: &mut [Node]) -> Result<()> {
+    // Each node's distance file lists its distance to every node on the host
+    // in ascending order of node ID.
+    let mut node_ids: Vec<usize> = nodes.iter().map(|node| node.id).collect();
+    node_ids.sort();
+
+    for node in nodes.iter_mut() {
+        let path = format!("/sys/devices/system/node/node{}/distance", node.id);
+        let distances =
+            std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))?;
+        for (node_id, distance) in node_ids.iter().zip(distances.split_whitespace()) {
+            let distance = distance.parse::<usize>().with_context(|| {
+                format!("Failed to parse NUMA distance {} in {}", distance, path)
+            })?;
+            node.distances.insert(*node_id, distance);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_topology_snapshot_roundtrip() {
+        use super::Topology;
+
+        let cpu = |id: usize, node_id: usize, llc_id: usize, core_id: usize| {
+            serde_json::json!({
+                "id": id, "online": true, "node_id": node_id, "llc_id": llc_id,
+                "core_id": core_id, "package_id": node_id, "capacity": 1024,
+                "min_freq": 400000, "max_freq": 3000000,
+            })
+        };
+        let snapshot = serde_json::json!({
+            "nr_cpus": 128,
+            "nr_nodes": 2,
+            "nr_llcs": 2,
+            "nodes": [
+                { "id": 0, "distances": [[0, 10], [1, 20]] },
+                { "id": 1, "distances": [[0, 20], [1, 10]] },
+            ],
+            "cpus": [cpu(0, 0, 0, 0), cpu(64, 0, 0, 0), cpu(1, 1, 1, 1), cpu(65, 1, 1, 1)],
+        })
+        .to_string();
+
+        let topo = Topology::from_snapshot(&mut snapshot.as_bytes()).unwrap();
+        assert_eq!(topo.nr_cpus(), 128);
+        assert_eq!(topo.nodes().len(), 2);
+        assert_eq!(topo.cores().len(), 2);
+        assert_eq!(topo.numa_distance(0, 1), Some(20));
+        assert_eq!(topo.span().weight(), 4);
+
+        let mut buf = vec![];
+        topo.serialize(&mut buf).unwrap();
+        let restored = Topology::deserialize(&mut buf.as_slice()).unwrap();
+        assert_eq!(restored.cpus().len(), 4);
+        assert_eq!(restored.cpus()[&65].node_id(), 1);
+        assert_eq!(restored.cores()[&0].span().weight(), 2);
+    }
+
+    #[test]
+    fn test_topology_from_mock() {
+        use super::Topology;
+
+        let topo = Topology::from_mock(16, 2, 4);
+        assert_eq!(topo.nr_cpus(), 16);
+        assert_eq!(topo.cpus().len(), 16);
+        assert_eq!(topo.nodes().len(), 2);
+        assert_eq!(topo.nodes()[0].llcs().len(), 2);
+        assert_eq!(topo.cpus()[&5].llc_id(), 1);
+        assert_eq!(topo.cpus()[&5].node_id(), 1);
+        assert_eq!(topo.cpus_sharing_llc_by_id(2).unwrap().weight(), 4);
+        assert_eq!(topo.cpus_on_node(0).unwrap().weight(), 8);
+        assert_eq!(topo.numa_distance(0, 1), Some(20));
+    }
+
+    #[test]
+    fn test_topology_reachable_from() {
+        use super::Topology;
+
+        let topo = Topology::from_mock(8, 2, 4);
+        assert_eq!(topo.reachable_from(0, 0).weight(), 1);
+        assert_eq!(topo.reachable_from(0, 1).weight(), 2);
+        assert!(topo.reachable_from(0, 1).test_cpu(4));
+        assert_eq!(topo.reachable_from(0, 2).weight(), 4);
+        assert_eq!(topo.reachable_from(0, 3).weight(), 8);
+        assert_eq!(topo.reachable_from(0, 5).weight(), 8);
+        assert_eq!(topo.reachable_from(8, 3).weight(), 0);
+    }
+
+    #[test]
+    fn test_topology_export_graphviz() {
+        use super::Topology;
+
+        let topo = Topology::from_mock(8, 2, 4);
+        let mut buf = vec![];
+        topo.export_graphviz(&mut buf).unwrap();
+        let dot = String::from_utf8(buf).unwrap();
+        assert!(dot.starts_with("digraph topology {"));
+        assert!(dot.contains("subgraph cluster_llc3 {"));
+        assert!(dot.contains("pkg1 -> node1;"));
+        assert!(dot.contains("node1 -> llc3;"));
+        assert!(dot.contains("core7 -> cpu7;"));
+        assert_eq!(dot.matches(" -> ").count(), 2 + 4 + 8 + 8);
+    }
+}
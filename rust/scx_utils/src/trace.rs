@@ -0,0 +1,286 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Event Trace
+//!
+//! A crate that allows user space tools to consume the sched_ext ftrace
+//! events emitted by the kernel, e.g. to build latency histograms from real
+//! scheduler events.
+//!
+//! ScxEventTrace
+//! -------------
+//!
+//! ScxEventTrace::start() enables the sched_ext_enqueue, sched_ext_dispatch
+//! and sched_ext_dequeue events in tracefs, and spawns a thread which parses
+//! the events read from trace_pipe into ScxEvents and sends them on a
+//! channel. The events are disabled and the thread is stopped when the
+//! ScxEventTrace is dropped:
+//!
+//!```
+//!     let (_trace, events) = ScxEventTrace::start()?;
+//!     for event in events.iter() {
+//!         if event.kind == ScxEventKind::Dispatch {
+//!             histogram.record(event.latency_ns);
+//!         }
+//!     }
+//!```
+//!
+//! Events are expected to carry pid, cpu and latency_ns fields. Events
+//! without pid or cpu fields are attributed to the task and CPU which
+//! emitted them, and events without a latency_ns field have a latency of 0.
+//! Note that trace_pipe is a consuming read shared by all readers, so only a
+//! single ScxEventTrace should be active at a time.
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::io::Read;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+
+const TRACEFS_PATHS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+const POLL_TIMEOUT_MS: i32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScxEventKind {
+    Enqueue,
+    Dispatch,
+    Dequeue,
+}
+
+impl ScxEventKind {
+    const ALL: [ScxEventKind; 3] = [Self::Enqueue, Self::Dispatch, Self::Dequeue];
+
+    fn event_name(&self) -> &'static str {
+        match self {
+            Self::Enqueue => "sched_ext_enqueue",
+            Self::Dispatch => "sched_ext_dispatch",
+            Self::Dequeue => "sched_ext_dequeue",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScxEvent {
+    pub kind: ScxEventKind,
+    pub pid: u32,
+    pub cpu: usize,
+    pub latency_ns: u64,
+    /// The trace timestamp of the event
+    pub timestamp_ns: u64,
+}
+
+impl ScxEvent {
+    /// Parse a line read from trace_pipe, e.g.
+    ///
+    /// "  kworker/3:1-123  [003] d..2.  1234.567890: sched_ext_dispatch: pid=456 cpu=3 latency_ns=7890"
+    ///
+    /// Returns None if the line isn't a sched_ext event of a known kind.
+    pub fn parse(line: &str) -> Option<ScxEvent> {
+        let (kind, (header, fields)) = ScxEventKind::ALL.into_iter().find_map(|kind| {
+            line.split_once(&format!(" {}: ", kind.event_name()))
+                .map(|split| (kind, split))
+        })?;
+
+        // The header is "<comm>-<pid> [<cpu>] <flags> <secs>.<usecs>:", where
+        // comm may contain spaces and dashes.
+        let cpu_start = header.rfind('[')?;
+        let cpu_end = header.rfind(']')?;
+        let task_pid = header[..cpu_start]
+            .trim_end()
+            .rsplit('-')
+            .next()?
+            .parse()
+            .ok()?;
+        let task_cpu = header.get(cpu_start + 1..cpu_end)?.trim().parse().ok()?;
+        let timestamp_ns = match header[cpu_end + 1..]
+            .trim_end_matches(':')
+            .rsplit(' ')
+            .next()
+        {
+            Some(ts) => match ts.split_once('.') {
+                Some((secs, usecs)) => {
+                    secs.parse::<u64>().ok()? * 1_000_000_000 + usecs.parse::<u64>().ok()? * 1000
+                }
+                None => 0,
+            },
+            None => 0,
+        };
+
+        let mut event = ScxEvent {
+            kind,
+            pid: task_pid,
+            cpu: task_cpu,
+            latency_ns: 0,
+            timestamp_ns,
+        };
+        for (key, val) in fields.split_whitespace().filter_map(|f| f.split_once('=')) {
+            match key {
+                "pid" => event.pid = val.parse().ok()?,
+                "cpu" => event.cpu = val.parse().ok()?,
+                "latency_ns" => event.latency_ns = val.parse().ok()?,
+                _ => {}
+            }
+        }
+        Some(event)
+    }
+}
+
+#[derive(Debug)]
+pub struct ScxEventTrace {
+    enabled: Vec<PathBuf>,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+fn tracefs_path() -> Result<&'static Path> {
+    for path in TRACEFS_PATHS.iter().map(Path::new) {
+        if path.join("events/sched_ext").exists() {
+            return Ok(path);
+        }
+    }
+    bail!("No sched_ext events found in tracefs, is tracefs mounted?");
+}
+
+fn read_events(
+    mut pipe: std::fs::File,
+    stop: Arc<AtomicBool>,
+    tx: mpsc::Sender<ScxEvent>,
+) -> Result<()> {
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut pending = String::new();
+
+    while !stop.load(Ordering::Relaxed) {
+        let mut pollfd = libc::pollfd {
+            fd: pipe.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut pollfd, 1, POLL_TIMEOUT_MS) } <= 0 {
+            continue;
+        }
+
+        let len = match pipe.read(&mut buf) {
+            Ok(len) => len,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e).context("Failed to read trace_pipe"),
+        };
+        pending.push_str(&String::from_utf8_lossy(&buf[..len]));
+
+        // Keep the trailing partial line for the next read.
+        let complete = match pending.rfind('\n') {
+            Some(pos) => pos + 1,
+            None => continue,
+        };
+        for event in pending[..complete].lines().filter_map(ScxEvent::parse) {
+            if tx.send(event).is_err() {
+                // The receiver is gone, nobody is interested anymore.
+                return Ok(());
+            }
+        }
+        pending.drain(..complete);
+    }
+    Ok(())
+}
+
+impl ScxEventTrace {
+    /// Enable the sched_ext events and start reading them. Returns the
+    /// ScxEventTrace, which must be kept alive for as long as events should
+    /// be read, and the channel the events are sent on. Returns an error if
+    /// none of the events is supported by the kernel.
+    pub fn start() -> Result<(ScxEventTrace, mpsc::Receiver<ScxEvent>)> {
+        let tracefs = tracefs_path()?;
+
+        let mut trace = ScxEventTrace {
+            enabled: vec![],
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        };
+        for kind in ScxEventKind::ALL.iter() {
+            let path = tracefs
+                .join("events/sched_ext")
+                .join(kind.event_name())
+                .join("enable");
+            if !path.exists() {
+                continue;
+            }
+            std::fs::write(&path, "1").with_context(|| format!("Failed to enable {:?}", path))?;
+            trace.enabled.push(path);
+        }
+        if trace.enabled.is_empty() {
+            bail!("The kernel doesn't support any sched_ext enqueue, dispatch or dequeue events");
+        }
+
+        let pipe_path = tracefs.join("trace_pipe");
+        let pipe = std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&pipe_path)
+            .with_context(|| format!("Failed to open {:?}", pipe_path))?;
+
+        let (tx, rx) = mpsc::channel();
+        let stop = trace.stop.clone();
+        trace.thread = Some(
+            thread::Builder::new()
+                .name("scx_event_trace".to_string())
+                .spawn(move || {
+                    if let Err(e) = read_events(pipe, stop, tx) {
+                        log::warn!("Stopped reading sched_ext events: {:#}", e);
+                    }
+                })
+                .context("Failed to spawn event trace thread")?,
+        );
+
+        Ok((trace, rx))
+    }
+}
+
+impl Drop for ScxEventTrace {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        for path in self.enabled.iter() {
+            if let Err(e) = std::fs::write(path, "0") {
+                log::warn!("Failed to disable {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScxEvent;
+    use super::ScxEventKind;
+
+    #[test]
+    fn test_parse_event() {
+        let event = ScxEvent::parse(
+            "  kworker/3:1-bio-123  [003] d..2.  1234.567890: sched_ext_dispatch: pid=456 cpu=5 latency_ns=7890",
+        )
+        .unwrap();
+        assert_eq!(event.kind, ScxEventKind::Dispatch);
+        assert_eq!(event.pid, 456);
+        assert_eq!(event.cpu, 5);
+        assert_eq!(event.latency_ns, 7890);
+        assert_eq!(event.timestamp_ns, 1_234_567_890_000);
+
+        let event =
+            ScxEvent::parse("  stress-ng-77  [001] d..2.  10.000001: sched_ext_enqueue: flags=0x1")
+                .unwrap();
+        assert_eq!(event.kind, ScxEventKind::Enqueue);
+        assert_eq!((event.pid, event.cpu, event.latency_ns), (77, 1, 0));
+
+        assert!(ScxEvent::parse("  bash-1  [000] d..2.  1.0: sched_switch: prev_pid=1").is_none());
+    }
+}
@@ -0,0 +1,281 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Verifier
+//!
+//! A crate that allows schedulers to check whether their BPF object is
+//! compatible with the running kernel before trying to load it, so that an
+//! incompatibility can be reported in terms of sched_ext_ops fields rather
+//! than as a libbpf error.
+//!
+//! ScxVerifier
+//! -----------
+//!
+//! A ScxVerifier compares the sched_ext_ops struct the BPF object was built
+//! against with the sched_ext_ops struct in the BTF of the running kernel:
+//!
+//!```
+//!     let report = ScxVerifier::new().check_object(obj_bytes)?;
+//!     for field in report.extra_fields.iter() {
+//!         info!("sched_ext_ops.{} is not used by the scheduler", field);
+//!     }
+//!     report.ensure_compatible()?;
+//!
+//!     let skel = skel_builder.open()?.load()?;
+//!```
+//!
+//! Fields which the scheduler sets in its struct_ops map, i.e. which have an
+//! initializer or a relocation to a BPF program, but which the kernel lacks
+//! are reported in CompatReport::missing_fields. Fields of the vmlinux.h the
+//! BPF object was built against which the scheduler leaves unset are not,
+//! as libbpf skips them when loading. Fields which only the kernel has are
+//! reported in CompatReport::extra_fields, and are harmless as they are left
+//! zeroed by libbpf.
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use libbpf_rs::btf::types::DataSec;
+use libbpf_rs::btf::types::MemberAttr;
+use libbpf_rs::btf::types::Struct;
+use libbpf_rs::btf::types::Var;
+use libbpf_rs::btf::HasSize;
+use libbpf_rs::btf::ReferencesType;
+use libbpf_rs::Btf;
+use std::path::PathBuf;
+
+const OPS_STRUCT: &str = "sched_ext_ops";
+const STRUCT_OPS_SECS: [&str; 2] = [".struct_ops", ".struct_ops.link"];
+
+const SHT_REL: u64 = 9;
+
+#[derive(Debug, Clone, Default)]
+pub struct CompatReport {
+    /// sched_ext_ops fields set by the BPF object the kernel doesn't have
+    pub missing_fields: Vec<String>,
+    /// sched_ext_ops fields of the kernel the BPF object doesn't have
+    pub extra_fields: Vec<String>,
+}
+
+impl CompatReport {
+    /// Can the BPF object be loaded on the running kernel?
+    pub fn is_compatible(&self) -> bool {
+        self.missing_fields.is_empty()
+    }
+
+    /// Return an error listing the missing fields if the BPF object can't be
+    /// loaded on the running kernel.
+    pub fn ensure_compatible(&self) -> Result<()> {
+        if !self.is_compatible() {
+            bail!(
+                concat!(
+                    "The BPF scheduler requires sched_ext_ops fields {:?} which",
+                    " the running kernel doesn't support, upgrade the kernel or",
+                    " rebuild the scheduler against the running kernel's vmlinux.h"
+                ),
+                self.missing_fields
+            );
+        }
+        Ok(())
+    }
+}
+
+fn ops_fields(btf: &Btf, what: &str) -> Result<Vec<String>> {
+    let ops: Struct = btf
+        .type_by_name(OPS_STRUCT)
+        .ok_or_else(|| anyhow!("No struct {} found in the BTF of {}", OPS_STRUCT, what))?;
+
+    Ok(ops
+        .iter()
+        .filter_map(|member| member.name)
+        .map(|name| name.to_string_lossy().to_string())
+        .collect())
+}
+
+// A section of an ELF object file, with the offsets of the relocations
+// applying to it.
+struct ElfSection<'a> {
+    name: String,
+    data: &'a [u8],
+    relocs: Vec<usize>,
+}
+
+// Parse the sections of the ELF64 object file @elf.
+fn elf_sections(elf: &[u8]) -> Result<Vec<ElfSection<'_>>> {
+    if elf.get(..5) != Some(b"\x7fELF\x02") {
+        bail!("BPF object is not an ELF64 file");
+    }
+    let little_endian = elf[5] == 1;
+    let read = |off: usize, len: usize| -> Result<u64> {
+        let bytes = elf
+            .get(off..off + len)
+            .ok_or_else(|| anyhow!("BPF object is truncated"))?;
+        let mut val = 0u64;
+        for (i, byte) in bytes.iter().enumerate() {
+            let shift = if little_endian { i } else { len - 1 - i } * 8;
+            val |= (*byte as u64) << shift;
+        }
+        Ok(val)
+    };
+
+    // (name offset, type, data offset, size, info) of each section header
+    let shoff = read(0x28, 8)? as usize;
+    let shentsize = read(0x3a, 2)? as usize;
+    let mut headers = vec![];
+    for i in 0..read(0x3c, 2)? as usize {
+        let hdr = shoff + i * shentsize;
+        headers.push((
+            read(hdr, 4)? as usize,
+            read(hdr + 0x4, 4)?,
+            read(hdr + 0x18, 8)? as usize,
+            read(hdr + 0x20, 8)? as usize,
+            read(hdr + 0x2c, 4)? as usize,
+        ));
+    }
+    let data = |off: usize, size: usize| {
+        elf.get(off..off + size)
+            .ok_or_else(|| anyhow!("BPF object is truncated"))
+    };
+    let (_, _, strtab_off, strtab_size, _) = *headers
+        .get(read(0x3e, 2)? as usize)
+        .ok_or_else(|| anyhow!("BPF object has no section name table"))?;
+    let strtab = data(strtab_off, strtab_size)?;
+
+    let mut sections = vec![];
+    for &(name_off, _, off, size, _) in headers.iter() {
+        let name = strtab.get(name_off..).unwrap_or_default();
+        let name = name.split(|c| *c == 0).next().unwrap_or_default();
+        sections.push(ElfSection {
+            name: String::from_utf8_lossy(name).to_string(),
+            data: data(off, size).unwrap_or_default(),
+            relocs: vec![],
+        });
+    }
+    for &(_, sh_type, off, size, info) in headers.iter() {
+        if sh_type != SHT_REL || info >= sections.len() {
+            continue;
+        }
+        // Elf64_Rel is 16 bytes, starting with r_offset.
+        for rel in (off..off + size).step_by(16) {
+            let r_offset = read(rel, 8)? as usize;
+            sections[info].relocs.push(r_offset);
+        }
+    }
+    Ok(sections)
+}
+
+// Get the sched_ext_ops fields set by the struct_ops maps of the BPF object
+// with the BTF @btf and file contents @obj_bytes, i.e. those which are
+// initialized or relocated, e.g. to a BPF program.
+fn set_ops_fields(btf: &Btf, obj_bytes: &[u8]) -> Result<Vec<String>> {
+    let ops: Struct = btf.type_by_name(OPS_STRUCT).ok_or_else(|| {
+        anyhow!(
+            "No struct {} found in the BTF of the BPF object",
+            OPS_STRUCT
+        )
+    })?;
+    // (name, byte offset) of each member
+    let members: Vec<(String, usize)> = ops
+        .iter()
+        .filter_map(|member| {
+            let offset = match member.attr {
+                MemberAttr::Normal { offset } => offset,
+                MemberAttr::BitField { offset, .. } => offset,
+            };
+            let name = member.name?.to_string_lossy().to_string();
+            Some((name, offset as usize / 8))
+        })
+        .collect();
+
+    let mut fields = vec![];
+    for sec in elf_sections(obj_bytes)? {
+        if !STRUCT_OPS_SECS.contains(&sec.name.as_str()) {
+            continue;
+        }
+        let datasec: DataSec = match btf.type_by_name(&sec.name) {
+            Some(datasec) => datasec,
+            None => continue,
+        };
+        for var in datasec.iter() {
+            let is_ops = btf
+                .type_by_id::<Var>(var.ty)
+                .map(|v| v.referenced_type().skip_mods_and_typedefs().type_id() == ops.type_id())
+                .unwrap_or(false);
+            if !is_ops {
+                continue;
+            }
+
+            for (i, (name, start)) in members.iter().enumerate() {
+                let end = members.get(i + 1).map_or(ops.size(), |(_, off)| *off);
+                let range = var.offset as usize + start..var.offset as usize + end;
+                let initialized = sec
+                    .data
+                    .get(range.clone())
+                    .is_some_and(|bytes| bytes.iter().any(|b| *b != 0));
+                let relocated = sec.relocs.iter().any(|off| range.contains(off));
+                if (initialized || relocated) && !fields.contains(name) {
+                    fields.push(name.clone());
+                }
+            }
+        }
+    }
+    Ok(fields)
+}
+
+#[derive(Debug, Default)]
+pub struct ScxVerifier {
+    kernel_btf: Option<PathBuf>,
+}
+
+impl ScxVerifier {
+    /// Create a ScxVerifier which checks against the BTF of the running
+    /// kernel, /sys/kernel/btf/vmlinux.
+    pub fn new() -> ScxVerifier {
+        Self::default()
+    }
+
+    /// Create a ScxVerifier which checks against the kernel BTF at @path,
+    /// e.g. to check compatibility with a kernel other than the running one.
+    pub fn with_kernel_btf(path: PathBuf) -> ScxVerifier {
+        ScxVerifier {
+            kernel_btf: Some(path),
+        }
+    }
+
+    /// Compare the sched_ext_ops struct in the BTF of the BPF object file
+    /// contents @obj_bytes against the one of the kernel. Only the fields the
+    /// struct_ops maps of the BPF object set can be missing. Returns an error if
+    /// either BTF can't be read or lacks sched_ext_ops altogether, e.g. if
+    /// the kernel doesn't support sched_ext.
+    pub fn check_object(&self, obj_bytes: &[u8]) -> Result<CompatReport> {
+        let kernel_btf = match &self.kernel_btf {
+            Some(path) => Btf::from_path(path)
+                .with_context(|| format!("Failed to read kernel BTF from {:?}", path))?,
+            None => Btf::from_vmlinux().context("Failed to read kernel BTF")?,
+        };
+        let kernel_fields = ops_fields(&kernel_btf, "the kernel")
+            .context("Is the kernel built with CONFIG_SCHED_CLASS_EXT?")?;
+
+        let obj_btf = Btf::from_raw("scx_obj", obj_bytes)
+            .context("Failed to open BPF object")?
+            .ok_or_else(|| anyhow!("BPF object has no BTF"))?;
+        let obj_fields = ops_fields(&obj_btf, "the BPF object")?;
+        let set_fields = set_ops_fields(&obj_btf, obj_bytes)?;
+
+        Ok(CompatReport {
+            missing_fields: set_fields
+                .iter()
+                .filter(|f| !kernel_fields.contains(f))
+                .cloned()
+                .collect(),
+            extra_fields: kernel_fields
+                .iter()
+                .filter(|f| !obj_fields.contains(f))
+                .cloned()
+                .collect(),
+        })
+    }
+}
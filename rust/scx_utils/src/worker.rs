@@ -0,0 +1,117 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # SCX Worker Threads
+//!
+//! Helpers for the background threads the crate spawns on behalf of
+//! schedulers, e.g. to collect stats or to watch over the BPF scheduler.
+//!
+//! A PeriodicThread calls a function every interval until it is dropped.
+//! Threads which must keep running even if the BPF scheduler misbehaves can
+//! be made SCHED_FIFO, so that they aren't scheduled by sched_ext. This
+//! requires CAP_SYS_NICE. Without it, the thread keeps running under the
+//! default policy, and a warning is logged.
+
+use anyhow::bail;
+use anyhow::Result;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Make the calling thread SCHED_FIFO at the highest priority.
+pub(crate) fn set_fifo() -> Result<()> {
+    let param = libc::sched_param {
+        sched_priority: unsafe { libc::sched_get_priority_max(libc::SCHED_FIFO) },
+    };
+    if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } < 0 {
+        bail!(
+            "Failed to set SCHED_FIFO ({})",
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+/// Stops and joins the thread when dropped.
+#[derive(Debug)]
+pub(crate) struct PeriodicThread {
+    stop_tx: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl PeriodicThread {
+    /// Spawn a thread named @name which calls @f every @interval, starting
+    /// one @interval from now. If @fifo, the thread tries to make itself
+    /// SCHED_FIFO first.
+    pub(crate) fn spawn<F>(name: String, interval: Duration, fifo: bool, mut f: F) -> Result<Self>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let thread = thread::Builder::new().name(name.clone()).spawn(move || {
+            if fifo {
+                if let Err(e) = set_fifo() {
+                    log::warn!("Thread {} isn't SCHED_FIFO: {:#}", name, e);
+                }
+            }
+            // The stop channel only ever disconnects, which ends the loop.
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+                f();
+            }
+        })?;
+
+        Ok(PeriodicThread {
+            stop_tx: Some(stop_tx),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for PeriodicThread {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes up the thread.
+        self.stop_tx.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PeriodicThread;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    #[test]
+    fn test_periodic_thread_drop() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let thread_calls = calls.clone();
+        let thread = PeriodicThread::spawn(
+            "scx_test".to_string(),
+            Duration::from_millis(1),
+            false,
+            move || {
+                thread_calls.fetch_add(1, Ordering::Relaxed);
+            },
+        )
+        .unwrap();
+
+        let start = Instant::now();
+        while calls.load(Ordering::Relaxed) < 2 {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // Dropping must stop the thread, so no more calls happen afterwards.
+        drop(thread);
+        let nr_calls = calls.load(Ordering::Relaxed);
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(calls.load(Ordering::Relaxed), nr_calls);
+    }
+}